@@ -0,0 +1,61 @@
+//! Times full renders of synthetic graphs whose edges have to travel along
+//! long corridors between node columns, and the routing phase alone for
+//! those and for the random graphs of `gen-example random`.
+//!
+//! Run with `cargo bench --bench routing`.
+
+use std::time::{Duration, Instant};
+
+use mermaid_ascii::generate::{generate, Topology};
+use mermaid_ascii::parser::mermaid_file_to_map;
+use mermaid_ascii::render::{render_properties, Graph, RenderOptions};
+
+/// Builds an LR graph with `edges` edges between a column of sources and a
+/// column of sinks, wired so that most edges run a long way up or down the
+/// corridor between the two columns.
+fn corridor_graph(edges: usize) -> String {
+    let mut source = String::from("graph LR\n");
+    let rows = edges / 3;
+    for edge in 0..edges {
+        let from = edge % rows;
+        let to = (from * 7 + edge / rows * 11) % rows;
+        source.push_str(&format!("S{} --> T{}\n", from, to));
+    }
+    source
+}
+
+fn time_render(source: &str, iterations: u32) -> Duration {
+    let properties = mermaid_file_to_map(source, "cli").expect("benchmark graph parses");
+    let options = RenderOptions::default();
+    let start = Instant::now();
+    for _ in 0..iterations {
+        render_properties(&properties, &options).expect("benchmark graph renders");
+    }
+    start.elapsed() / iterations
+}
+
+/// The routing phase of a layout of `source`, averaged over `iterations`.
+fn time_routing(source: &str, iterations: u32) -> Duration {
+    let properties = mermaid_file_to_map(source, "cli").expect("benchmark graph parses");
+    let mut total = Duration::ZERO;
+    for _ in 0..iterations {
+        let mut graph = Graph::new(&properties, RenderOptions::default());
+        graph.layout().expect("benchmark graph lays out");
+        let phases = graph.stats().phases;
+        total += phases.iter().find(|(name, _)| *name == "routing").map_or(Duration::ZERO, |(_, time)| *time);
+    }
+    total / iterations
+}
+
+fn main() {
+    for (edges, iterations) in [(30, 20), (100, 5), (300, 2)] {
+        let source = corridor_graph(edges);
+        let per_render = time_render(&source, iterations);
+        let routing = time_routing(&source, iterations);
+        println!("{:>4} edges: {:>10.2?} per render, {:>10.2?} routing", edges, per_render, routing);
+    }
+    for (nodes, iterations) in [(50, 10), (150, 5), (400, 2)] {
+        let routing = time_routing(&generate(Topology::Random, nodes, 1), iterations);
+        println!("{:>4} random nodes: {:>10.2?} routing", nodes, routing);
+    }
+}
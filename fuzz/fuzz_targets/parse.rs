@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mermaid_ascii::parser::mermaid_file_to_map;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = std::str::from_utf8(data) {
+        let _ = mermaid_file_to_map(input, "cli");
+    }
+});
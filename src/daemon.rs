@@ -0,0 +1,113 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use mermaid_ascii::error::ErrorCategory;
+use serde_json::{json, Value};
+
+/// Longest request line read, so one client cannot exhaust memory.
+const MAX_LINE_BYTES: usize = 1 << 20;
+
+/// Serves renderings over a Unix socket at `path` until the process is
+/// stopped, one thread per connection.
+///
+/// Each request is one line of JSON, `{"source": "graph LR\nA --> B"}` with
+/// an optional `"ascii": true|false` override, and gets one line back:
+/// `{"ok": true, "output": …}`, or `{"ok": false, "error": …, "exit_code":
+/// …}` with the exit code the same input would give on the command line.
+/// A socket left at `path` by an earlier run is replaced.
+#[cfg(unix)]
+pub fn run<F>(path: &Path, render: F) -> Result<()>
+where
+    F: Fn(&str, Option<bool>) -> Result<String> + Sync,
+{
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+
+    use anyhow::Context;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path).with_context(|| format!("removing stale socket {}", path.display()))?;
+    }
+    let listener = UnixListener::bind(path).with_context(|| format!("binding {}", path.display()))?;
+    eprintln!("listening on {}", path.display());
+    std::thread::scope(|scope| {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let render = &render;
+                    scope.spawn(move || {
+                        let handled = stream
+                            .try_clone()
+                            .map_err(anyhow::Error::from)
+                            .and_then(|reader| handle(BufReader::new(reader), stream, render));
+                        if let Err(err) = handled {
+                            log::warn!("connection failed: {:#}", err);
+                        }
+                    });
+                }
+                Err(err) => log::warn!("accept failed: {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn run<F>(_path: &Path, _render: F) -> Result<()>
+where
+    F: Fn(&str, Option<bool>) -> Result<String> + Sync,
+{
+    Err(anyhow!("the daemon needs Unix domain sockets, which this platform does not have"))
+}
+
+/// Answers requests from `reader` on `writer` until the client hangs up.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn handle<F>(mut reader: impl BufRead, mut writer: impl Write, render: &F) -> Result<()>
+where
+    F: Fn(&str, Option<bool>) -> Result<String>,
+{
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = (&mut reader).take(MAX_LINE_BYTES as u64 + 1).read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok(());
+        }
+        let response = if line.len() > MAX_LINE_BYTES {
+            failure(&anyhow!("request longer than {} bytes", MAX_LINE_BYTES))
+        } else if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        } else {
+            match parse_request(&line).and_then(|(source, ascii)| render(&source, ascii)) {
+                Ok(output) => json!({"ok": true, "output": output}),
+                Err(err) => failure(&err),
+            }
+        };
+        writeln!(writer, "{}", response)?;
+        writer.flush()?;
+        if line.len() > MAX_LINE_BYTES {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg_attr(not(unix), allow(dead_code))]
+fn parse_request(line: &[u8]) -> Result<(String, Option<bool>)> {
+    let request: Value = serde_json::from_slice(line).map_err(|err| anyhow!("request is not JSON: {}", err))?;
+    let source = request["source"]
+        .as_str()
+        .ok_or_else(|| anyhow!("request has no `source` string"))?;
+    let ascii = match &request["ascii"] {
+        Value::Null => None,
+        Value::Bool(ascii) => Some(*ascii),
+        _ => return Err(anyhow!("`ascii` must be true or false")),
+    };
+    Ok((source.to_string(), ascii))
+}
+
+#[cfg_attr(not(unix), allow(dead_code))]
+fn failure(err: &anyhow::Error) -> Value {
+    let code = ErrorCategory::of(err).map_or(1, ErrorCategory::exit_code);
+    json!({"ok": false, "error": format!("{:#}", err), "exit_code": code})
+}
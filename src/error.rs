@@ -0,0 +1,65 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+/// What kind of failure an error from this crate is, so wrappers can map it
+/// (the CLI turns it into its exit code).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// The input could not be decoded or is not a diagram we understand.
+    Parse,
+    /// The diagram parsed but could not be laid out or drawn.
+    Layout,
+    /// Reading input or writing output failed.
+    Io,
+}
+
+impl ErrorCategory {
+    /// Finds the category of `err`, looking through any context added on
+    /// top. Untagged errors caused by an `std::io::Error` count as [`Io`].
+    ///
+    /// [`Io`]: ErrorCategory::Io
+    pub fn of(err: &anyhow::Error) -> Option<ErrorCategory> {
+        if let Some(tagged) = err.downcast_ref::<Categorized>() {
+            return Some(tagged.category);
+        }
+        if err.chain().any(|cause| cause.is::<std::io::Error>()) {
+            return Some(ErrorCategory::Io);
+        }
+        None
+    }
+
+    /// Process exit code for the CLI: 2, 3 and 4 for parse, layout and I/O.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorCategory::Parse => 2,
+            ErrorCategory::Layout => 3,
+            ErrorCategory::Io => 4,
+        }
+    }
+}
+
+/// Tags an error with its category without changing how it displays.
+#[derive(Debug)]
+struct Categorized {
+    category: ErrorCategory,
+    error: anyhow::Error,
+}
+
+impl fmt::Display for Categorized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl StdError for Categorized {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.error.source()
+    }
+}
+
+pub(crate) fn tagged(category: ErrorCategory, error: anyhow::Error) -> anyhow::Error {
+    if error.downcast_ref::<Categorized>().is_some() {
+        return error;
+    }
+    anyhow::Error::new(Categorized { category, error })
+}
@@ -0,0 +1,139 @@
+use crate::parser::{inline_label, structural_keyword, Keyword, DOTTED_LABEL_REGEX, INLINE_LABEL_REGEX, LINK_REGEX};
+
+/// Re-indents a flowchart for `mermaid-ascii fmt`: the header and any front
+/// matter stay at the left margin, statements are indented four spaces and
+/// each `subgraph` … `end` body one level more. Links and `&` get single
+/// spaces around them; text inside brackets, quotes and `|labels|` is left
+/// as written, as are comments. Runs of blank lines collapse to one.
+/// Formatting the output again changes nothing.
+pub fn format_mermaid(input: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut source = input.lines().peekable();
+    while source.peek().is_some_and(|line| line.trim().is_empty()) {
+        source.next();
+    }
+    if source.peek().is_some_and(|line| line.trim() == "---") {
+        lines.push("---".to_string());
+        source.next();
+        for line in source.by_ref() {
+            lines.push(line.trim_end().to_string());
+            if line.trim() == "---" {
+                break;
+            }
+        }
+    }
+
+    let mut header_seen = false;
+    let mut depth = 0usize;
+    for line in source {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            if lines.last().is_some_and(|last| !last.is_empty()) {
+                lines.push(String::new());
+            }
+            continue;
+        }
+        let keyword = structural_keyword(trimmed);
+        if keyword == Some(Keyword::End) {
+            depth = depth.saturating_sub(1);
+        }
+        let is_header = !header_seen && (trimmed.starts_with("graph") || trimmed.starts_with("flowchart"));
+        if is_header || trimmed == "---" {
+            header_seen = is_header;
+            lines.push(trimmed.split_whitespace().collect::<Vec<_>>().join(" "));
+            continue;
+        }
+        let text = if trimmed.starts_with("%%") {
+            trimmed.to_string()
+        } else {
+            space_links(trimmed)
+        };
+        lines.push(format!("{}{}", "    ".repeat(depth + 1), text));
+        if matches!(keyword, Some(Keyword::Subgraph(_))) {
+            depth += 1;
+        }
+    }
+    while lines.last().is_some_and(|last| last.is_empty()) {
+        lines.pop();
+    }
+    let mut output = lines.join("\n");
+    output.push('\n');
+    output
+}
+
+/// Puts one space either side of each link and `&` outside brackets and
+/// quotes, keeping a link's `|label|` and `{weight=N}` attached to it, and
+/// around the dashes of a label written inside a link (`A -- yes --> B`).
+/// Links are matched as the parser reads them, however many dashes.
+fn space_links(statement: &str) -> String {
+    let mut output = String::new();
+    // Where the text since the last link starts in `output`.
+    let mut segment = 0;
+    let mut depth = 0usize;
+    let mut quoted = false;
+    let mut rest = statement;
+    while let Some(c) = rest.chars().next() {
+        if depth == 0 && !quoted {
+            if rest.starts_with("%%") {
+                output.push_str(rest);
+                break;
+            }
+            // The `o` of `A o--o B` only starts a link after a space.
+            let link = LINK_REGEX
+                .find(rest)
+                .filter(|_| !rest.starts_with(['o', 'x']) || output.ends_with(char::is_whitespace));
+            if rest.starts_with(".->") {
+                if let Some(spaced) = spaced_label(&DOTTED_LABEL_REGEX, &output[segment..], "-.") {
+                    output.truncate(segment);
+                    output.push_str(&spaced);
+                    output.push_str(" .-> ");
+                    segment = output.len();
+                    rest = rest[3..].trim_start();
+                    continue;
+                }
+            }
+            if let Some(link) = link {
+                let end = link.end();
+                let plain = !rest[..end].contains(['.', '~', '|']) && !rest.starts_with('<');
+                if let Some(spaced) = spaced_label(&INLINE_LABEL_REGEX, &output[segment..], "--").filter(|_| plain) {
+                    output.truncate(segment);
+                    output.push_str(&spaced);
+                }
+                trim_spaces_end(&mut output);
+                output.push(' ');
+                output.push_str(&rest[..end]);
+                output.push(' ');
+                segment = output.len();
+                rest = rest[end..].trim_start();
+                continue;
+            }
+            if c == '&' && output.ends_with(char::is_whitespace) && rest[1..].starts_with(char::is_whitespace) {
+                trim_spaces_end(&mut output);
+                output.push_str(" & ");
+                rest = rest[1..].trim_start();
+                continue;
+            }
+        }
+        match c {
+            '"' => quoted = !quoted,
+            '[' | '(' | '{' if !quoted => depth += 1,
+            ']' | ')' | '}' if !quoted => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        output.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    output.trim_end().to_string()
+}
+
+/// `text` as `node <dashes> label` when it ends in a label written inside a
+/// link, as the parser reads it.
+fn spaced_label(regex: &regex::Regex, text: &str, dashes: &str) -> Option<String> {
+    let (node, label) = inline_label(regex, text.trim_end())?;
+    Some(format!("{} {} {}", node, dashes, label))
+}
+
+fn trim_spaces_end(text: &mut String) {
+    let len = text.trim_end().len();
+    text.truncate(len);
+}
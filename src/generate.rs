@@ -0,0 +1,72 @@
+use std::fmt::Write;
+
+/// Shapes of synthetic graph that [`generate`] can build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    /// `N0 --> N1 --> … --> Nn`.
+    Chain,
+    /// A binary tree rooted at `N0`.
+    Tree,
+    /// A square grid with edges to the right and downwards.
+    Mesh,
+    /// A random spanning tree plus about `nodes / 2` extra edges.
+    Random,
+}
+
+/// Builds a Mermaid flowchart with `nodes` nodes named `N0`, `N1`, …. The
+/// same arguments always produce the same text; `seed` only matters for
+/// [`Topology::Random`].
+pub fn generate(topology: Topology, nodes: usize, seed: u64) -> String {
+    let mut edges = Vec::new();
+    match topology {
+        Topology::Chain => edges.extend((1..nodes).map(|to| (to - 1, to))),
+        Topology::Tree => edges.extend((1..nodes).map(|to| ((to - 1) / 2, to))),
+        Topology::Mesh => {
+            let side = (1..).find(|side| side * side >= nodes).unwrap_or(1);
+            for node in 0..nodes {
+                if (node + 1) % side != 0 && node + 1 < nodes {
+                    edges.push((node, node + 1));
+                }
+                if node + side < nodes {
+                    edges.push((node, node + side));
+                }
+            }
+        }
+        Topology::Random => {
+            let mut rng = XorShift::new(seed);
+            edges.extend((1..nodes).map(|to| (rng.below(to), to)));
+            for _ in 0..nodes / 2 {
+                let (from, to) = (rng.below(nodes), rng.below(nodes));
+                if from != to && !edges.contains(&(from, to)) {
+                    edges.push((from, to));
+                }
+            }
+        }
+    }
+
+    let mut source = String::from("graph LR\n");
+    if nodes == 1 {
+        source.push_str("N0\n");
+    }
+    for (from, to) in edges {
+        let _ = writeln!(source, "N{} --> N{}", from, to);
+    }
+    source
+}
+
+/// Small deterministic generator so fixtures don't change between builds or
+/// platforms.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
@@ -0,0 +1,222 @@
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::error::{tagged, ErrorCategory};
+use crate::parser::{GraphProperties, LineStyle, NodeShape, TextEdge, TextNode, Terminator};
+
+/// The input formats [`detect_format`] tells apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DetectedFormat {
+    Mermaid,
+    Json,
+    Csv,
+}
+
+static DOT_HEADER_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"^(?:strict\s+)?(?:digraph\b|graph(?:\s+(?:"[^"]*"|\w+))?\s*\{)"#).unwrap());
+
+/// Guesses the format of `input` from its first line that is not blank or a
+/// `%%`, `//` or `#` comment: `{` or `[` starts JSON, a line with
+/// `delimiter` in it is a CSV row, and anything else is Mermaid. Graphviz
+/// DOT (`digraph …`, `graph G {`) is recognised only to fail with a clear
+/// parse error, as it cannot be read.
+pub fn detect_format(input: &str, delimiter: char) -> Result<DetectedFormat> {
+    let comment = |line: &str| ["%%", "//", "#"].iter().any(|start| line.starts_with(start));
+    let Some(first) = input.lines().map(str::trim).find(|line| !line.is_empty() && !comment(line)) else {
+        return Ok(DetectedFormat::Mermaid);
+    };
+    if DOT_HEADER_REGEX.is_match(first) {
+        return Err(tagged(
+            ErrorCategory::Parse,
+            anyhow!("input looks like Graphviz DOT, which cannot be read; convert it to Mermaid, JSON or CSV"),
+        ));
+    }
+    Ok(if first.starts_with(['{', '[']) {
+        DetectedFormat::Json
+    } else if first.contains(delimiter) {
+        DetectedFormat::Csv
+    } else {
+        DetectedFormat::Mermaid
+    })
+}
+
+/// Reads a graph from JSON Graph Format (`{"graph": {"nodes": …, "edges":
+/// …}}`, nodes as an array or keyed by id) or vis.js style
+/// (`{"nodes": [{"id", "label"}], "edges": [{"from", "to"}]}`). Edges are
+/// directed unless the graph or the edge says `"directed": false`, or a
+/// vis.js edge has `"arrows"` without `to`.
+pub fn json_to_map(input: &str, style_type: &str) -> Result<GraphProperties> {
+    parse_json(input, style_type).map_err(|err| tagged(ErrorCategory::Parse, err))
+}
+
+fn parse_json(input: &str, style_type: &str) -> Result<GraphProperties> {
+    let root: Value = serde_json::from_str(input).context("parsing JSON graph")?;
+    let graph = match (&root["graph"], &root["graphs"]) {
+        (Value::Object(_), _) => &root["graph"],
+        (_, Value::Array(graphs)) if !graphs.is_empty() => &graphs[0],
+        _ => &root,
+    };
+    if graph.get("nodes").is_none() && graph.get("edges").is_none() {
+        return Err(anyhow!("expected a JSON graph with `nodes` or `edges`"));
+    }
+
+    let mut properties = GraphProperties::new(style_type);
+    properties.title = graph["label"].as_str().map(str::to_string);
+    match &graph["nodes"] {
+        Value::Array(nodes) => {
+            for (idx, node) in nodes.iter().enumerate() {
+                let id = id_of(&node["id"]).ok_or_else(|| anyhow!("node {} has no id", idx))?;
+                properties.add_node(&text_node(id, &node["label"]));
+            }
+        }
+        Value::Object(nodes) => {
+            for (id, node) in nodes {
+                properties.add_node(&text_node(id.clone(), &node["label"]));
+            }
+        }
+        Value::Null => {}
+        _ => return Err(anyhow!("`nodes` must be an array or an object")),
+    }
+
+    let directed = graph["directed"].as_bool().unwrap_or(true);
+    let edges = match &graph["edges"] {
+        Value::Array(edges) => edges.as_slice(),
+        Value::Null => &[],
+        _ => return Err(anyhow!("`edges` must be an array")),
+    };
+    for (idx, edge) in edges.iter().enumerate() {
+        let end = |keys: [&str; 2]| keys.iter().find_map(|key| id_of(&edge[*key]));
+        let from = end(["source", "from"]).ok_or_else(|| anyhow!("edge {} has no source", idx))?;
+        let to = end(["target", "to"]).ok_or_else(|| anyhow!("edge {} has no target", idx))?;
+        let arrow = match (&edge["directed"], &edge["arrows"]) {
+            (Value::Bool(directed), _) => *directed,
+            (_, Value::String(arrows)) => arrows.contains("to"),
+            _ => directed,
+        };
+        properties.add_edge(TextEdge {
+            parent: text_node(from, &Value::Null),
+            child: text_node(to, &Value::Null),
+            label: edge["label"].as_str().unwrap_or("").to_string(),
+            line_style: LineStyle::Solid,
+            terminator: if arrow { Terminator::Arrow } else { Terminator::None },
+            start_terminator: Terminator::None,
+            weight: edge["weight"].as_u64().map(|weight| weight as u32),
+            order: 0,
+            min_length: 1,
+            parent_port: None,
+            child_port: None,
+        });
+    }
+    Ok(properties)
+}
+
+/// Ids may be strings or numbers.
+fn id_of(value: &Value) -> Option<String> {
+    match value {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+fn text_node(name: String, label: &Value) -> TextNode {
+    TextNode {
+        name,
+        label: label.as_str().map(str::to_string),
+        style_class: None,
+        shape: NodeShape::Rectangle,
+    }
+}
+
+/// Reads an edge list with `from,to,label` columns; `to` and `label` may be
+/// left out. A first row naming the columns (`from`/`source`, `to`/`target`,
+/// `label`) is used to find them, in any order. Fields may be quoted with
+/// `"`, doubling quotes inside.
+pub fn csv_to_map(input: &str, delimiter: char, style_type: &str) -> Result<GraphProperties> {
+    parse_csv(input, delimiter, style_type).map_err(|err| tagged(ErrorCategory::Parse, err))
+}
+
+fn parse_csv(input: &str, delimiter: char, style_type: &str) -> Result<GraphProperties> {
+    let mut rows = Vec::new();
+    for (idx, line) in input.lines().enumerate() {
+        if !line.trim().is_empty() {
+            let fields = split_csv_line(line, delimiter).with_context(|| format!("line {}", idx + 1))?;
+            rows.push((idx + 1, fields));
+        }
+    }
+
+    let mut columns = [Some(0), Some(1), Some(2)];
+    if let Some((_, header)) = rows.first() {
+        let position = |names: &[&str]| {
+            header
+                .iter()
+                .position(|field| names.contains(&field.trim().to_ascii_lowercase().as_str()))
+        };
+        let named = [position(&["from", "source"]), position(&["to", "target"]), position(&["label"])];
+        if named.iter().any(Option::is_some) {
+            if named[0].is_none() {
+                return Err(anyhow!("CSV header has no `from` column"));
+            }
+            columns = named;
+            rows.remove(0);
+        }
+    }
+
+    let mut properties = GraphProperties::new(style_type);
+    for (line, fields) in rows {
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|idx| fields.get(idx))
+                .map(|field| field.trim())
+                .filter(|field| !field.is_empty())
+        };
+        let from = field(columns[0]).ok_or_else(|| anyhow!("line {} has no `from` node", line))?;
+        let from = text_node(from.to_string(), &Value::Null);
+        match field(columns[1]) {
+            Some(to) => properties.add_edge(TextEdge {
+                parent: from,
+                child: text_node(to.to_string(), &Value::Null),
+                label: field(columns[2]).unwrap_or("").to_string(),
+                line_style: LineStyle::Solid,
+                terminator: Terminator::Arrow,
+                start_terminator: Terminator::None,
+                weight: None,
+                order: 0,
+                min_length: 1,
+                parent_port: None,
+                child_port: None,
+            }),
+            None => properties.add_node(&from),
+        }
+    }
+    Ok(properties)
+}
+
+fn split_csv_line(line: &str, delimiter: char) -> Result<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow!("unterminated quoted field"));
+    }
+    fields.push(field);
+    Ok(fields)
+}
@@ -0,0 +1,69 @@
+use anyhow::{Context, Result};
+
+const BEGIN_MARKER: &str = "<!-- mermaid-ascii:begin -->";
+const END_MARKER: &str = "<!-- mermaid-ascii:end -->";
+
+/// Puts a ```` ```text ```` rendering between the markers after every
+/// ```` ```mermaid ```` block of a Markdown document, replacing the one that
+/// is already there. Running it on its own output changes nothing. `render`
+/// gets the source of each diagram; trailing spaces are stripped from what
+/// it returns.
+pub fn inject<F>(doc: &str, mut render: F) -> Result<String>
+where
+    F: FnMut(&str) -> Result<String>,
+{
+    let lines: Vec<&str> = doc.lines().collect();
+    let mut output: Vec<String> = Vec::with_capacity(lines.len());
+    let mut idx = 0;
+    while idx < lines.len() {
+        let line = lines[idx];
+        output.push(line.to_string());
+        idx += 1;
+        if !is_mermaid_fence(line) {
+            continue;
+        }
+
+        let start = idx;
+        while idx < lines.len() && lines[idx].trim() != "```" {
+            idx += 1;
+        }
+        let source = lines[start..idx].join("\n");
+        output.extend(lines[start..idx].iter().map(|line| line.to_string()));
+        if idx == lines.len() {
+            break;
+        }
+        output.push(lines[idx].to_string());
+        idx += 1;
+
+        let drawing = render(&source).with_context(|| format!("rendering the diagram on line {}", start))?;
+        if let Some(end) = existing_block(&lines, idx) {
+            idx = end + 1;
+        }
+        output.push(String::new());
+        output.push(BEGIN_MARKER.to_string());
+        output.push("```text".to_string());
+        output.extend(drawing.lines().map(|line| line.trim_end().to_string()));
+        output.push("```".to_string());
+        output.push(END_MARKER.to_string());
+    }
+
+    let mut text = output.join("\n");
+    if doc.ends_with('\n') {
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+fn is_mermaid_fence(line: &str) -> bool {
+    line.trim_start().strip_prefix("```").is_some_and(|info| info.trim() == "mermaid")
+}
+
+/// The line of the end marker of a rendering that follows a diagram closed
+/// on the line before `from`, skipping blank lines between them.
+fn existing_block(lines: &[&str], from: usize) -> Option<usize> {
+    let begin = (from..lines.len()).find(|idx| !lines[*idx].trim().is_empty())?;
+    if lines[begin].trim() != BEGIN_MARKER {
+        return None;
+    }
+    (begin..lines.len()).find(|idx| lines[*idx].trim() == END_MARKER)
+}
@@ -0,0 +1,12 @@
+pub mod error;
+#[cfg(feature = "flowchart")]
+pub mod format;
+#[cfg(feature = "flowchart")]
+pub mod generate;
+#[cfg(feature = "flowchart")]
+pub mod import;
+#[cfg(feature = "flowchart")]
+pub mod parser;
+pub mod render;
+pub mod source;
+pub mod trace;
@@ -1,81 +1,1198 @@
 use std::fs;
-use std::io::{self, Read};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Context, Result};
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use unicode_width::UnicodeWidthStr;
 
-mod parser;
-mod render;
+use mermaid_ascii::error::ErrorCategory;
+use mermaid_ascii::generate::{self, Topology};
+use mermaid_ascii::format;
+use mermaid_ascii::import;
+use mermaid_ascii::parser::{self, GraphDirection, Strictness};
+use mermaid_ascii::trace;
+use mermaid_ascii::render::{
+    AsciiStyle, AutoStyle, ColorTheme, Graph, IconTable, RenderOptions, RenderOptionsBuilder, Sides,
+};
+use mermaid_ascii::source::{decode_source, expand_includes, SourceEncoding};
 
-use crate::render::{render_properties, RenderOptions};
+mod daemon;
+mod inject;
+mod serve;
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(
     name = "mermaid-ascii",
     about = "Generate ASCII diagrams from Mermaid definitions."
 )]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Mermaid file to parse. Use '-' or omit to read from stdin.
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     file: Option<PathBuf>,
 
     /// Verbose logging
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     verbose: bool,
 
+    /// Log per-phase timings and counts (parse, mapping, routing, drawing)
+    #[arg(long = "trace-layout", global = true)]
+    trace_layout: bool,
+
+    /// Write layout, routing and drawing spans to this file as folded stacks
+    /// (see `inferno-flamegraph`)
+    #[cfg(feature = "profiling")]
+    #[arg(long, value_name = "PATH", global = true)]
+    profile: Option<PathBuf>,
+
     /// Use ASCII characters only
-    #[arg(short = 'a', long = "ascii")]
+    #[arg(short = 'a', long = "ascii", global = true)]
     use_ascii: bool,
 
+    /// Corner and junction characters for --ascii output
+    #[arg(long = "ascii-style", value_enum, global = true)]
+    ascii_style: Option<AsciiStyleArg>,
+
+    /// Bold, italic and colored node text and borders from classDef styles,
+    /// as ANSI attributes; `auto` uses them when stdout is a terminal and
+    /// NO_COLOR is unset
+    #[arg(long, value_enum, value_name = "WHEN", global = true)]
+    color: Option<ColorArg>,
+
+    /// Terminal palette classDef hex colors are matched to with --color
+    #[arg(long = "color-theme", value_enum, value_name = "THEME", global = true)]
+    color_theme: Option<ColorThemeArg>,
+
+    /// Write classDef hex colors as exact 24-bit colors with --color
+    #[arg(long, global = true)]
+    truecolor: bool,
+
+    /// Draw nodes whose name or label matches this regular expression with
+    /// heavy borders, in inverse video with --color
+    #[arg(long, value_name = "REGEX", global = true)]
+    highlight: Option<String>,
+
+    /// Emphasize nodes the way --highlight does, picked by `degree`: the
+    /// --auto-style-top nodes with the most edges
+    #[arg(long = "auto-style", value_enum, global = true)]
+    auto_style: Option<AutoStyleArg>,
+
+    /// How many nodes --auto-style picks [default: 3]
+    #[arg(long = "auto-style-top", value_name = "K", global = true)]
+    auto_style_top: Option<usize>,
+
+    /// Draw the nodes no path of edges leads to from this node with dotted
+    /// borders, or faint with --color
+    #[arg(long = "reachable-from", value_name = "NODE", global = true)]
+    reachable_from: Option<String>,
+
+    /// Render without printing the diagram; only errors and the exit code
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
     /// Show coordinate helpers in the output
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     coords: bool,
 
-    /// Horizontal space between nodes
-    #[arg(short = 'x', long = "paddingX", default_value_t = 5)]
-    padding_x: i32,
+    /// Halve the vertical gaps between nodes so diagrams look proportionate
+    /// in terminal cells, which are about twice as tall as wide
+    #[arg(long = "aspect-compensate", global = true)]
+    aspect_compensate: bool,
+
+    /// Seed for randomized layout tie-breaking; output is reproducible for a
+    /// given input, options and seed
+    #[arg(long = "seed", value_name = "N", global = true)]
+    seed: Option<u64>,
+
+    /// Number the layout levels in a ruler along the main axis
+    #[arg(long = "show-ranks", global = true)]
+    show_ranks: bool,
+
+    /// Report likely mistakes in the input, such as a node relabelled later
+    #[arg(long, global = true)]
+    lint: bool,
+
+    /// Print node and edge counts, grid and output size, and the time each
+    /// phase took to stderr
+    #[arg(long, global = true)]
+    summary: bool,
+
+    /// Merge nodes whose names differ only in case, reporting each merge
+    #[arg(long = "merge-case-insensitive", global = true)]
+    merge_case_insensitive: bool,
+
+    /// Trim whitespace around node names and labels, merging nodes that
+    /// then share a name and reporting each merge
+    #[arg(long = "trim-labels", global = true)]
+    trim_labels: bool,
+
+    /// Draw a border around the diagram with its title in the top edge
+    #[arg(long, global = true)]
+    frame: bool,
+
+    /// Title for --frame [default: the front matter's title]
+    #[arg(long, global = true)]
+    title: Option<String>,
+
+    /// Prefix edge labels with their position in the source
+    #[arg(long = "number-edges", global = true)]
+    number_edges: bool,
+
+    /// Draw edges with braille dots for finer line resolution
+    #[arg(long, conflicts_with = "use_ascii", global = true)]
+    braille: bool,
+
+    /// Abbreviate node names and list the full names below the diagram
+    #[arg(long = "shorten-labels", global = true)]
+    shorten_labels: bool,
+
+    /// List each classDef with its styles and the nodes using it
+    #[arg(long = "class-legend", global = true)]
+    class_legend: bool,
+
+    /// Print how each edge's route was chosen after the diagram
+    #[arg(long, global = true)]
+    explain: bool,
+
+    /// Let edge lines run right up to their labels
+    #[arg(long = "no-label-background", global = true)]
+    no_label_background: bool,
+
+    /// Stack TD edge labels too wide for their column one character per row
+    #[arg(long = "vertical-labels", global = true)]
+    vertical_labels: bool,
+
+    /// Number each node's box in a left gutter with the line it is declared on
+    #[arg(long = "source-map", global = true)]
+    source_map: bool,
+
+    /// Draw edges before node boxes so boxes hide any line that runs into them
+    #[arg(long = "edges-under-nodes", default_value_t = true, action = ArgAction::Set, value_name = "BOOL", global = true)]
+    edges_under_nodes: bool,
+
+    /// Leave right-to-left labels in logical order (for bidi-aware terminals)
+    #[arg(long = "no-bidi", global = true)]
+    no_bidi: bool,
+
+    /// Render each top-level subgraph as its own diagram
+    #[arg(long = "split-by-subgraph", global = true)]
+    split_by_subgraph: bool,
+
+    /// Draw this subgraph as one box with its title and node count; repeatable
+    #[arg(long, value_name = "SUBGRAPH", global = true)]
+    collapse: Vec<String>,
+
+    /// Collapse every subgraph nested more than N levels deep
+    #[arg(long = "collapse-all-below-depth", value_name = "N", global = true)]
+    collapse_all_below_depth: Option<usize>,
+
+    /// Keep reading stdin and re-render each diagram terminated by a blank
+    /// line or `%%end`
+    #[arg(long, global = true)]
+    follow: bool,
 
-    /// Vertical space between nodes
-    #[arg(short = 'y', long = "paddingY", default_value_t = 5)]
-    padding_y: i32,
+    /// Same as the `daemon` subcommand
+    #[arg(long, hide = true, requires = "socket")]
+    daemon: bool,
 
-    /// Padding between text and border
-    #[arg(short = 'p', long = "borderPadding", default_value_t = 1)]
-    border_padding: i32,
+    /// Socket for `--daemon`
+    #[arg(long, hide = true, requires = "daemon")]
+    socket: Option<PathBuf>,
+
+    /// Write the layout grid (node cells, column widths, row heights) to this
+    /// file, for comparing layouts across versions
+    #[arg(long = "dump-grid", value_name = "PATH", global = true)]
+    dump_grid: Option<PathBuf>,
+
+    /// Render options file (TOML); flags given on the command line win
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Lay the graph out in this direction instead of the header's; `auto`
+    /// picks whichever fits the terminal better
+    #[arg(long, value_enum, global = true)]
+    direction: Option<DirectionArg>,
+
+    /// Horizontal space between nodes [default: paddingX directive or 5]
+    #[arg(short = 'x', long = "paddingX", global = true)]
+    padding_x: Option<i32>,
+
+    /// Vertical space between nodes [default: paddingY directive or 5]
+    #[arg(short = 'y', long = "paddingY", global = true)]
+    padding_y: Option<i32>,
+
+    /// Layout heuristics to turn on, comma-separated
+    #[arg(long, value_enum, value_delimiter = ',', global = true)]
+    layout: Vec<LayoutArg>,
+
+    /// Grid cells edges keep clear of nodes they don't connect to [default: 1]
+    #[arg(long = "edge-clearance", global = true)]
+    edge_clearance: Option<i32>,
+
+    /// Extra cost of each corner when routing, in cells; 0 takes the
+    /// shortest route however it bends [default: 1]
+    #[arg(long = "turn-penalty", global = true)]
+    turn_penalty: Option<i32>,
+
+    /// Most nodes a graph may have [default: 10000]
+    #[arg(long = "max-nodes", global = true)]
+    max_nodes: Option<usize>,
+
+    /// Most cells the drawing may take, checked before it is allocated
+    /// [default: 4000000]
+    #[arg(long = "max-canvas-cells", global = true)]
+    max_canvas_cells: Option<usize>,
+
+    /// Format of the input; `auto` tells them apart by the first line
+    #[arg(long = "input-format", value_enum, default_value_t = InputFormat::Auto, global = true)]
+    input_format: InputFormat,
+
+    /// Field separator for `--input-format csv`
+    #[arg(long = "csv-delimiter", default_value_t = ',', global = true)]
+    csv_delimiter: char,
+
+    /// How to treat valid Mermaid that is not drawn as Mermaid would: reject
+    /// it, warn about it, or draw what can be drawn silently
+    #[arg(long, value_enum, default_value_t = StrictnessArg::Compat, global = true)]
+    strictness: StrictnessArg,
+
+    /// Most nodes and links a single statement may contain
+    #[arg(long = "max-statement-terms", default_value_t = parser::DEFAULT_MAX_STATEMENT_TERMS, global = true)]
+    max_statement_terms: usize,
+
+    /// Icon table file with `name unicode ascii` lines, added to the built-in icons
+    #[arg(long, global = true)]
+    icons: Option<PathBuf>,
+
+    /// Make every box at least this many cells wide [default: 0]
+    #[arg(long = "min-node-width", global = true)]
+    min_node_width: Option<i32>,
+
+    /// Blank cells around the diagram: 1 to 4 comma-separated values,
+    /// clockwise from the top as in CSS
+    #[arg(long, value_delimiter = ',', value_name = "CELLS", global = true)]
+    padding: Vec<usize>,
+
+    /// Blank rows above the diagram
+    #[arg(long = "padding-top", global = true)]
+    padding_top: Option<usize>,
+
+    /// Blank columns right of the diagram
+    #[arg(long = "padding-right", global = true)]
+    padding_right: Option<usize>,
+
+    /// Blank rows below the diagram
+    #[arg(long = "padding-bottom", global = true)]
+    padding_bottom: Option<usize>,
+
+    /// Blank columns left of the diagram
+    #[arg(long = "padding-left", global = true)]
+    padding_left: Option<usize>,
+
+    /// Padding between text and border [default: 1]
+    #[arg(short = 'p', long = "borderPadding", global = true)]
+    border_padding: Option<i32>,
+
+    /// Add a comment with the version, options and input hash (footer by default)
+    #[arg(long = "emit-metadata", value_enum, num_args = 0..=1, default_missing_value = "footer", global = true)]
+    emit_metadata: Option<MetadataPosition>,
+
+    /// Put the output in a ```text fence for pasting into Markdown;
+    /// `with-source` adds the input in a collapsed <details> block after it
+    #[arg(long = "wrap-codeblock", value_enum, num_args = 0..=1, default_missing_value = "plain", global = true)]
+    wrap_codeblock: Option<CodeblockWrap>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Render the diagram (what running without a subcommand does)
+    Render,
+    /// Parse and lay out the diagram without printing it, reporting --lint
+    /// warnings; the exit code says whether it renders
+    Check,
+    /// Print the Mermaid source with consistent indentation and spacing
+    /// around links
+    Fmt {
+        /// Rewrite --file in place instead of printing
+        #[arg(long)]
+        write: bool,
+    },
+    /// Print the --summary line (counts, sizes, phase times) instead of the
+    /// diagram
+    Stats,
+    /// Print a shell completion script
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Serve renderings over HTTP, including Kroki-style GET URLs
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8000")]
+        addr: String,
+    },
+    /// Stay resident and render JSON-lines requests from a Unix socket, for
+    /// editors that render on every keystroke
+    Daemon {
+        /// Path of the socket to listen on
+        #[arg(long)]
+        socket: PathBuf,
+    },
+    /// Render the ```mermaid blocks of a Markdown file into ```text blocks
+    /// after them, updating earlier renderings in place
+    Inject {
+        /// Markdown file to update
+        #[arg(long)]
+        doc: PathBuf,
+
+        /// Change nothing; fail if the renderings are missing or out of date
+        #[arg(long)]
+        check: bool,
+    },
+    /// Print a synthetic graph of the given shape and size
+    GenExample {
+        #[arg(value_enum)]
+        topology: TopologyArg,
+
+        /// Number of nodes
+        #[arg(long, default_value_t = 10)]
+        nodes: usize,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum TopologyArg {
+    Chain,
+    Tree,
+    Mesh,
+    Random,
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+impl From<TopologyArg> for Topology {
+    fn from(topology: TopologyArg) -> Topology {
+        match topology {
+            TopologyArg::Chain => Topology::Chain,
+            TopologyArg::Tree => Topology::Tree,
+            TopologyArg::Mesh => Topology::Mesh,
+            TopologyArg::Random => Topology::Random,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum AsciiStyleArg {
+    /// `+` for every corner and junction
+    Plain,
+    /// `.` and `'` for corners, `+` only where lines meet
+    Fancy,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum AutoStyleArg {
+    /// The nodes with the most edges
+    Degree,
+}
+
+impl From<AutoStyleArg> for AutoStyle {
+    fn from(style: AutoStyleArg) -> AutoStyle {
+        match style {
+            AutoStyleArg::Degree => AutoStyle::Degree,
+        }
+    }
+}
+
+impl From<AsciiStyleArg> for AsciiStyle {
+    fn from(style: AsciiStyleArg) -> AsciiStyle {
+        match style {
+            AsciiStyleArg::Plain => AsciiStyle::Plain,
+            AsciiStyleArg::Fancy => AsciiStyle::Fancy,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum StrictnessArg {
+    /// Fail on anything outside the supported grammar
+    Strict,
+    /// Warn about unsupported Mermaid constructs
+    Compat,
+    /// Leave out unsupported statements and say how many
+    Skip,
+    /// Draw what can be drawn without warnings
+    Lenient,
+}
+
+impl From<StrictnessArg> for Strictness {
+    fn from(strictness: StrictnessArg) -> Strictness {
+        match strictness {
+            StrictnessArg::Strict => Strictness::Strict,
+            StrictnessArg::Compat => Strictness::Compat,
+            StrictnessArg::Skip => Strictness::Skip,
+            StrictnessArg::Lenient => Strictness::Lenient,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ColorArg {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ColorThemeArg {
+    /// Bright colors, for dark backgrounds
+    Dark,
+    /// Normal colors without yellow and white, for light backgrounds
+    Light,
+    /// The Solarized accent colors
+    Solarized,
+}
+
+impl From<ColorThemeArg> for ColorTheme {
+    fn from(theme: ColorThemeArg) -> ColorTheme {
+        match theme {
+            ColorThemeArg::Dark => ColorTheme::Dark,
+            ColorThemeArg::Light => ColorTheme::Light,
+            ColorThemeArg::Solarized => ColorTheme::Solarized,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum LayoutArg {
+    /// Put the nodes with most edges in the middle of their level
+    CenterHubs,
+    /// Ignore edge direction and grow a breadth-first tree from the node
+    /// with most edges
+    Undirected,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum DirectionArg {
+    Lr,
+    Td,
+    Auto,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+    Auto,
+    Mermaid,
+    /// JSON Graph Format or vis.js `nodes`/`edges`
+    Json,
+    /// `from,to,label` rows
+    Csv,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum MetadataPosition {
+    Header,
+    Footer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum CodeblockWrap {
+    Plain,
+    WithSource,
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(err) => {
+            let _ = err.print();
+            return if err.use_stderr() { ExitCode::from(1) } else { ExitCode::SUCCESS };
+        }
+    };
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        // Whoever reads our output stopped early (`| head`); that's not a failure.
+        Err(err) if is_broken_pipe(&err) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(ErrorCategory::of(&err).map_or(1, ErrorCategory::exit_code))
+        }
+    }
+}
+
+fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|cause| cause.kind() == io::ErrorKind::BrokenPipe)
+}
+
+/// Writes a finished rendering to stdout unless `--quiet` was given.
+fn write_output(cli: &Cli, text: &str) -> Result<()> {
+    if cli.quiet {
+        return Ok(());
+    }
+    let mut out = io::stdout().lock();
+    out.write_all(text.as_bytes())?;
+    out.flush()?;
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<()> {
     let mut builder = env_logger::Builder::from_default_env();
     if cli.verbose {
         builder.filter_level(log::LevelFilter::Debug);
     } else {
         builder.filter_level(log::LevelFilter::Info);
     }
+    if cli.trace_layout {
+        builder.filter_module(trace::TARGET, log::LevelFilter::Debug);
+    }
     builder.init();
+    #[cfg(feature = "profiling")]
+    let _flame = match &cli.profile {
+        Some(path) => Some(profiling_subscriber(path)?),
+        None => None,
+    };
 
-    let mut input = String::new();
-    match cli.file {
-        Some(path) if path.to_string_lossy() != "-" => {
-            input = fs::read_to_string(&path)?;
+    match &cli.command {
+        Some(Command::GenExample { topology, nodes }) => {
+            // The random topology's seed; 1 unless `--seed` says otherwise.
+            let seed = cli.seed.unwrap_or(1);
+            return write_output(&cli, &generate::generate((*topology).into(), *nodes, seed));
         }
-        _ => {
-            io::stdin().read_to_string(&mut input)?;
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(*shell, &mut Cli::command(), "mermaid-ascii", &mut io::stdout());
+            return Ok(());
         }
+        _ => {}
     }
 
-    let mut properties = parser::mermaid_file_to_map(&input, "cli")?;
-    properties.padding_x = cli.padding_x;
-    properties.padding_y = cli.padding_y;
+    let options = render_options(&cli)?;
+
+    if let Some(Command::Serve { addr }) = &cli.command {
+        return serve::run(addr, |input, ascii| {
+            let mut options = options.clone();
+            if let Some(ascii) = ascii {
+                options.use_ascii = ascii;
+                options.braille &= !ascii;
+            }
+            render(input, &cli, &options)
+        });
+    }
 
-    let options = RenderOptions {
-        border_padding: cli.border_padding,
-        use_ascii: cli.use_ascii,
-        show_coords: cli.coords,
+    let daemon_socket = match &cli.command {
+        Some(Command::Daemon { socket }) => Some(socket),
+        _ => cli.socket.as_ref().filter(|_| cli.daemon),
     };
+    if let Some(socket) = daemon_socket {
+        return daemon::run(socket, |input, ascii| {
+            let mut options = options.clone();
+            if let Some(ascii) = ascii {
+                options.use_ascii = ascii;
+                options.braille &= !ascii;
+            }
+            render(input, &cli, &options)
+        });
+    }
+
+    if let Some(Command::Inject { doc, check }) = &cli.command {
+        return inject_doc(doc, *check, &cli, &options);
+    }
+
+    if cli.follow {
+        return follow_stdin(&cli, &options);
+    }
 
-    let drawing = render_properties(&properties, &options)?;
-    println!("{}", drawing);
+    let mut bytes = Vec::new();
+    let file = cli.file.as_deref().filter(|path| path.to_string_lossy() != "-");
+    match file {
+        Some(path) => {
+            bytes = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        }
+        None => {
+            io::stdin().read_to_end(&mut bytes)?;
+        }
+    }
+    let (input, encoding) = decode_source(&bytes)?;
+    if let Some(Command::Fmt { write }) = &cli.command {
+        let formatted = format::format_mermaid(&input);
+        return match file {
+            Some(path) if *write => {
+                if formatted != input {
+                    fs::write(path, formatted).with_context(|| format!("writing {}", path.display()))?;
+                }
+                Ok(())
+            }
+            _ if *write => anyhow::bail!("fmt --write needs --file"),
+            _ => write_output(&cli, &formatted),
+        };
+    }
+    let input = expand_includes(&input, file)?;
+
+    let stdout = io::stdout();
+    let silent = matches!(cli.command, Some(Command::Check | Command::Stats));
+    let mut out: Box<dyn Write> = if cli.quiet || silent {
+        Box::new(io::sink())
+    } else {
+        Box::new(io::BufWriter::new(stdout.lock()))
+    };
+    let rendered = render_to(&input, &cli, &options, &mut out);
+    if encoding == SourceEncoding::Utf8 {
+        rendered?;
+    } else {
+        rendered.with_context(|| format!("rendering {} input", encoding))?;
+    }
+    writeln!(out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Sends spans to a flame layer writing `path`; the returned guard flushes
+/// it when dropped.
+#[cfg(feature = "profiling")]
+fn profiling_subscriber(path: &std::path::Path) -> Result<tracing_flame::FlushGuard<io::BufWriter<fs::File>>> {
+    use tracing_subscriber::prelude::*;
+
+    let (layer, guard) = tracing_flame::FlameLayer::with_file(path)
+        .with_context(|| format!("creating {}", path.display()))?;
+    // Not `.init()`: that would also take over the `log` records env_logger
+    // already handles.
+    let layer = layer.with_file_and_line(false).with_threads_collapsed(true);
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))?;
+    Ok(guard)
+}
+
+fn inject_doc(doc: &std::path::Path, check: bool, cli: &Cli, options: &RenderOptions) -> Result<()> {
+    let text = fs::read_to_string(doc).with_context(|| format!("reading {}", doc.display()))?;
+    let updated = inject::inject(&text, |source| {
+        let source = expand_includes(source, Some(doc))?;
+        render(&source, cli, options)
+    })
+    .with_context(|| format!("updating {}", doc.display()))?;
+    if updated == text {
+        return Ok(());
+    }
+    if check {
+        anyhow::bail!("{} is out of date; run `mermaid-ascii inject --doc {}`", doc.display(), doc.display());
+    }
+    fs::write(doc, updated).with_context(|| format!("writing {}", doc.display()))
+}
+
+/// Starts from the `--config` file (or the defaults) and applies the flags
+/// that were given on the command line.
+fn render_options(cli: &Cli) -> Result<RenderOptions> {
+    let options = match &cli.config {
+        Some(path) => {
+            let text = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+            toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))?
+        }
+        None => RenderOptions::default(),
+    };
+    let mut sides = options.outer_padding;
+    if !cli.padding.is_empty() {
+        sides = Sides::from_shorthand(&cli.padding)?;
+    }
+    sides.top = cli.padding_top.unwrap_or(sides.top);
+    sides.right = cli.padding_right.unwrap_or(sides.right);
+    sides.bottom = cli.padding_bottom.unwrap_or(sides.bottom);
+    sides.left = cli.padding_left.unwrap_or(sides.left);
+    let mut builder = RenderOptionsBuilder::from(options).outer_padding(sides);
+    if let Some(value) = cli.border_padding {
+        builder = builder.border_padding(value);
+    }
+    if let Some(value) = cli.padding_x {
+        builder = builder.padding_x(value);
+    }
+    if let Some(value) = cli.padding_y {
+        builder = builder.padding_y(value);
+    }
+    if let Some(value) = cli.ascii_style {
+        builder = builder.ascii_style(value.into());
+    }
+    builder = match cli.color {
+        Some(ColorArg::Always) => builder.color(true),
+        Some(ColorArg::Never) => builder.color(false),
+        Some(ColorArg::Auto) => {
+            builder.color(io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none())
+        }
+        None => builder,
+    };
+    if let Some(theme) = cli.color_theme {
+        builder = builder.color_theme(theme.into());
+    }
+    if cli.truecolor {
+        builder = builder.truecolor(true);
+    }
+    if let Some(pattern) = &cli.highlight {
+        builder = builder.highlight(pattern);
+    }
+    if let Some(style) = cli.auto_style {
+        builder = builder.auto_style(style.into());
+    }
+    if let Some(value) = cli.auto_style_top {
+        builder = builder.auto_style_top(value);
+    }
+    if let Some(name) = &cli.reachable_from {
+        builder = builder.reachable_from(name);
+    }
+    builder = match cli.direction {
+        Some(DirectionArg::Lr) => builder.direction(GraphDirection::Lr),
+        Some(DirectionArg::Td) => builder.direction(GraphDirection::Td),
+        Some(DirectionArg::Auto) => {
+            let (columns, rows) = terminal_size();
+            builder.fit_to(columns, rows)
+        }
+        None => builder,
+    };
+    for tweak in &cli.layout {
+        builder = match tweak {
+            LayoutArg::CenterHubs => builder.center_hubs(true),
+            LayoutArg::Undirected => builder.undirected(true),
+        };
+    }
+    if let Some(value) = cli.edge_clearance {
+        builder = builder.edge_clearance(value);
+    }
+    if let Some(value) = cli.turn_penalty {
+        builder = builder.turn_penalty(value);
+    }
+    if let Some(value) = cli.max_nodes {
+        builder = builder.max_nodes(value);
+    }
+    if let Some(value) = cli.max_canvas_cells {
+        builder = builder.max_canvas_cells(value);
+    }
+    if let Some(value) = cli.min_node_width {
+        builder = builder.min_node_width(value);
+    }
+    if cli.use_ascii {
+        builder = builder.use_ascii(true);
+    }
+    if cli.coords {
+        builder = builder.show_coords(true);
+    }
+    if cli.show_ranks {
+        builder = builder.show_ranks(true);
+    }
+    if cli.frame {
+        builder = builder.frame(true);
+    }
+    if cli.aspect_compensate {
+        builder = builder.aspect_compensate(true);
+    }
+    if let Some(seed) = cli.seed {
+        builder = builder.seed(seed);
+    }
+    if let Some(title) = &cli.title {
+        builder = builder.title(title.clone());
+    }
+    if cli.number_edges {
+        builder = builder.number_edges(true);
+    }
+    if cli.braille {
+        builder = builder.braille(true);
+    }
+    if cli.shorten_labels {
+        builder = builder.shorten_labels(true);
+    }
+    if cli.class_legend {
+        builder = builder.class_legend(true);
+    }
+    if cli.explain {
+        builder = builder.explain(true);
+    }
+    if cli.no_label_background {
+        builder = builder.clear_label_background(false);
+    }
+    if cli.vertical_labels {
+        builder = builder.vertical_labels(true);
+    }
+    if cli.source_map {
+        builder = builder.source_map(true);
+    }
+    if !cli.edges_under_nodes {
+        builder = builder.edges_under_nodes(false);
+    }
+    if cli.no_bidi {
+        builder = builder.reorder_bidi(false);
+    }
+    if let Some(path) = &cli.icons {
+        let table = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let mut icons = IconTable::default();
+        icons.load(&table)?;
+        builder = builder.icons(icons);
+    }
+    builder.build()
+}
+
+/// `COLUMNS`/`LINES` if set, else the size of the terminal on stdout, else
+/// 80×24.
+fn terminal_size() -> (usize, usize) {
+    let from_env = |name| std::env::var(name).ok().and_then(|value| value.parse::<usize>().ok()).filter(|n| *n > 0);
+    let (columns, rows) = window_size().unwrap_or((80, 24));
+    (from_env("COLUMNS").unwrap_or(columns), from_env("LINES").unwrap_or(rows))
+}
+
+#[cfg(unix)]
+fn window_size() -> Option<(usize, usize)> {
+    let mut size = libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+    // SAFETY: TIOCGWINSZ only writes a `winsize` through the pointer.
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    (ok && size.ws_col > 0 && size.ws_row > 0).then_some((size.ws_col as usize, size.ws_row as usize))
+}
+
+#[cfg(not(unix))]
+fn window_size() -> Option<(usize, usize)> {
+    None
+}
+
+fn render(input: &str, cli: &Cli, options: &RenderOptions) -> Result<String> {
+    let mut output = Vec::new();
+    render_to(input, cli, options, &mut output)?;
+    Ok(String::from_utf8(output)?)
+}
+
+/// Writes the rendering of `input` to `out` as it is drawn, with the
+/// `--emit-metadata` comment around it and the `--wrap-codeblock` fence
+/// inside that.
+fn render_to(input: &str, cli: &Cli, options: &RenderOptions, out: &mut dyn Write) -> Result<()> {
+    if cli.emit_metadata == Some(MetadataPosition::Header) {
+        writeln!(out, "{}", metadata_comment(input, cli, options))?;
+    }
+    if cli.wrap_codeblock.is_some() {
+        writeln!(out, "```text")?;
+    }
+    render_diagram(input, cli, options, out)?;
+    if let Some(wrap) = cli.wrap_codeblock {
+        write!(out, "\n```")?;
+        if wrap == CodeblockWrap::WithSource {
+            let language = match input_format(input, cli)? {
+                InputFormat::Auto | InputFormat::Mermaid => "mermaid",
+                InputFormat::Json => "json",
+                InputFormat::Csv => "csv",
+            };
+            let source = input.trim_end_matches('\n');
+            write!(out, "\n\n<details>\n<summary>Source</summary>\n\n```{}\n{}\n```\n\n</details>", language, source)?;
+        }
+    }
+    if cli.emit_metadata == Some(MetadataPosition::Footer) {
+        write!(out, "\n{}", metadata_comment(input, cli, options))?;
+    }
+    Ok(())
+}
+
+/// An HTML comment recording how a diagram was produced, so a copy pasted
+/// into docs can be checked against its source and regenerated.
+fn metadata_comment(input: &str, cli: &Cli, options: &RenderOptions) -> String {
+    let mut flags = Vec::new();
+    if let Some(padding_x) = options.padding_x {
+        flags.push(format!("-x {}", padding_x));
+    }
+    if let Some(padding_y) = options.padding_y {
+        flags.push(format!("-y {}", padding_y));
+    }
+    if options.ascii_style == AsciiStyle::Fancy {
+        flags.push("--ascii-style fancy".to_string());
+    }
+    match options.color_theme {
+        ColorTheme::Dark => {}
+        ColorTheme::Light => flags.push("--color-theme light".to_string()),
+        ColorTheme::Solarized => flags.push("--color-theme solarized".to_string()),
+    }
+    if let Some(direction) = options.direction {
+        flags.push(format!("--direction {}", if direction == GraphDirection::Lr { "lr" } else { "td" }));
+    } else if options.fit_to.is_some() {
+        flags.push("--direction auto".to_string());
+    }
+    let layouts: Vec<&str> = [(options.center_hubs, "center-hubs"), (options.undirected, "undirected")]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, name)| *name)
+        .collect();
+    if !layouts.is_empty() {
+        flags.push(format!("--layout {}", layouts.join(",")));
+    }
+    if let Some(title) = &options.title {
+        flags.push(format!("--title {:?}", title));
+    }
+    if let Some(pattern) = &options.highlight {
+        flags.push(format!("--highlight {:?}", pattern));
+    }
+    if options.auto_style == Some(AutoStyle::Degree) {
+        flags.push(format!("--auto-style degree --auto-style-top {}", options.auto_style_top));
+    }
+    if let Some(name) = &options.reachable_from {
+        flags.push(format!("--reachable-from {:?}", name));
+    }
+    if options.outer_padding != Sides::default() {
+        let Sides { top, right, bottom, left } = options.outer_padding;
+        flags.push(format!("--padding {},{},{},{}", top, right, bottom, left));
+    }
+    match cli.input_format {
+        InputFormat::Auto | InputFormat::Mermaid => {}
+        InputFormat::Json => flags.push("--input-format json".to_string()),
+        InputFormat::Csv => flags.push(format!("--input-format csv --csv-delimiter {:?}", cli.csv_delimiter)),
+    }
+    if let Some(path) = &cli.icons {
+        flags.push(format!("--icons {:?}", path));
+    }
+    match cli.strictness {
+        StrictnessArg::Compat => {}
+        StrictnessArg::Strict => flags.push("--strictness strict".to_string()),
+        StrictnessArg::Skip => flags.push("--strictness skip".to_string()),
+        StrictnessArg::Lenient => flags.push("--strictness lenient".to_string()),
+    }
+    match cli.wrap_codeblock {
+        None => {}
+        Some(CodeblockWrap::Plain) => flags.push("--wrap-codeblock".to_string()),
+        Some(CodeblockWrap::WithSource) => flags.push("--wrap-codeblock with-source".to_string()),
+    }
+    flags.extend([
+        format!("-p {}", options.border_padding),
+        format!("--edge-clearance {}", options.edge_clearance),
+        format!("--turn-penalty {}", options.turn_penalty),
+        format!("--min-node-width {}", options.min_node_width),
+        format!("--seed {}", options.seed),
+        format!("--max-statement-terms {}", cli.max_statement_terms),
+    ]);
+    flags.extend(cli.collapse.iter().map(|name| format!("--collapse {:?}", name)));
+    if let Some(depth) = cli.collapse_all_below_depth {
+        flags.push(format!("--collapse-all-below-depth {}", depth));
+    }
+    let switches = [
+        (options.use_ascii, "--ascii"),
+        (options.show_coords, "--coords"),
+        (options.show_ranks, "--show-ranks"),
+        (options.frame, "--frame"),
+        (options.aspect_compensate, "--aspect-compensate"),
+        (options.color, "--color always"),
+        (options.truecolor, "--truecolor"),
+        (options.number_edges, "--number-edges"),
+        (options.braille, "--braille"),
+        (options.shorten_labels, "--shorten-labels"),
+        (options.class_legend, "--class-legend"),
+        (options.explain, "--explain"),
+        (!options.clear_label_background, "--no-label-background"),
+        (options.vertical_labels, "--vertical-labels"),
+        (options.source_map, "--source-map"),
+        (!options.reorder_bidi, "--no-bidi"),
+        (!options.edges_under_nodes, "--edges-under-nodes=false"),
+        (cli.split_by_subgraph, "--split-by-subgraph"),
+        (cli.merge_case_insensitive, "--merge-case-insensitive"),
+        (cli.trim_labels, "--trim-labels"),
+    ];
+    flags.extend(
+        switches
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, flag)| flag.to_string()),
+    );
+    format!(
+        "<!-- mermaid-ascii {} | options: {} | input fnv1a64: {:016x} -->",
+        env!("CARGO_PKG_VERSION"),
+        flags.join(" "),
+        fnv1a64(input.as_bytes())
+    )
+}
+
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// `--input-format`, or with `auto` the format `input` looks like.
+fn input_format(input: &str, cli: &Cli) -> Result<InputFormat> {
+    if cli.input_format != InputFormat::Auto {
+        return Ok(cli.input_format);
+    }
+    Ok(match import::detect_format(input, cli.csv_delimiter)? {
+        import::DetectedFormat::Mermaid => InputFormat::Mermaid,
+        import::DetectedFormat::Json => InputFormat::Json,
+        import::DetectedFormat::Csv => InputFormat::Csv,
+    })
+}
+
+fn render_diagram(input: &str, cli: &Cli, options: &RenderOptions, out: &mut dyn Write) -> Result<()> {
+    let started = Instant::now();
+    let mut properties = match input_format(input, cli)? {
+        InputFormat::Auto | InputFormat::Mermaid => {
+            if matches!(cli.strictness, StrictnessArg::Skip) {
+                let (kept, skipped, diagnostics) = parser::skip_unsupported(input);
+                for diagnostic in diagnostics {
+                    log::warn!("{}", diagnostic);
+                }
+                if skipped.statements > 0 {
+                    log::warn!("{}", skipped);
+                }
+                parser::mermaid_file_to_map_with_limit(&kept, "cli", cli.max_statement_terms)?
+            } else {
+                for diagnostic in parser::check_grammar(input, cli.strictness.into())? {
+                    log::warn!("{}", diagnostic);
+                }
+                parser::mermaid_file_to_map_with_limit(input, "cli", cli.max_statement_terms)?
+            }
+        }
+        InputFormat::Json => import::json_to_map(input, "cli")?,
+        InputFormat::Csv => import::csv_to_map(input, cli.csv_delimiter, "cli")?,
+    };
+    let mut summary = Summary::new(started.elapsed());
+    if cli.lint || matches!(cli.command, Some(Command::Check)) {
+        for warning in &properties.warnings {
+            log::warn!("{}", warning);
+        }
+    }
+    if cli.merge_case_insensitive || cli.trim_labels {
+        for merge in properties.merge_aliases(cli.merge_case_insensitive, cli.trim_labels) {
+            log::info!("{}", merge);
+        }
+    }
+    if !cli.collapse.is_empty() || cli.collapse_all_below_depth.is_some() {
+        properties.collapse_subgraphs(&cli.collapse, cli.collapse_all_below_depth)?;
+    }
+
+    let parts = if cli.split_by_subgraph && !properties.subgraphs.is_empty() {
+        properties.split_by_subgraph()
+    } else {
+        vec![(String::new(), properties)]
+    };
+    // With `--summary` the output is collected so its size can be measured.
+    let mut collected = Vec::new();
+    let stats = matches!(cli.command, Some(Command::Stats));
+    let target: &mut dyn Write = if cli.summary || stats { &mut collected } else { out };
+    let mut grids = Vec::new();
+    for (idx, (name, part)) in parts.iter().enumerate() {
+        let mut graph = Graph::new(part, options.clone());
+        graph.layout()?;
+        if idx > 0 {
+            target.write_all(b"\n\n")?;
+        }
+        if name.is_empty() {
+            grids.push(graph.grid_snapshot());
+        } else {
+            writeln!(target, "{}", name)?;
+            grids.push(format!("# {}\n{}", name, graph.grid_snapshot()));
+        }
+        let started = Instant::now();
+        graph.render_to(&mut *target)?;
+        summary.add(&graph, started.elapsed());
+    }
+    if let Some(path) = &cli.dump_grid {
+        fs::write(path, grids.join("\n")).with_context(|| format!("writing {}", path.display()))?;
+    }
+    if cli.summary || stats {
+        let text = String::from_utf8_lossy(&collected);
+        summary.output = (text.lines().map(visible_width).max().unwrap_or(0), text.lines().count());
+        if stats {
+            println!("{}", summary);
+        } else {
+            eprintln!("{}", summary);
+        }
+        out.write_all(&collected)?;
+    }
     Ok(())
 }
+
+/// What `--summary` reports, added up over the parts of a split diagram.
+struct Summary {
+    nodes: usize,
+    edges: usize,
+    grids: Vec<(usize, usize)>,
+    output: (usize, usize),
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Summary {
+    fn new(parse: Duration) -> Summary {
+        Summary { nodes: 0, edges: 0, grids: Vec::new(), output: (0, 0), phases: vec![("parse", parse)] }
+    }
+
+    fn add(&mut self, graph: &Graph, drawing_time: Duration) {
+        let stats = graph.stats();
+        self.nodes += stats.nodes;
+        self.edges += stats.edges;
+        self.grids.push(stats.grid);
+        for (name, elapsed) in stats.phases.into_iter().chain([("drawing", drawing_time)]) {
+            match self.phases.iter_mut().find(|(phase, _)| *phase == name) {
+                Some((_, total)) => *total += elapsed,
+                None => self.phases.push((name, elapsed)),
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let grids: Vec<String> = self.grids.iter().map(|(columns, rows)| format!("{}×{}", columns, rows)).collect();
+        write!(
+            f,
+            "summary: {} nodes, {} edges, grid {}, output {}×{}",
+            self.nodes,
+            self.edges,
+            grids.join("+"),
+            self.output.0,
+            self.output.1
+        )?;
+        for (name, elapsed) in &self.phases {
+            write!(f, ", {} {:.1}ms", name, elapsed.as_secs_f64() * 1000.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Terminal columns of a line of output, not counting ANSI SGR sequences.
+fn visible_width(line: &str) -> usize {
+    let mut visible = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find('\x1b') {
+        visible.push_str(&rest[..start]);
+        rest = rest[start..].find('m').map_or("", |end| &rest[start + end + 1..]);
+    }
+    visible.push_str(rest);
+    UnicodeWidthStr::width(visible.as_str())
+}
+
+/// Renders every diagram that arrives on stdin, each one terminated by a
+/// blank line or a `%%end` line. Bad diagrams are reported and skipped so a
+/// long-running producer can fix them in its next update.
+fn follow_stdin(cli: &Cli, options: &RenderOptions) -> Result<()> {
+    let stdout = io::stdout();
+    let clear_screen = stdout.is_terminal();
+    let mut pending = String::new();
+    let emit = |pending: &mut String| -> Result<()> {
+        if pending.trim().is_empty() {
+            pending.clear();
+            return Ok(());
+        }
+        let mut out = stdout.lock();
+        match expand_includes(pending, None).and_then(|input| render(&input, cli, options)) {
+            Ok(_) if cli.quiet => {}
+            Ok(drawing) => {
+                if clear_screen {
+                    write!(out, "\x1b[2J\x1b[H")?;
+                }
+                writeln!(out, "{}\n", drawing)?;
+                out.flush()?;
+            }
+            Err(err) => log::error!("{:#}", err),
+        }
+        pending.clear();
+        Ok(())
+    };
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let line = line.trim_start_matches('\u{feff}');
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed == "%%end" {
+            emit(&mut pending)?;
+        } else {
+            pending.push_str(line);
+            pending.push('\n');
+        }
+    }
+    emit(&mut pending)
+}
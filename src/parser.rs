@@ -1,11 +1,17 @@
 use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 
 use anyhow::{anyhow, Result};
 use indexmap::IndexMap;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{tagged, ErrorCategory};
+use crate::trace::Phase;
 
 pub const DEFAULT_PADDING: i32 = 5;
+pub const DEFAULT_MAX_STATEMENT_TERMS: usize = 10_000;
 
 #[derive(Clone, Debug)]
 pub struct StyleClass {
@@ -13,10 +19,81 @@ pub struct StyleClass {
     pub styles: HashMap<String, String>,
 }
 
+impl StyleClass {
+    /// The style of a node with the classes `names`, in the order they were
+    /// applied: later classes override the keys of earlier ones. Names
+    /// without a `classDef` are skipped, and with none left there is no
+    /// style.
+    pub fn resolve(names: &[String], style_classes: &HashMap<String, StyleClass>) -> Option<StyleClass> {
+        let defined: Vec<&StyleClass> = names.iter().filter_map(|name| style_classes.get(name)).collect();
+        if defined.is_empty() {
+            return None;
+        }
+        Some(StyleClass {
+            name: defined.iter().map(|class| class.name.as_str()).collect::<Vec<_>>().join(","),
+            styles: defined.iter().flat_map(|class| class.styles.clone()).collect(),
+        })
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TextNode {
     pub name: String,
+    /// Display text from `name[label]`, when given.
+    pub label: Option<String>,
+    /// Text after `:::`, with `:::` between the names of several classes.
     pub style_class: Option<String>,
+    pub shape: NodeShape,
+}
+
+/// The outline drawn around a node, from the brackets it was declared with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum NodeShape {
+    /// `A[text]`, or no brackets at all.
+    #[default]
+    Rectangle,
+    /// `A>text]`, notched on the left.
+    Flag,
+    /// `A[/text\]`, narrower at the top.
+    Trapezoid,
+    /// `A[\text/]`, narrower at the bottom.
+    ReverseTrapezoid,
+    /// `A[[title|key: value|…]]`, a title over a table of rows. The label
+    /// keeps the `|`-separated parts.
+    Record,
+    /// A bare `spacer` term or a node named by `%%spacer`: it takes up grid
+    /// space but nothing is drawn for it.
+    Spacer,
+}
+
+/// The side of a box an edge is fixed to by `A:e --> B:w`, as with
+/// Graphviz compass ports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Port {
+    North,
+    South,
+    East,
+    West,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineStyle {
+    #[default]
+    Solid,
+    Dotted,
+    /// `~~~`: placed like any edge but not drawn.
+    Invisible,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Terminator {
+    None,
+    #[default]
+    Arrow,
+    /// `--o`: a dot on the line.
+    Circle,
+    /// `--x`: a cross on the line.
+    Cross,
 }
 
 #[derive(Clone, Debug)]
@@ -24,6 +101,33 @@ pub struct TextEdge {
     pub parent: TextNode,
     pub child: TextNode,
     pub label: String,
+    pub line_style: LineStyle,
+    pub terminator: Terminator,
+    /// What the parent end gets: a circle or cross for `o--o` and `x--x`.
+    pub start_terminator: Terminator,
+    /// Relative magnitude from `{weight=N}` or a `linkStyle` stroke width.
+    pub weight: Option<u32>,
+    /// Position of the edge in the source, counting from zero.
+    pub order: usize,
+    /// Levels between the ends: 1, plus one per extra dash in `---->`.
+    pub min_length: usize,
+    /// Sides the edge leaves `parent` from and enters `child` by, when
+    /// given as ports.
+    pub parent_port: Option<Port>,
+    pub child_port: Option<Port>,
+}
+
+impl TextEdge {
+    /// Whether `other` links the same nodes the same way, so merging graphs
+    /// or nodes keeps only one of them.
+    pub fn same_as(&self, other: &TextEdge) -> bool {
+        self.parent.name == other.parent.name
+            && self.child.name == other.child.name
+            && self.label == other.label
+            && self.line_style == other.line_style
+            && self.terminator == other.terminator
+            && self.start_terminator == other.start_terminator
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -34,9 +138,12 @@ pub struct TextSubgraph {
     pub children: Vec<usize>,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum GraphDirection {
+    #[serde(alias = "LR")]
     Lr,
+    #[serde(alias = "TD", alias = "tb", alias = "TB")]
     Td,
 }
 
@@ -49,52 +156,539 @@ pub struct GraphProperties {
     pub padding_x: i32,
     pub padding_y: i32,
     pub subgraphs: Vec<TextSubgraph>,
+    /// Display text for nodes declared as `name[label]`, keyed by name.
+    pub labels: HashMap<String, String>,
+    /// Shapes other than a rectangle, keyed by node name.
+    pub shapes: HashMap<String, NodeShape>,
+    /// The classes given to each node with `:::class` or a `class`
+    /// statement, in the order they were applied, keyed by name.
+    pub classes: HashMap<String, Vec<String>>,
+    /// The `title:` from the diagram's front matter.
+    pub title: Option<String>,
+    /// Things that parsed but are probably mistakes, e.g. a node declared
+    /// again with a different label.
+    pub warnings: Vec<String>,
+    /// Bytes of the parsed text where each node is first written, keyed by
+    /// name.
+    pub node_spans: HashMap<String, Range<usize>>,
+    /// The line of the parsed text, counting from 1, on which each node's
+    /// span starts.
+    pub node_lines: HashMap<String, usize>,
+    /// Bytes of the parsed text declaring each edge, from its source node
+    /// (or group) to its target, keyed by edge order.
+    pub edge_spans: HashMap<usize, Range<usize>>,
 }
 
 impl GraphProperties {
-    fn add_node(&mut self, node: &TextNode) {
+    /// An empty left-to-right graph with the default padding, for building
+    /// graphs from other formats.
+    pub fn new(style_type: &str) -> GraphProperties {
+        GraphProperties {
+            data: IndexMap::new(),
+            style_classes: HashMap::new(),
+            graph_direction: GraphDirection::Lr,
+            style_type: style_type.to_string(),
+            padding_x: DEFAULT_PADDING,
+            padding_y: DEFAULT_PADDING,
+            subgraphs: Vec::new(),
+            labels: HashMap::new(),
+            shapes: HashMap::new(),
+            classes: HashMap::new(),
+            title: None,
+            warnings: Vec::new(),
+            node_spans: HashMap::new(),
+            node_lines: HashMap::new(),
+            edge_spans: HashMap::new(),
+        }
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.data.values().map(Vec::len).sum()
+    }
+
+    /// Breaks the graph into one graph per top-level subgraph, plus one for
+    /// nodes outside every subgraph. Edges that leave a partition end in a
+    /// stub node named after the node on the other side (`to X` / `from X`).
+    pub fn split_by_subgraph(&self) -> Vec<(String, GraphProperties)> {
+        let mut partitions: Vec<(String, Vec<String>, Vec<usize>)> = Vec::new();
+        for (idx, subgraph) in self.subgraphs.iter().enumerate() {
+            if subgraph.parent.is_none() {
+                partitions.push((subgraph.name.clone(), subgraph.nodes.clone(), vec![idx]));
+            }
+        }
+        let grouped: HashSet<&String> = partitions.iter().flat_map(|(_, nodes, _)| nodes).collect();
+        let ungrouped: Vec<String> = self
+            .data
+            .keys()
+            .filter(|name| !grouped.contains(name))
+            .cloned()
+            .collect();
+        if !ungrouped.is_empty() {
+            partitions.push(("(ungrouped)".to_string(), ungrouped, Vec::new()));
+        }
+
+        partitions
+            .into_iter()
+            .map(|(name, members, roots)| {
+                let members: HashSet<String> = members.into_iter().collect();
+                let mut part = GraphProperties {
+                    data: IndexMap::new(),
+                    style_classes: self.style_classes.clone(),
+                    graph_direction: self.graph_direction,
+                    style_type: self.style_type.clone(),
+                    padding_x: self.padding_x,
+                    padding_y: self.padding_y,
+                    subgraphs: Vec::new(),
+                    labels: self.labels.clone(),
+                    shapes: self.shapes.clone(),
+                    classes: self.classes.clone(),
+                    title: Some(name.clone()),
+                    warnings: Vec::new(),
+                    node_spans: self.node_spans.clone(),
+                    node_lines: self.node_lines.clone(),
+                    edge_spans: self.edge_spans.clone(),
+                };
+                for (node, edges) in &self.data {
+                    if members.contains(node) {
+                        part.data.entry(node.clone()).or_default();
+                    }
+                    for edge in edges {
+                        let (parent_in, child_in) =
+                            (members.contains(node), members.contains(&edge.child.name));
+                        if !parent_in && !child_in {
+                            continue;
+                        }
+                        let mut edge = edge.clone();
+                        if !child_in {
+                            edge.child = stub_node(&format!("to {}", self.display_name(&edge.child.name)));
+                        }
+                        if !parent_in {
+                            edge.parent = stub_node(&format!("from {}", self.display_name(&edge.parent.name)));
+                        }
+                        let parent = edge.parent.clone();
+                        part.set_data(&parent, edge);
+                    }
+                }
+                part.copy_subgraphs(self, &roots, None);
+                (name, part)
+            })
+            .collect()
+    }
+
+    /// Adds the nodes, edges, style classes and subgraphs of `other`. Nodes
+    /// with the same name are the same node, and an edge that is already
+    /// present is not added twice. Where both graphs define a label or a
+    /// class, `self` keeps its own. Subgraphs whose name is taken get a
+    /// numeric suffix (`Backend (2)`). Direction and padding stay as they are.
+    pub fn merge(&mut self, other: GraphProperties) {
+        if self.title.is_none() {
+            self.title = other.title;
+        }
+        for (name, style) in other.style_classes {
+            self.style_classes.entry(name).or_insert(style);
+        }
+        for (name, label) in other.labels {
+            self.labels.entry(name).or_insert(label);
+        }
+        for (name, shape) in other.shapes {
+            self.shapes.entry(name).or_insert(shape);
+        }
+        for (name, classes) in other.classes {
+            self.classes.entry(name).or_insert(classes);
+        }
+        self.warnings.extend(other.warnings);
+
+        let mut edges: Vec<TextEdge> = other.data.values().flatten().cloned().collect();
+        edges.sort_by_key(|edge| edge.order);
+        for name in other.data.keys() {
+            self.data.entry(name.clone()).or_default();
+        }
+        for edge in edges {
+            if !self.data[&edge.parent.name].iter().any(|existing| existing.same_as(&edge)) {
+                self.add_edge(edge);
+            }
+        }
+
+        let offset = self.subgraphs.len();
+        for mut subgraph in other.subgraphs {
+            let mut name = subgraph.name.clone();
+            let mut suffix = 2;
+            while self.subgraphs.iter().any(|existing| existing.name == name) {
+                name = format!("{} ({})", subgraph.name, suffix);
+                suffix += 1;
+            }
+            subgraph.name = name;
+            subgraph.parent = subgraph.parent.map(|idx| idx + offset);
+            subgraph.children = subgraph.children.iter().map(|idx| idx + offset).collect();
+            self.subgraphs.push(subgraph);
+        }
+    }
+
+    /// Merges nodes whose names differ only in surrounding whitespace (with
+    /// `trim`, which also trims labels) or in case (with `ignore_case`) into
+    /// the first one seen, and returns a `merged X into Y` line for each
+    /// name that was folded into another. Edges that become identical are
+    /// kept once.
+    pub fn merge_aliases(&mut self, ignore_case: bool, trim: bool) -> Vec<String> {
+        let key = |name: &str| {
+            let name = if trim { name.trim() } else { name };
+            if ignore_case { name.to_lowercase() } else { name.to_string() }
+        };
+        // The first name seen for each key, and what it becomes.
+        let mut first: HashMap<String, (&String, String)> = HashMap::new();
+        let mut renames: HashMap<String, String> = HashMap::new();
+        let mut merges = Vec::new();
+        for name in self.data.keys() {
+            let (original, kept) = first
+                .entry(key(name))
+                .or_insert_with(|| (name, if trim { name.trim().to_string() } else { name.clone() }));
+            if *original != name {
+                merges.push(format!("merged {:?} into {:?}", name, kept));
+            }
+            if kept != name {
+                renames.insert(name.clone(), kept.clone());
+            }
+        }
+        if renames.is_empty() && !trim {
+            return merges;
+        }
+
+        let rename = |name: &str| renames.get(name).cloned().unwrap_or_else(|| name.to_string());
+        let tidy = |node: &mut TextNode| {
+            node.name = rename(&node.name);
+            if trim {
+                node.label = node.label.as_ref().map(|label| label.trim().to_string());
+            }
+        };
+        let mut data: IndexMap<String, Vec<TextEdge>> = IndexMap::new();
+        for (name, edges) in std::mem::take(&mut self.data) {
+            let merged = data.entry(rename(&name)).or_default();
+            for mut edge in edges {
+                tidy(&mut edge.parent);
+                tidy(&mut edge.child);
+                if !merged.iter().any(|existing| existing.same_as(&edge)) {
+                    merged.push(edge);
+                }
+            }
+        }
+        self.data = data;
+        for (name, label) in std::mem::take(&mut self.labels) {
+            let label = if trim { label.trim().to_string() } else { label };
+            self.labels.entry(rename(&name)).or_insert(label);
+        }
+        for (name, shape) in std::mem::take(&mut self.shapes) {
+            self.shapes.entry(rename(&name)).or_insert(shape);
+        }
+        for (name, classes) in std::mem::take(&mut self.classes) {
+            self.add_classes(&rename(&name), classes.iter().map(String::as_str));
+        }
+        for subgraph in &mut self.subgraphs {
+            let mut seen = HashSet::new();
+            subgraph.nodes = subgraph
+                .nodes
+                .iter()
+                .map(|name| rename(name))
+                .filter(|name| seen.insert(name.clone()))
+                .collect();
+        }
+        merges
+    }
+
+    /// Replaces each subgraph named in `names`, and with `below_depth` each
+    /// one nested deeper than that (top-level subgraphs are at depth 1), with
+    /// a single node labelled with its title and node count. Edges between
+    /// its nodes are dropped, edges leaving it start or end at the new node
+    /// instead, and edges that become identical are kept once.
+    pub fn collapse_subgraphs(&mut self, names: &[String], below_depth: Option<usize>) -> Result<()> {
+        for name in names {
+            if !self.subgraphs.iter().any(|subgraph| &subgraph.name == name) {
+                return Err(anyhow!("collapse names no subgraph: {}", name));
+            }
+        }
+        let ancestors = |idx: usize| {
+            std::iter::successors(self.subgraphs[idx].parent, |parent| self.subgraphs[*parent].parent)
+        };
+        let chosen: Vec<bool> = (0..self.subgraphs.len())
+            .map(|idx| {
+                names.contains(&self.subgraphs[idx].name)
+                    || below_depth.is_some_and(|limit| ancestors(idx).count() + 1 > limit)
+            })
+            .collect();
+        // Only the outermost chosen subgraphs become nodes; the ones inside
+        // them go with them.
+        let removed: Vec<bool> =
+            (0..chosen.len()).map(|idx| chosen[idx] || ancestors(idx).any(|parent| chosen[parent])).collect();
+        let roots: Vec<usize> =
+            (0..chosen.len()).filter(|idx| chosen[*idx] && !ancestors(*idx).any(|parent| chosen[parent])).collect();
+        if roots.is_empty() {
+            return Ok(());
+        }
+
+        let mut renames: HashMap<String, String> = HashMap::new();
+        for idx in roots {
+            let subgraph = &self.subgraphs[idx];
+            let taken = |name: &String| {
+                (self.data.contains_key(name) && !subgraph.nodes.contains(name)) || renames.values().any(|v| v == name)
+            };
+            let mut summary = subgraph.name.clone();
+            let mut suffix = 2;
+            while taken(&summary) {
+                summary = format!("{} ({})", subgraph.name, suffix);
+                suffix += 1;
+            }
+            let count = subgraph.nodes.len();
+            let label = format!("{} ({} node{})", subgraph.name, count, if count == 1 { "" } else { "s" });
+            for member in &subgraph.nodes {
+                self.labels.remove(member);
+                self.shapes.remove(member);
+                self.classes.remove(member);
+                self.node_spans.remove(member);
+                self.node_lines.remove(member);
+                renames.insert(member.clone(), summary.clone());
+            }
+            self.labels.insert(summary, label);
+        }
+
+        let retarget = |node: &mut TextNode| {
+            if let Some(summary) = renames.get(&node.name) {
+                *node = TextNode { name: summary.clone(), label: None, style_class: None, shape: NodeShape::Rectangle };
+            }
+        };
+        let mut data: IndexMap<String, Vec<TextEdge>> = IndexMap::new();
+        for (name, edges) in std::mem::take(&mut self.data) {
+            let collapsed = renames.get(&name).cloned().unwrap_or(name);
+            let kept = data.entry(collapsed.clone()).or_default();
+            for mut edge in edges {
+                let touches = renames.contains_key(&edge.parent.name) || renames.contains_key(&edge.child.name);
+                retarget(&mut edge.parent);
+                retarget(&mut edge.child);
+                if touches && edge.child.name == collapsed {
+                    continue;
+                }
+                if !kept.iter().any(|existing| existing.same_as(&edge)) {
+                    kept.push(edge);
+                }
+            }
+        }
+        self.data = data;
+
+        let mut new_index = Vec::with_capacity(removed.len());
+        let mut next = 0;
+        for gone in &removed {
+            new_index.push((!gone).then_some(next));
+            next += usize::from(!gone);
+        }
+        let subgraphs = std::mem::take(&mut self.subgraphs);
+        for (_, mut subgraph) in subgraphs.into_iter().enumerate().filter(|(idx, _)| !removed[*idx]) {
+            subgraph.parent = subgraph.parent.and_then(|parent| new_index[parent]);
+            subgraph.children = subgraph.children.iter().filter_map(|child| new_index[*child]).collect();
+            let mut seen = HashSet::new();
+            subgraph.nodes = subgraph
+                .nodes
+                .iter()
+                .map(|name| renames.get(name).unwrap_or(name).clone())
+                .filter(|name| seen.insert(name.clone()))
+                .collect();
+            self.subgraphs.push(subgraph);
+        }
+        Ok(())
+    }
+
+    fn display_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.labels.get(name).map(String::as_str).unwrap_or(name)
+    }
+
+    fn copy_subgraphs(&mut self, source: &GraphProperties, indices: &[usize], parent: Option<usize>) {
+        for idx in indices {
+            let subgraph = &source.subgraphs[*idx];
+            let new_idx = self.subgraphs.len();
+            self.subgraphs.push(TextSubgraph {
+                name: subgraph.name.clone(),
+                nodes: subgraph.nodes.clone(),
+                parent,
+                children: Vec::new(),
+            });
+            if let Some(parent_idx) = parent {
+                self.subgraphs[parent_idx].children.push(new_idx);
+            }
+            self.copy_subgraphs(source, &subgraph.children, Some(new_idx));
+        }
+    }
+
+    /// Adds `node` if it is new, recording its label either way.
+    pub fn add_node(&mut self, node: &TextNode) {
+        self.record_declaration(node);
         self.data.entry(node.name.clone()).or_default();
     }
 
+    /// A later `name[label]` replaces the earlier label and shape, as in
+    /// Mermaid, and a later `name:::class` adds to its classes. A bare
+    /// `name` keeps what was declared.
+    fn record_declaration(&mut self, node: &TextNode) {
+        if let Some(classes) = &node.style_class {
+            self.add_classes(&node.name, classes.split(":::"));
+        }
+        if let Some(label) = &node.label {
+            match node.shape {
+                NodeShape::Rectangle => self.shapes.remove(&node.name),
+                shape => self.shapes.insert(node.name.clone(), shape),
+            };
+            if let Some(previous) = self.labels.insert(node.name.clone(), label.clone()) {
+                if previous != *label {
+                    self.warnings.push(format!(
+                        "node {} relabelled from {:?} to {:?}",
+                        node.name, previous, label
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Applies `classes` to the node `name` after the ones it has.
+    fn add_classes<'a>(&mut self, name: &str, classes: impl IntoIterator<Item = &'a str>) {
+        for class in classes.into_iter().map(str::trim).filter(|class| !class.is_empty()) {
+            let applied = self.classes.entry(name.to_string()).or_default();
+            if !applied.iter().any(|existing| existing == class) {
+                applied.push(class.to_string());
+            }
+        }
+    }
+
+    /// Appends `edge` after the existing ones, adding its nodes as needed.
+    pub fn add_edge(&mut self, mut edge: TextEdge) {
+        edge.order = self.edge_count();
+        let parent = edge.parent.clone();
+        self.set_data(&parent, edge);
+    }
+
     fn set_data(&mut self, parent: &TextNode, edge: TextEdge) {
+        self.record_declaration(parent);
+        self.record_declaration(&edge.child);
+        let child = edge.child.name.clone();
         self.data.entry(parent.name.clone()).or_default().push(edge);
-        self.data.entry(edge.child.name.clone()).or_default();
+        self.data.entry(child).or_default();
     }
 
     fn set_arrow_with_label(
         &mut self,
-        lhs: &[TextNode],
-        rhs: &[TextNode],
+        lhs: &[(TextNode, Option<Port>)],
+        rhs: &[(TextNode, Option<Port>)],
         label: &str,
-    ) -> Vec<TextNode> {
+        link: &str,
+        weight: Option<u32>,
+    ) -> Vec<(TextNode, Option<Port>)> {
+        let (line_style, start_terminator, terminator) = link_kind(link);
+        // `B <-- A` is `A --> B`; `<==` is also drawn heavy.
+        let reversed = link.starts_with('<');
+        let weight = weight.or(link.starts_with("<=").then_some(HEAVY_LINK_WEIGHT));
+        let min_length = link_length(link);
         for l in lhs {
             for r in rhs {
+                let ((parent, parent_port), (child, child_port)) = if reversed { (r, l) } else { (l, r) };
                 let edge = TextEdge {
-                    parent: l.clone(),
-                    child: r.clone(),
+                    parent: parent.clone(),
+                    child: child.clone(),
                     label: label.to_string(),
+                    line_style,
+                    terminator,
+                    start_terminator,
+                    weight,
+                    order: self.edge_count(),
+                    min_length,
+                    parent_port: *parent_port,
+                    child_port: *child_port,
                 };
-                self.set_data(l, edge);
+                self.set_data(parent, edge);
             }
         }
         rhs.to_vec()
     }
 
-    fn set_arrow(&mut self, lhs: &[TextNode], rhs: &[TextNode]) -> Vec<TextNode> {
-        self.set_arrow_with_label(lhs, rhs, "")
+    /// Gives each bare `spacer` term a node of its own.
+    fn name_spacer(&mut self, node: &mut TextNode) {
+        if node.name != "spacer" || node.label.is_some() || node.style_class.is_some() {
+            return;
+        }
+        let count = self.shapes.values().filter(|shape| **shape == NodeShape::Spacer).count();
+        node.name = format!("spacer~{}", count + 1);
+        node.label = Some(String::new());
+        node.shape = NodeShape::Spacer;
     }
 
-    fn parse_line(&mut self, line: &str) -> Result<Vec<TextNode>> {
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            return Ok(Vec::new());
+    fn set_link_weight(&mut self, selector: &str, weight: u32) {
+        let orders: Option<Vec<usize>> = if selector.trim() == "default" {
+            None
+        } else {
+            Some(
+                selector
+                    .split(',')
+                    .filter_map(|idx| idx.trim().parse().ok())
+                    .collect(),
+            )
+        };
+        for edge in self.data.values_mut().flatten() {
+            if orders.as_ref().is_none_or(|o| o.contains(&edge.order)) {
+                edge.weight = Some(weight);
+            }
+        }
+    }
+
+    /// Parses a statement like `A & B --> C --> D` without recursing: the
+    /// chain is unrolled from its last link, each side split on `&`, and the
+    /// edges added in source order. `source` is the text the statement was
+    /// sliced from, for the spans.
+    fn parse_statement(&mut self, line: &str, source: &str, max_terms: usize) -> Result<Vec<TextNode>> {
+        let mut links = Vec::new();
+        let mut start = line;
+        while let Some((lhs, link)) = split_last_link(start) {
+            links.push(link);
+            if links.len() > max_terms {
+                return Err(too_many_terms(max_terms));
+            }
+            start = lhs;
+        }
+        links.reverse();
+
+        let texts: Vec<Vec<&str>> =
+            std::iter::once(group_texts(start)).chain(links.iter().map(|link| group_texts(link.rhs))).collect();
+        let mut groups: Vec<Vec<(TextNode, Option<Port>)>> =
+            texts.iter().map(|group| group.iter().copied().map(parse_port).collect()).collect();
+        for (node, _) in groups.iter_mut().flatten() {
+            self.name_spacer(node);
+        }
+        let terms = links.len() + groups.iter().map(Vec::len).sum::<usize>();
+        if terms > max_terms {
+            return Err(too_many_terms(max_terms));
+        }
+        for ((node, _), text) in groups.iter().flatten().zip(texts.iter().flatten()) {
+            if let Some(span) = span_in(source, text) {
+                self.node_spans.entry(node.name.clone()).or_insert(span);
+            }
         }
+
+        let mut groups = groups.into_iter();
+        let mut nodes = groups.next().unwrap_or_default();
+        for (idx, (link, rhs)) in links.iter().zip(groups).enumerate() {
+            let first_edge = self.edge_count();
+            nodes = self.set_arrow_with_label(&nodes, &rhs, link.label, link.link, link.weight);
+            let (from, to) = (texts[idx][0], texts[idx + 1][texts[idx + 1].len() - 1]);
+            if let (Some(from), Some(to)) = (span_in(source, from), span_in(source, to)) {
+                for order in first_edge..self.edge_count() {
+                    self.edge_spans.insert(order, from.start..to.end);
+                }
+            }
+        }
+        Ok(nodes.into_iter().map(|(node, _)| node).collect())
+    }
+
+    fn parse_line(&mut self, line: &str, source: &str, max_terms: usize) -> Result<Vec<TextNode>> {
+        let trimmed = line.trim();
         for parser in PATTERNS.iter() {
             if let Some(capture) = parser.regex.captures(trimmed) {
                 return (parser.handler)(self, capture);
             }
         }
-        Err(anyhow!("Could not parse line: {}", line))
+        self.parse_statement(trimmed, source, max_terms)
     }
 }
 
@@ -103,13 +697,26 @@ struct Pattern {
     handler: fn(&mut GraphProperties, regex::Captures) -> Result<Vec<TextNode>>,
 }
 
+/// Weight given to `<==` links, which Mermaid draws thick.
+const HEAVY_LINK_WEIGHT: u32 = 4;
+
 static EMPTY_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*$").unwrap());
-static ARROW_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(.+)\s+-->\s+(.+)$").unwrap());
-static ARROW_LABEL_REGEX: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"^(.+)\s+-->\|(.+)\|\s+(.+)$").unwrap());
+pub(crate) static LINK_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"^(-{2,}>|-\.+->|[ox]?-{2,}[ox]\b|[ox]?-\.+-[ox]\b|-{3,}|<-{2,}|<-\.+-|<={2,}|~{3,})(?:\|("[^"]*"|[^|]*)\|)?(?:\{weight=(\d+)\})?"#).unwrap()
+});
+/// `A -- yes` before a link, spaces optional; the `--` may not be part of a
+/// longer run of dashes.
+pub(crate) static INLINE_LABEL_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(.*[^\s-])\s*--\s*([^\s\[\](){}|-][^\[\](){}|]*?)$").unwrap());
+/// `A -. yes` before a `.->`, spaces optional.
+pub(crate) static DOTTED_LABEL_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(.*[^\s-])\s*-\.\s*([^\s.\[\](){}|-][^\[\](){}|]*?)$").unwrap());
+static LINK_STYLE_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^linkStyle\s+(default|[\d,\s]+?)\s+(\S.*)$").unwrap());
 static CLASS_DEF_REGEX: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"^classDef\s+(.+)\s+(.+)$").unwrap());
-static AND_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(.+)\s+&\s+(.+)$").unwrap());
+static CLASS_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^class\s+(\w[^\s,]*(?:\s*,\s*\w[^\s,]*)*)\s+(\w[\w-]*(?:\s*,\s*\w[\w-]*)*)$").unwrap());
 
 static PATTERNS: Lazy<Vec<Pattern>> = Lazy::new(|| {
     vec![
@@ -118,31 +725,15 @@ static PATTERNS: Lazy<Vec<Pattern>> = Lazy::new(|| {
             handler: |_, _| Ok(Vec::new()),
         },
         Pattern {
-            regex: &ARROW_REGEX,
-            handler: |gp, caps| {
-                let lhs = gp.parse_line(caps.get(1).unwrap().as_str()).unwrap_or_else(|_| {
-                    vec![parse_node(caps.get(1).unwrap().as_str())]
-                });
-                let rhs = gp.parse_line(caps.get(2).unwrap().as_str()).unwrap_or_else(|_| {
-                    vec![parse_node(caps.get(2).unwrap().as_str())]
-                });
-                Ok(gp.set_arrow(&lhs, &rhs))
-            },
-        },
-        Pattern {
-            regex: &ARROW_LABEL_REGEX,
+            regex: &LINK_STYLE_REGEX,
             handler: |gp, caps| {
-                let lhs = gp.parse_line(caps.get(1).unwrap().as_str()).unwrap_or_else(|_| {
-                    vec![parse_node(caps.get(1).unwrap().as_str())]
-                });
-                let rhs = gp.parse_line(caps.get(3).unwrap().as_str()).unwrap_or_else(|_| {
-                    vec![parse_node(caps.get(3).unwrap().as_str())]
-                });
-                Ok(gp.set_arrow_with_label(
-                    &lhs,
-                    &rhs,
-                    caps.get(2).unwrap().as_str(),
-                ))
+                let styles = parse_style_class("linkStyle", caps.get(2).unwrap().as_str());
+                if let Some(width) = styles.styles.get("stroke-width") {
+                    if let Ok(weight) = width.trim_end_matches("px").parse() {
+                        gp.set_link_weight(caps.get(1).unwrap().as_str(), weight);
+                    }
+                }
+                Ok(Vec::new())
             },
         },
         Pattern {
@@ -157,36 +748,302 @@ static PATTERNS: Lazy<Vec<Pattern>> = Lazy::new(|| {
             },
         },
         Pattern {
-            regex: &AND_REGEX,
+            regex: &CLASS_REGEX,
             handler: |gp, caps| {
-                let mut nodes = Vec::new();
-                let left = gp.parse_line(caps.get(1).unwrap().as_str()).unwrap_or_else(|_| {
-                    vec![parse_node(caps.get(1).unwrap().as_str())]
-                });
-                let right = gp.parse_line(caps.get(2).unwrap().as_str()).unwrap_or_else(|_| {
-                    vec![parse_node(caps.get(2).unwrap().as_str())]
-                });
-                nodes.extend(left);
-                nodes.extend(right);
-                Ok(nodes)
+                let classes: Vec<&str> = caps.get(2).unwrap().as_str().split(',').collect();
+                for name in caps.get(1).unwrap().as_str().split(',').map(str::trim) {
+                    gp.add_classes(name, classes.iter().copied());
+                }
+                Ok(Vec::new())
             },
         },
     ]
 });
 
+/// The line style of `link` and what its start and end get.
+fn link_kind(link: &str) -> (LineStyle, Terminator, Terminator) {
+    let line_style = if link.starts_with('~') {
+        LineStyle::Invisible
+    } else if link.contains('.') {
+        LineStyle::Dotted
+    } else {
+        LineStyle::Solid
+    };
+    let marker = |c: Option<char>| match c {
+        Some('o') => Terminator::Circle,
+        Some('x') => Terminator::Cross,
+        _ => Terminator::None,
+    };
+    let terminator = if link.ends_with('>') || link.starts_with('<') {
+        Terminator::Arrow
+    } else {
+        marker(link.chars().last())
+    };
+    (line_style, marker(link.chars().next()), terminator)
+}
+
+/// How many levels a link asks for: Mermaid makes `---->`, `-..->`, `<===`
+/// and `~~~~` one level longer per extra dash, dot, `=` or `~`.
+fn link_length(link: &str) -> usize {
+    let extra = if link.starts_with('~') {
+        link.len() - 2
+    } else if link.contains('.') {
+        link.matches('.').count()
+    } else if link.contains('=') {
+        link.matches('=').count() - 1
+    } else if link.ends_with(['>', 'o', 'x']) || link.starts_with('<') {
+        link.matches('-').count() - 1
+    } else {
+        link.matches('-').count() - 2
+    };
+    extra.max(1)
+}
+
 fn parse_node(line: &str) -> TextNode {
-    static NODE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(.+):::(.+)$").unwrap());
-    if let Some(caps) = NODE_REGEX.captures(line.trim()) {
-        TextNode {
-            name: caps.get(1).unwrap().as_str().trim().to_string(),
-            style_class: Some(caps.get(2).unwrap().as_str().trim().to_string()),
+    let text = line.trim();
+    // `\end` and `\subgraph` are nodes named `end` and `subgraph`.
+    let escaped = ["\\end", "\\subgraph"].iter().any(|keyword| {
+        text.strip_prefix(keyword)
+            .is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+    });
+    let text = if escaped { &text[1..] } else { text };
+    let class_at = top_level(text)
+        .map(|(idx, _)| idx)
+        .find(|idx| *idx > 0 && text[*idx..].starts_with(":::") && text.len() > idx + 3);
+    let (text, style_class) = match class_at {
+        Some(idx) => (text[..idx].trim(), Some(text[idx + 3..].trim().to_string())),
+        None => (text, None),
+    };
+    match node_shape(text) {
+        Some((name, label, shape)) => TextNode {
+            name: name.to_string(),
+            label: Some(unquote(label.trim()).to_string()),
+            style_class,
+            shape,
+        },
+        None => TextNode {
+            name: text.to_string(),
+            label: None,
+            style_class,
+            shape: NodeShape::Rectangle,
+        },
+    }
+}
+
+/// Splits `A[label]` into its name, label body and shape: rectangles, flags
+/// (`>…]`), trapezoids (`[/…\]`, `[\…/]`) and `[[…|…]]` records. A body in
+/// double quotes may hold any characters; other shapes such as `[(` and
+/// `[[…]]` without a `|` are not recognised and stay part of the name.
+fn node_shape(text: &str) -> Option<(&str, &str, NodeShape)> {
+    let open = text.find(['[', '>'])?;
+    let (name, rest) = text.split_at(open);
+    if name.is_empty() || name.contains(char::is_whitespace) || name.contains(']') {
+        return None;
+    }
+    let enclosed = |open: &str, close: &str| rest.strip_prefix(open)?.strip_suffix(close);
+    let plain = |body: &str| !body.contains(['[', ']']);
+    let quoted = |body: &str| {
+        let body = body.trim();
+        body.len() >= 2 && body.starts_with('"') && body.ends_with('"') && !body[1..body.len() - 1].contains('"')
+    };
+    let valid = |body: &str| !body.is_empty() && (quoted(body) || plain(body));
+
+    if let Some(body) = enclosed("[[", "]]").filter(|body| plain(body) && body.contains('|')) {
+        return Some((name, body, NodeShape::Record));
+    }
+    if let Some(body) = enclosed("[/", "\\]").filter(|body| valid(body)) {
+        return Some((name, body, NodeShape::Trapezoid));
+    }
+    if let Some(body) = enclosed("[\\", "/]").filter(|body| valid(body)) {
+        return Some((name, body, NodeShape::ReverseTrapezoid));
+    }
+    if let Some(body) = enclosed(">", "]").filter(|body| valid(body)) {
+        return Some((name, body, NodeShape::Flag));
+    }
+    enclosed("[", "]")
+        .filter(|body| quoted(body) || (valid(body) && !body.starts_with(['(', ')', '/', '\\'])))
+        .map(|body| (name, body, NodeShape::Rectangle))
+}
+
+/// `text` without the double quotes around it, if it has them.
+fn unquote(text: &str) -> &str {
+    text.strip_prefix('"')
+        .and_then(|inner| inner.strip_suffix('"'))
+        .unwrap_or(text)
+}
+
+/// The characters of `text` that can start syntax, with their offsets:
+/// those outside double quotes, brackets and a link's `|label|`. Labels can
+/// hold links, `&`, `|` and brackets this way without ending early.
+fn top_level(text: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut quoted = false;
+    let mut depth = 0usize;
+    let mut piped = false;
+    text.char_indices().filter(move |&(_, c)| {
+        let outside = !quoted && depth == 0 && !piped;
+        match c {
+            '"' => quoted = !quoted,
+            '[' | '(' | '{' if !quoted && !piped => depth += 1,
+            ']' | ')' | '}' if !quoted && !piped => depth = depth.saturating_sub(1),
+            '|' if !quoted && depth == 0 => piped = !piped,
+            _ => {}
+        }
+        outside
+    })
+}
+
+struct Link<'a> {
+    link: &'a str,
+    label: &'a str,
+    weight: Option<u32>,
+    rhs: &'a str,
+}
+
+/// Splits `line` at its last link. The left-hand side is as long as possible,
+/// so `A --> B --> C` splits before `C`.
+fn split_last_link(line: &str) -> Option<(&str, Link<'_>)> {
+    let starts: Vec<usize> = top_level(line)
+        .filter(|(_, c)| matches!(c, '-' | '<' | '~' | 'o' | 'x'))
+        .map(|(idx, _)| idx)
+        .collect();
+    // The `o` of `A o--o B` starts a link only after a space.
+    let marker_start = |idx: usize| {
+        line[..idx].ends_with(char::is_whitespace) && LINK_REGEX.is_match(&line[idx..])
+    };
+    for idx in starts.into_iter().rev() {
+        let lhs = line[..idx].trim_end();
+        // A link starts at the beginning of its run of dashes.
+        if lhs.is_empty() || line[..idx].ends_with(['-', '<', '~']) || (line[..idx].ends_with(['o', 'x']) && marker_start(idx - 1)) {
+            continue;
+        }
+        if line[idx..].starts_with(['o', 'x']) && !marker_start(idx) {
+            continue;
+        }
+        // `A -. yes .-> B` puts a dotted link's label between `-.` and `.->`.
+        if line[..idx].ends_with('.') && line[idx..].starts_with("->") {
+            let rhs = line[idx + 2..].trim();
+            let lhs = line[..idx - 1].trim_end();
+            let inline = inline_label(&DOTTED_LABEL_REGEX, lhs);
+            if let (Some((lhs, label)), false) = (inline, rhs.is_empty()) {
+                return Some((lhs, Link { link: "-.->", label, weight: None, rhs }));
+            }
+        }
+        let Some(caps) = LINK_REGEX.captures(&line[idx..]) else {
+            continue;
+        };
+        let rhs = line[idx + caps.get(0).unwrap().end()..].trim();
+        if rhs.is_empty() {
+            continue;
         }
+        let mut link = Link {
+            link: caps.get(1).unwrap().as_str(),
+            label: caps.get(2).map(|m| unquote(m.as_str())).unwrap_or(""),
+            // Only digits match, so a weight that does not parse is too big.
+            weight: caps.get(3).map(|m| m.as_str().parse().unwrap_or(u32::MAX)),
+            rhs,
+        };
+        // `A -- yes --> B` puts the label between the dashes.
+        let mut lhs = lhs;
+        if caps.get(2).is_none() && !link.link.contains(['.', '~']) && !link.link.starts_with('<') {
+            if let Some((node, label)) = inline_label(&INLINE_LABEL_REGEX, lhs) {
+                link.label = label;
+                lhs = node;
+            }
+        }
+        return Some((lhs, link));
+    }
+    None
+}
+
+/// Splits the text before a link into the node and a label written inside
+/// the link by `regex`, when the opening dashes are outside brackets and do
+/// not start a link of their own.
+pub(crate) fn inline_label<'a>(regex: &Regex, lhs: &'a str) -> Option<(&'a str, &'a str)> {
+    let inline = regex.captures(lhs)?;
+    let (node, label) = (inline.get(1).unwrap(), inline.get(2).unwrap());
+    let dashes = node.end() + lhs[node.end()..].find('-')?;
+    let is_link = LINK_REGEX.is_match(&lhs[dashes..]);
+    (!is_link && top_level(lhs).any(|(idx, _)| idx == dashes)).then_some((node.as_str(), label.as_str()))
+}
+
+/// The text of each `&`-separated node of one side of a link.
+fn group_texts(text: &str) -> Vec<&str> {
+    let parts: Vec<&str> = group_parts(text).into_iter().map(str::trim).filter(|part| !part.is_empty()).collect();
+    if parts.is_empty() {
+        vec![text]
     } else {
-        TextNode {
-            name: line.trim().to_string(),
-            style_class: None,
+        parts
+    }
+}
+
+/// Where `part`, a slice of `source`, sits in it.
+fn span_in(source: &str, part: &str) -> Option<Range<usize>> {
+    let start = (part.as_ptr() as usize).checked_sub(source.as_ptr() as usize)?;
+    (start + part.len() <= source.len()).then_some(start..start + part.len())
+}
+
+/// The line each span starts on, counting newlines in one pass over `input`.
+fn line_numbers(input: &str, spans: &HashMap<String, Range<usize>>) -> HashMap<String, usize> {
+    let mut starts: Vec<(usize, &String)> = spans.iter().map(|(name, span)| (span.start, name)).collect();
+    starts.sort_unstable();
+    let (mut line, mut counted) = (1, 0);
+    let mut lines = HashMap::new();
+    for (start, name) in starts {
+        line += input.as_bytes()[counted..start].iter().filter(|b| **b == b'\n').count();
+        counted = start;
+        lines.insert(name.clone(), line);
+    }
+    lines
+}
+
+/// Splits a side of a link at each `&` with space around it.
+fn group_parts(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for (idx, _) in top_level(text).filter(|(_, c)| *c == '&') {
+        let spaced = text[..idx].ends_with(char::is_whitespace) && text[idx + 1..].starts_with(char::is_whitespace);
+        if spaced {
+            parts.push(&text[start..idx]);
+            start = idx + 1;
         }
     }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Parses a node with an optional compass port after it: `A:e`,
+/// `A[label]:w`, `A:::class:n`.
+fn parse_port(text: &str) -> (TextNode, Option<Port>) {
+    let (node, port) = split_port(text.trim());
+    (parse_node(node), port)
+}
+
+fn split_port(text: &str) -> (&str, Option<Port>) {
+    let ports = [(":n", Port::North), (":s", Port::South), (":e", Port::East), (":w", Port::West)];
+    for (suffix, port) in ports {
+        if let Some(node) = text.strip_suffix(suffix) {
+            if !node.is_empty() && !node.ends_with(':') {
+                return (node, Some(port));
+            }
+        }
+    }
+    (text, None)
+}
+
+fn too_many_terms(max_terms: usize) -> anyhow::Error {
+    anyhow!(
+        "statement has more than {} nodes and links; split it up or raise the limit",
+        max_terms
+    )
+}
+
+fn stub_node(name: &str) -> TextNode {
+    TextNode {
+        name: name.to_string(),
+        label: None,
+        style_class: None,
+        shape: NodeShape::Rectangle,
+    }
 }
 
 fn parse_style_class(name: &str, styles: &str) -> StyleClass {
@@ -203,39 +1060,214 @@ fn parse_style_class(name: &str, styles: &str) -> StyleClass {
     }
 }
 
+/// Drops a trailing `%%` comment, leaving any `%%` inside double-quoted text.
+fn strip_comment(line: &str) -> &str {
+    let mut in_quotes = false;
+    for (idx, ch) in line.char_indices() {
+        match ch {
+            '"' => in_quotes = !in_quotes,
+            '%' if !in_quotes && line[idx..].starts_with("%%") => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Splits a line on the `;` statement separators Mermaid accepts, ignoring
+/// any that appear inside double-quoted text.
+fn split_statements(line: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (idx, ch) in line.char_indices() {
+        match ch {
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                statements.push(&line[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    statements.push(&line[start..]);
+    statements
+}
+
+/// `graph LR`, `flowchart TD` and the like. `TB` is `TD`; `RL` and `BT`
+/// are drawn as `LR` and `TD`, and a bare `graph` is top-down as in Mermaid.
+fn header_direction(line: &str) -> Option<GraphDirection> {
+    let mut words = line.split_whitespace();
+    if !matches!(words.next(), Some("graph" | "flowchart")) {
+        return None;
+    }
+    let direction = match words.next() {
+        Some("LR" | "RL") => GraphDirection::Lr,
+        Some("TD" | "TB" | "BT") | None => GraphDirection::Td,
+        Some(_) => return None,
+    };
+    words.next().is_none().then_some(direction)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Keyword<'a> {
+    Subgraph(&'a str),
+    End,
+}
+
+/// Reads `statement` as `subgraph <title>` or `end`. A keyword followed by a
+/// link, in brackets or in quotes is a node, and `\end` and `\subgraph`
+/// escape one that would otherwise be read as a keyword.
+pub(crate) fn structural_keyword(statement: &str) -> Option<Keyword<'_>> {
+    let statement = statement.trim();
+    if statement == "end" {
+        return Some(Keyword::End);
+    }
+    let title = statement.strip_prefix("subgraph")?;
+    let trimmed = title.trim_start();
+    let starts_link = LINK_REGEX.find(trimmed).is_some_and(|link| link.start() == 0);
+    let is_keyword = title.starts_with(char::is_whitespace) && !trimmed.is_empty() && !starts_link;
+    is_keyword.then_some(Keyword::Subgraph(trimmed))
+}
+
 pub fn mermaid_file_to_map(input: &str, style_type: &str) -> Result<GraphProperties> {
+    mermaid_file_to_map_with_limit(input, style_type, DEFAULT_MAX_STATEMENT_TERMS)
+}
+
+/// Like [`mermaid_file_to_map`], failing on any statement with more than
+/// `max_terms` nodes and links.
+pub fn mermaid_file_to_map_with_limit(
+    input: &str,
+    style_type: &str,
+    max_terms: usize,
+) -> Result<GraphProperties> {
+    parse_properties(input, style_type, max_terms).map_err(|err| tagged(ErrorCategory::Parse, err))
+}
+
+/// Splits a leading `---` … `---` block off `input`. Without a closing
+/// `---` the input is left alone.
+fn split_front_matter(input: &str) -> (Option<&str>, &str) {
+    let start = input.len() - input.trim_start().len();
+    let mut lines = input[start..].split_inclusive('\n');
+    match lines.next() {
+        Some(first) if first.trim() == "---" => {
+            let body = start + first.len();
+            let mut offset = body;
+            for line in lines {
+                if line.trim() == "---" {
+                    return (Some(&input[body..offset]), &input[offset + line.len()..]);
+                }
+                offset += line.len();
+            }
+            (None, input)
+        }
+        _ => (None, input),
+    }
+}
+
+/// The top-level `title:` of a front matter block, unquoted.
+fn front_matter_title(front_matter: &str) -> Option<String> {
+    front_matter.lines().find_map(|line| {
+        let value = unquote_scalar(line.strip_prefix("title:")?.trim());
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
+
+/// A YAML scalar without the single or double quotes around it.
+fn unquote_scalar(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+        .unwrap_or(value)
+}
+
+/// The top-level `vars:` of a front matter block, as a flow mapping
+/// (`vars: {env: prod}`) or indented `key: value` lines under it.
+fn front_matter_vars(front_matter: &str) -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+    let mut lines = front_matter.lines();
+    let Some(rest) = lines.by_ref().find_map(|line| line.strip_prefix("vars:")) else {
+        return vars;
+    };
+    let pairs: Vec<&str> = match rest.trim().strip_prefix('{').and_then(|rest| rest.strip_suffix('}')) {
+        Some(flow) => flow.split(',').collect(),
+        None => lines.take_while(|line| line.starts_with(char::is_whitespace)).collect(),
+    };
+    for pair in pairs {
+        if let Some((key, value)) = pair.split_once(':') {
+            vars.insert(unquote_scalar(key.trim()).to_string(), unquote_scalar(value.trim()).to_string());
+        }
+    }
+    vars
+}
+
+static VAR_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{\{\s*([\w.-]+)\s*\}\}").unwrap());
+
+/// Puts the front matter's `vars` into the labels, edge labels, subgraph
+/// titles and title of `properties`. Placeholders naming no variable are
+/// left as written, with a warning.
+fn substitute_vars(properties: &mut GraphProperties, vars: &HashMap<String, String>) {
+    let mut undefined = Vec::new();
+    let mut substitute = |text: &mut String| {
+        if !text.contains("{{") {
+            return;
+        }
+        let replaced = VAR_REGEX.replace_all(text, |caps: &regex::Captures| {
+            let name = caps.get(1).unwrap().as_str();
+            match vars.get(name) {
+                Some(value) => value.clone(),
+                None => {
+                    undefined.push(name.to_string());
+                    caps.get(0).unwrap().as_str().to_string()
+                }
+            }
+        });
+        *text = replaced.into_owned();
+    };
+    properties.labels.values_mut().for_each(&mut substitute);
+    properties.data.values_mut().flatten().for_each(|edge| substitute(&mut edge.label));
+    properties.subgraphs.iter_mut().for_each(|subgraph| substitute(&mut subgraph.name));
+    if let Some(title) = &mut properties.title {
+        substitute(title);
+    }
+    undefined.sort();
+    undefined.dedup();
+    for name in undefined {
+        properties.warnings.push(format!("`{{{{{}}}}}` is not one of the front matter's vars", name));
+    }
+}
+
+fn parse_properties(input: &str, style_type: &str, max_terms: usize) -> Result<GraphProperties> {
+    let mut phase = Phase::start("parse");
+    let source = input;
+    let (front_matter, input) = split_front_matter(input);
     let newline_pattern = Regex::new(r"\n|\\n").unwrap();
     let mut lines = Vec::new();
+    let mut spacers = Vec::new();
     for line in newline_pattern.split(input) {
         if line.trim() == "---" {
             break;
         }
         let trimmed = line.trim();
+        if let Some(names) = trimmed.strip_prefix("%%spacer") {
+            spacers.extend(names.split([',', ' ']).map(str::trim).filter(|name| !name.is_empty()).map(str::to_string));
+        }
         if trimmed.starts_with("%%") {
             continue;
         }
-        let mut processed = line.to_string();
-        if let Some(idx) = processed.find("%%") {
-            processed.truncate(idx);
-        }
-        let processed = processed.trim();
-        if !processed.is_empty() {
-            lines.push(processed.to_string());
+        for statement in split_statements(strip_comment(line)) {
+            let statement = statement.trim();
+            if !statement.is_empty() {
+                lines.push(statement);
+            }
         }
     }
 
-    let mut properties = GraphProperties {
-        data: IndexMap::new(),
-        style_classes: HashMap::new(),
-        graph_direction: GraphDirection::Lr,
-        style_type: style_type.to_string(),
-        padding_x: DEFAULT_PADDING,
-        padding_y: DEFAULT_PADDING,
-        subgraphs: Vec::new(),
-    };
+    let mut properties = GraphProperties::new(style_type);
+    properties.title = front_matter.and_then(front_matter_title);
 
     let padding_regex = Regex::new(r"(?i)^padding([xy])\s*=\s*(\d+)$").unwrap();
-    let mut idx = 0;
+    let idx = 0;
     while idx < lines.len() {
         let trimmed = lines[idx].trim();
         if trimmed.is_empty() {
@@ -260,20 +1292,15 @@ pub fn mermaid_file_to_map(input: &str, style_type: &str) -> Result<GraphPropert
         return Err(anyhow!("missing graph definition"));
     }
 
-    match lines[0].trim() {
-        "graph LR" | "flowchart LR" => properties.graph_direction = GraphDirection::Lr,
-        "graph TD" | "flowchart TD" => properties.graph_direction = GraphDirection::Td,
-        _ => return Err(anyhow!("first line should define the graph")),
-    }
+    properties.graph_direction =
+        header_direction(lines[0]).ok_or_else(|| anyhow!("first line should define the graph"))?;
 
-    let subgraph_regex = Regex::new(r"^\s*subgraph\s+(.+)$").unwrap();
-    let end_regex = Regex::new(r"^\s*end\s*$").unwrap();
     let mut subgraph_stack: Vec<usize> = Vec::new();
 
     for line in lines.iter().skip(1) {
-        let trimmed_line = line.trim();
-        if let Some(caps) = subgraph_regex.captures(trimmed_line) {
-            let name = caps.get(1).unwrap().as_str().trim().to_string();
+        let keyword = structural_keyword(line);
+        if let Some(Keyword::Subgraph(title)) = keyword {
+            let name = unquote(title).to_string();
             let parent = subgraph_stack.last().copied();
             let idx = properties.subgraphs.len();
             properties.subgraphs.push(TextSubgraph {
@@ -288,22 +1315,14 @@ pub fn mermaid_file_to_map(input: &str, style_type: &str) -> Result<GraphPropert
             subgraph_stack.push(idx);
             continue;
         }
-        if end_regex.is_match(trimmed_line) {
+        if keyword == Some(Keyword::End) {
             subgraph_stack.pop();
             continue;
         }
 
         let existing_nodes: HashSet<String> = properties.data.keys().cloned().collect();
-        match properties.parse_line(line) {
-            Ok(nodes) => {
-                for node in nodes {
-                    properties.add_node(&node);
-                }
-            }
-            Err(_) => {
-                let node = parse_node(line);
-                properties.add_node(&node);
-            }
+        for node in properties.parse_line(line, source, max_terms)? {
+            properties.add_node(&node);
         }
 
         if !subgraph_stack.is_empty() {
@@ -323,5 +1342,210 @@ pub fn mermaid_file_to_map(input: &str, style_type: &str) -> Result<GraphPropert
         }
     }
 
+    for name in spacers {
+        properties.data.entry(name.clone()).or_default();
+        properties.shapes.insert(name, NodeShape::Spacer);
+    }
+    let vars = front_matter.map(front_matter_vars).unwrap_or_default();
+    if !vars.is_empty() {
+        substitute_vars(&mut properties, &vars);
+    }
+    properties.node_lines = line_numbers(source, &properties.node_spans);
+
+    phase.count("lines", lines.len());
+    phase.count("nodes", properties.data.len());
+    phase.count("edges", properties.edge_count());
+    phase.count("subgraphs", properties.subgraphs.len());
+    phase.finish();
     Ok(properties)
 }
+
+/// How closely a diagram has to keep to the grammar drawn here: `Strict`
+/// rejects any [`unsupported_constructs`], `Compat` warns about them,
+/// `Skip` leaves out the statements they are in (see [`skip_unsupported`])
+/// and `Lenient` draws what it can without a word.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strictness {
+    Strict,
+    #[default]
+    Compat,
+    Skip,
+    Lenient,
+}
+
+/// Valid Mermaid that is not drawn the way Mermaid would draw it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Line of the input, counting from one.
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Statements Mermaid ignores here would otherwise turn into nodes.
+const UNSUPPORTED_KEYWORDS: [&str; 5] = ["style", "click", "direction", "accTitle", "accDescr"];
+
+static UNSUPPORTED_LINK_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(<-+>|<\.+->|<=+>|=+>|={3,}|-\.+-)(?:\s|\||$)").unwrap());
+
+/// Lists the Mermaid flowchart syntax in `input` that is read differently
+/// from Mermaid: headers drawn in another direction, statements drawn as
+/// nodes, node shapes drawn as part of the name and links not recognised.
+pub fn unsupported_constructs(input: &str) -> Vec<Diagnostic> {
+    scan_constructs(input).into_iter().map(|finding| finding.diagnostic).collect()
+}
+
+/// A [`Diagnostic`] with the statement it is about, unless that is the
+/// header, and what kind of construct it found there.
+struct Finding<'a> {
+    diagnostic: Diagnostic,
+    statement: Option<&'a str>,
+    kind: String,
+}
+
+fn scan_constructs(input: &str) -> Vec<Finding<'_>> {
+    let mut diagnostics = Vec::new();
+    let mut header_seen = false;
+    let (_, body) = split_front_matter(input);
+    let skipped = input[..input.len() - body.len()].matches('\n').count();
+    for (idx, line) in body.lines().enumerate() {
+        for statement in split_statements(strip_comment(line)) {
+            let statement = statement.trim();
+            let mut report = |kind: String, message: String| {
+                diagnostics.push(Finding {
+                    diagnostic: Diagnostic { line: skipped + idx + 1, message },
+                    statement: header_seen.then_some(statement),
+                    kind,
+                })
+            };
+            if statement.is_empty() || statement.starts_with("%%") {
+                continue;
+            }
+            if !header_seen {
+                match statement.split_whitespace().nth(1) {
+                    Some(direction @ ("RL" | "BT")) if header_direction(statement).is_some() => {
+                        let drawn = if direction == "RL" { "LR" } else { "TD" };
+                        report(format!("graph {}", direction), format!("direction {} is drawn as {}", direction, drawn));
+                    }
+                    _ => {}
+                }
+                header_seen = true;
+                continue;
+            }
+            let keyword = statement.split_whitespace().next().unwrap_or("");
+            if UNSUPPORTED_KEYWORDS.contains(&keyword) {
+                let message = format!("`{}` statements are not supported and are drawn as a node", keyword);
+                report(format!("`{}`", keyword), message);
+                continue;
+            }
+            if structural_keyword(statement).is_some() || PATTERNS.iter().any(|p| p.regex.is_match(statement)) {
+                continue;
+            }
+            diagnose_statement(statement, &mut report);
+        }
+    }
+    diagnostics
+}
+
+/// What [`skip_unsupported`] left out.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Skipped {
+    pub statements: usize,
+    /// The kinds of construct found in them, such as `` `style` `` or
+    /// `node shape`, in the order they first appear.
+    pub kinds: Vec<String>,
+}
+
+impl std::fmt::Display for Skipped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let noun = if self.statements == 1 { "statement" } else { "statements" };
+        write!(f, "skipped {} {} (kinds: {})", self.statements, noun, self.kinds.join(", "))
+    }
+}
+
+/// `input` with every statement that holds one of its
+/// [`unsupported_constructs`] blanked out, so the rest can be drawn as
+/// Mermaid would draw it. Statements are overwritten with spaces, keeping
+/// byte offsets (and so spans) the same. The header is kept, and its
+/// diagnostic returned with what was skipped.
+pub fn skip_unsupported(input: &str) -> (String, Skipped, Vec<Diagnostic>) {
+    let mut kept = input.to_string();
+    let mut skipped = Skipped::default();
+    let mut remaining = Vec::new();
+    let mut last = None;
+    for finding in scan_constructs(input) {
+        let Some(span) = finding.statement.and_then(|statement| span_in(input, statement)) else {
+            remaining.push(finding.diagnostic);
+            continue;
+        };
+        if !skipped.kinds.contains(&finding.kind) {
+            skipped.kinds.push(finding.kind);
+        }
+        if last.replace(span.clone()) != Some(span.clone()) {
+            skipped.statements += 1;
+            kept.replace_range(span.clone(), &" ".repeat(span.len()));
+        }
+    }
+    (kept, skipped, remaining)
+}
+
+/// The [`unsupported_constructs`] of `input` to warn about under
+/// `strictness`. `Strict` fails on any of them with a parse error that lists
+/// them all.
+pub fn check_grammar(input: &str, strictness: Strictness) -> Result<Vec<Diagnostic>> {
+    if strictness == Strictness::Lenient {
+        return Ok(Vec::new());
+    }
+    if strictness == Strictness::Skip {
+        return Ok(skip_unsupported(input).2);
+    }
+    let diagnostics = unsupported_constructs(input);
+    if strictness == Strictness::Strict && !diagnostics.is_empty() {
+        let listed: Vec<String> = diagnostics.iter().map(Diagnostic::to_string).collect();
+        return Err(tagged(
+            ErrorCategory::Parse,
+            anyhow!("input is outside the supported grammar: {}", listed.join("; ")),
+        ));
+    }
+    Ok(diagnostics)
+}
+
+fn diagnose_statement(statement: &str, report: &mut impl FnMut(String, String)) {
+    for (idx, _) in top_level(statement) {
+        let rest = &statement[idx..];
+        let starts_run = !statement[..idx].ends_with(['-', '<', '=', '.', '~']);
+        if !starts_run {
+            continue;
+        }
+        if let Some(caps) = UNSUPPORTED_LINK_REGEX.captures(rest) {
+            let link = caps.get(1).unwrap().as_str();
+            report(format!("`{}` link", link), format!("link `{}` is not supported", link));
+        }
+    }
+
+    let mut sides = Vec::new();
+    let mut start = statement;
+    while let Some((lhs, link)) = split_last_link(start) {
+        sides.push(link.rhs);
+        start = lhs;
+    }
+    sides.push(start);
+    for side in sides.into_iter().rev() {
+        for part in group_parts(side) {
+            let (text, _) = split_port(part.trim());
+            let text = match top_level(text).map(|(idx, _)| idx).find(|idx| text[*idx..].starts_with(":::")) {
+                Some(idx) => &text[..idx],
+                None => text,
+            };
+            if text.contains(['[', '(', '{']) && node_shape(text).is_none() {
+                let message = format!("node shape in `{}` is not supported and is drawn as part of the name", text);
+                report("node shape".to_string(), message);
+            }
+        }
+    }
+}
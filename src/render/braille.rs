@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use crate::render::geom::DrawingCoord;
+
+/// Sparse braille dot canvas: pixel `(x, y)` lives in drawing cell `(x / 2, y / 4)`.
+#[derive(Clone, Debug, Default)]
+pub struct BrailleCanvas {
+    dots: HashMap<(i32, i32), u8>,
+}
+
+impl BrailleCanvas {
+    pub fn new() -> BrailleCanvas {
+        BrailleCanvas::default()
+    }
+
+    pub fn cell_center(coord: DrawingCoord) -> (i32, i32) {
+        (coord.x * 2, coord.y * 4 + 1)
+    }
+
+    pub fn set_pixel(&mut self, x: i32, y: i32) {
+        if x < 0 || y < 0 {
+            return;
+        }
+        let bit = match (x % 2, y % 4) {
+            (0, 0) => 0x01,
+            (0, 1) => 0x02,
+            (0, 2) => 0x04,
+            (1, 0) => 0x08,
+            (1, 1) => 0x10,
+            (1, 2) => 0x20,
+            (0, _) => 0x40,
+            _ => 0x80,
+        };
+        *self.dots.entry((x / 2, y / 4)).or_insert(0) |= bit;
+    }
+
+    pub fn line(&mut self, from: (i32, i32), to: (i32, i32)) {
+        let (mut x, mut y) = from;
+        let dx = (to.0 - x).abs();
+        let dy = -(to.1 - y).abs();
+        let step_x = if x < to.0 { 1 } else { -1 };
+        let step_y = if y < to.1 { 1 } else { -1 };
+        let mut err = dx + dy;
+        loop {
+            self.set_pixel(x, y);
+            if (x, y) == to {
+                break;
+            }
+            let doubled = 2 * err;
+            if doubled >= dy {
+                err += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                err += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = (DrawingCoord, String)> + '_ {
+        self.dots.iter().map(|((x, y), bits)| {
+            let ch = char::from_u32(0x2800 + u32::from(*bits)).unwrap_or(' ');
+            (DrawingCoord { x: *x, y: *y }, ch.to_string())
+        })
+    }
+}
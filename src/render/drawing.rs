@@ -1,25 +1,152 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+use unicode_bidi::BidiInfo;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 use crate::render::geom::{determine_direction, DrawingCoord, Direction, GenericCoord};
 
+/// Line thickness used for edge segments and their corners.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Stroke {
+    Thin,
+    #[default]
+    Normal,
+    Heavy,
+    Double,
+}
+
+impl Stroke {
+    /// Buckets an edge weight: 1 is thin, 2-3 normal, 4-6 heavy, 7+ double.
+    pub fn from_weight(weight: u32) -> Stroke {
+        match weight {
+            0..=1 => Stroke::Thin,
+            2..=3 => Stroke::Normal,
+            4..=6 => Stroke::Heavy,
+            _ => Stroke::Double,
+        }
+    }
+
+    pub fn horizontal(self, use_ascii: bool) -> &'static str {
+        match (self, use_ascii) {
+            (Stroke::Heavy | Stroke::Double, true) => "=",
+            (_, true) => "-",
+            (Stroke::Thin, false) => "╌",
+            (Stroke::Normal, false) => "─",
+            (Stroke::Heavy, false) => "━",
+            (Stroke::Double, false) => "═",
+        }
+    }
+
+    pub fn vertical(self, use_ascii: bool) -> &'static str {
+        match (self, use_ascii) {
+            (_, true) => "|",
+            (Stroke::Thin, false) => "╎",
+            (Stroke::Normal, false) => "│",
+            (Stroke::Heavy, false) => "┃",
+            (Stroke::Double, false) => "║",
+        }
+    }
+
+    /// Maps a light box-drawing corner onto this stroke's equivalent.
+    pub fn corner(self, light: &'static str) -> &'static str {
+        match (self, light) {
+            (Stroke::Heavy, "┌") => "┏",
+            (Stroke::Heavy, "┐") => "┓",
+            (Stroke::Heavy, "└") => "┗",
+            (Stroke::Heavy, "┘") => "┛",
+            (Stroke::Double, "┌") => "╔",
+            (Stroke::Double, "┐") => "╗",
+            (Stroke::Double, "└") => "╚",
+            (Stroke::Double, "┘") => "╝",
+            _ => light,
+        }
+    }
+}
+
+/// A foreground color: one of the 16 terminal colors, which the terminal's
+/// own palette decides the look of, or an exact 24-bit one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    Ansi(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// Text attributes for a cell, written out as SGR codes by
+/// [`Drawing::to_ansi`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextStyle {
+    pub bold: bool,
+    pub italic: bool,
+    /// Swapped foreground and background, for `--highlight`.
+    pub inverse: bool,
+    /// Faint, for nodes `--reachable-from` cannot reach.
+    pub dim: bool,
+    /// From a `classDef` `color` or `stroke`.
+    pub foreground: Option<Color>,
+}
+
+impl TextStyle {
+    fn sgr(self) -> String {
+        let mut codes = Vec::new();
+        if self.bold {
+            codes.push("1".to_string());
+        }
+        if self.dim {
+            codes.push("2".to_string());
+        }
+        if self.italic {
+            codes.push("3".to_string());
+        }
+        if self.inverse {
+            codes.push("7".to_string());
+        }
+        match self.foreground {
+            Some(Color::Ansi(idx)) if idx < 8 => codes.push((30 + idx).to_string()),
+            Some(Color::Ansi(idx)) => codes.push((90 + idx % 8).to_string()),
+            Some(Color::Rgb(r, g, b)) => codes.push(format!("38;2;{};{};{}", r, g, b)),
+            None => {}
+        }
+        if codes.is_empty() {
+            "\x1b[0m".to_string()
+        } else {
+            format!("\x1b[0;{}m", codes.join(";"))
+        }
+    }
+}
+
+/// A character canvas stored column-major (`cells[x][y]`), one string per
+/// cell. Wide characters occupy their cell and leave `""` in the cells they
+/// cover. Negative coordinates are clamped to 0, and writing past the edge
+/// grows the canvas with spaces. Cells can also carry a [`TextStyle`], which
+/// only [`Drawing::to_ansi`] shows.
 #[derive(Clone, Debug)]
 pub struct Drawing {
     cells: Vec<Vec<String>>,
+    styles: HashMap<(usize, usize), TextStyle>,
 }
 
 impl Drawing {
+    /// A blank canvas whose largest indexes are `width` and `height`, i.e.
+    /// `(width + 1) × (height + 1)` cells.
     pub fn new(width: usize, height: usize) -> Drawing {
         let mut cells = Vec::with_capacity(width + 1);
         for _ in 0..=width {
             cells.push(vec![" ".to_string(); height + 1]);
         }
-        Drawing { cells }
+        Drawing {
+            cells,
+            styles: HashMap::new(),
+        }
     }
 
     pub fn empty() -> Drawing {
         Drawing::new(0, 0)
     }
 
+    /// The largest valid `(x, y)`, matching the arguments of [`Drawing::new`].
     pub fn size(&self) -> (usize, usize) {
         let max_x = self.cells.len().saturating_sub(1);
         let max_y = if self.cells.is_empty() {
@@ -50,6 +177,7 @@ impl Drawing {
         }
     }
 
+    /// Panics if `coord` is outside the canvas.
     pub fn get(&self, coord: DrawingCoord) -> &str {
         let x = coord.x.max(0) as usize;
         let y = coord.y.max(0) as usize;
@@ -72,48 +200,143 @@ impl Drawing {
         Drawing::new(max_x, max_y)
     }
 
+    /// Writes `text` from `start`, one grapheme cluster per cell so accents,
+    /// flags and ZWJ emoji stay whole. A double-width cluster fills its own
+    /// cell and leaves the next one empty, so rows keep their alignment.
     pub fn draw_text(&mut self, start: DrawingCoord, text: &str) {
-        let mut x = start.x;
-        let y = start.y;
-        self.ensure_size((start.x + text.len() as i32) as usize, y as usize);
-        for ch in text.chars() {
-            self.cells[x as usize][y as usize] = ch.to_string();
-            x += 1;
+        let y = start.y.max(0) as usize;
+        self.ensure_size(start.x.max(0) as usize + text_width(text), y);
+        let mut x = start.x.max(0) as usize;
+        for grapheme in text.graphemes(true) {
+            let width = grapheme_width(grapheme);
+            if width == 0 {
+                if x > start.x.max(0) as usize {
+                    self.cells[x - 1][y].push_str(grapheme);
+                }
+                continue;
+            }
+            self.cells[x][y] = grapheme.to_string();
+            for covered in 1..width {
+                self.cells[x + covered][y] = String::new();
+            }
+            x += width;
         }
     }
 
-    pub fn draw_text_on_line(&mut self, line: &[DrawingCoord], label: &str) {
-        if line.len() < 2 || label.is_empty() {
-            return;
+    /// Gives the cells `text` would cover from `start` the attributes in
+    /// `style`, without changing what they hold.
+    pub fn style_text(&mut self, start: DrawingCoord, text: &str, style: TextStyle) {
+        let (x, y) = (start.x.max(0) as usize, start.y.max(0) as usize);
+        for x in x..x + text_width(text) {
+            if style == TextStyle::default() {
+                self.styles.remove(&(x, y));
+            } else {
+                self.styles.insert((x, y), style);
+            }
         }
-        let first = line[0];
-        let last = line[line.len() - 1];
-        let (min_x, max_x) = if first.x > last.x {
-            (last.x, first.x)
-        } else {
-            (first.x, last.x)
-        };
-        let (min_y, max_y) = if first.y > last.y {
-            (last.y, first.y)
-        } else {
-            (first.y, last.y)
-        };
-        let middle_x = min_x + (max_x - min_x) / 2;
-        let middle_y = min_y + (max_y - min_y) / 2;
-        let start_x = middle_x - (label.len() as i32) / 2;
-        let start = DrawingCoord {
-            x: start_x,
-            y: middle_y,
-        };
+    }
+
+    pub fn style(&self, coord: DrawingCoord) -> TextStyle {
+        let key = (coord.x.max(0) as usize, coord.y.max(0) as usize);
+        self.styles.get(&key).copied().unwrap_or_default()
+    }
+
+    /// Like the `Display` output, with SGR codes around styled runs. Each row
+    /// ends with its attributes reset.
+    pub fn to_ansi(&self) -> String {
+        let (max_x, max_y) = self.size();
+        (0..=max_y).map(|y| self.ansi_row(y, max_x)).collect::<Vec<_>>().join("\n")
+    }
+
+    /// Writes the drawing one row at a time, as [`fmt::Display`] or, with
+    /// `ansi`, as [`Drawing::to_ansi`] would give it.
+    pub fn write_to<W: io::Write>(&self, mut writer: W, ansi: bool) -> io::Result<()> {
+        let (max_x, max_y) = self.size();
+        for y in 0..=max_y {
+            let row = if ansi {
+                self.ansi_row(y, max_x)
+            } else {
+                (0..=max_x).map(|x| self.cells[x][y].as_str()).collect()
+            };
+            writer.write_all(row.as_bytes())?;
+            if y != max_y {
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn ansi_row(&self, y: usize, max_x: usize) -> String {
+        let mut output = String::new();
+        let mut current = TextStyle::default();
+        for x in 0..=max_x {
+            let style = self.styles.get(&(x, y)).copied().unwrap_or_default();
+            if style != current && !self.cells[x][y].is_empty() {
+                output.push_str(&style.sgr());
+                current = style;
+            }
+            output.push_str(&self.cells[x][y]);
+        }
+        if current != TextStyle::default() {
+            output.push_str(&TextStyle::default().sgr());
+        }
+        output
+    }
+
+    /// Centers `label` on `line` and returns the coordinate of its first
+    /// character.
+    pub fn draw_text_on_line(&mut self, line: &[DrawingCoord], label: &str) -> Option<DrawingCoord> {
+        if label.is_empty() {
+            return None;
+        }
+        let start = text_start_on_line(line, text_width(label))?;
         self.draw_text(start, label);
+        Some(start)
+    }
+
+    /// Blanks the given cells where they hold a straight line segment.
+    pub fn clear_lines_at(&mut self, coords: &[DrawingCoord]) {
+        let (max_x, max_y) = self.size();
+        for coord in coords {
+            let inside = (0..=max_x as i32).contains(&coord.x) && (0..=max_y as i32).contains(&coord.y);
+            if inside && is_line_char(self.get(*coord)) {
+                self.set(*coord, " ");
+            }
+        }
     }
 
+    /// Draws a straight line from `from` to `to` and returns the cells it
+    /// covers, in order. The ends are moved along the line's direction by
+    /// `offset_from` and `offset_to` first (the router passes `1, -1` to
+    /// leave both endpoints free). When that moves the start past the end,
+    /// nothing is drawn. Both ends are inclusive; a diagonal that is not at
+    /// 45° runs diagonally until one axis is done, then straight.
     pub fn draw_line(
         &mut self,
         from: DrawingCoord,
         to: DrawingCoord,
         offset_from: i32,
         offset_to: i32,
+        stroke: Stroke,
+        use_ascii: bool,
+    ) -> Vec<DrawingCoord> {
+        self.draw_dashed_line(from, to, offset_from, offset_to, &[], stroke, use_ascii)
+    }
+
+    /// Like [`Drawing::draw_line`], but only paints the cells that fall on a
+    /// dash. `dashes` alternates on/off run lengths the way SVG's
+    /// `stroke-dasharray` does (`[2, 1]` is two cells drawn, one skipped); an
+    /// empty pattern draws a solid line. Skipped cells are left untouched and
+    /// every cell of the line is still returned.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_dashed_line(
+        &mut self,
+        from: DrawingCoord,
+        to: DrawingCoord,
+        offset_from: i32,
+        offset_to: i32,
+        dashes: &[usize],
+        stroke: Stroke,
         use_ascii: bool,
     ) -> Vec<DrawingCoord> {
         let mut drawn = Vec::new();
@@ -168,15 +391,19 @@ impl Drawing {
             Direction::Middle => {}
         }
 
-        let step_x = to_sign(end.x - start.x);
-        let step_y = to_sign(end.y - start.y);
+        let (dir_x, dir_y) = (to_sign(to.x - from.x), to_sign(to.y - from.y));
+        let along = if dir_x == 0 && dir_y == 0 {
+            // `from == to`; determine_direction calls that Up.
+            start.y - end.y
+        } else {
+            (end.x - start.x) * dir_x + (end.y - start.y) * dir_y
+        };
+        if along < 0 {
+            return drawn;
+        }
         let ch = match dir {
-            Direction::Up | Direction::Down => {
-                if use_ascii { "|" } else { "│" }
-            }
-            Direction::Left | Direction::Right => {
-                if use_ascii { "-" } else { "─" }
-            }
+            Direction::Up | Direction::Down => stroke.vertical(use_ascii),
+            Direction::Left | Direction::Right => stroke.horizontal(use_ascii),
             Direction::UpperLeft | Direction::LowerRight => {
                 if use_ascii { "\\" } else { "╲" }
             }
@@ -186,22 +413,77 @@ impl Drawing {
             Direction::Middle => " ",
         };
 
+        let period: usize = dashes.iter().sum();
         let mut current = start;
         loop {
-            self.set(current, ch.to_string());
+            if period == 0 || is_dash_on(dashes, drawn.len() % period) {
+                self.set(current, ch.to_string());
+            }
             drawn.push(current);
             if current == end {
                 break;
             }
             current = DrawingCoord {
-                x: current.x + step_x,
-                y: current.y + step_y,
+                x: current.x + to_sign(end.x - current.x),
+                y: current.y + to_sign(end.y - current.y),
             };
         }
 
         drawn
     }
 
+    /// Replaces box-drawing glyphs with ASCII: `.` and `'` for top and
+    /// bottom corners, `+` where lines meet, and `-`, `|`, `=` for lines.
+    pub fn transliterate_to_ascii(&mut self) {
+        for cell in self.cells.iter_mut().flatten() {
+            let ascii = match cell.as_str() {
+                "─" | "╌" | "╴" | "╶" => "-",
+                "━" | "═" => "=",
+                "│" | "╎" | "┃" | "║" | "╵" | "╷" => "|",
+                "┌" | "┐" | "┏" | "┓" | "╔" | "╗" => ".",
+                "└" | "┘" | "┗" | "┛" | "╚" | "╝" => "'",
+                "├" | "┤" | "┬" | "┴" | "┼" | "┣" | "┫" | "┳" | "┻" | "╋" | "╠" | "╣" | "╦" | "╩" | "╬" => "+",
+                "╲" => "\\",
+                "╱" => "/",
+                "▲" => "^",
+                "▼" => "v",
+                "◄" => "<",
+                "►" => ">",
+                "◥" | "◤" | "◢" | "◣" | "●" => "*",
+                _ => continue,
+            };
+            *cell = ascii.to_string();
+        }
+    }
+
+    /// Adds line arms (see [`arm_towards`]) to the light box-drawing glyph at
+    /// `coord`, e.g. turning a border `│` into `├`. Other cells are left alone.
+    #[cfg_attr(not(feature = "flowchart"), allow(dead_code))]
+    pub(crate) fn add_arms(&mut self, coord: DrawingCoord, arms: usize) {
+        if let Some(existing) = junction_arms(self.get(coord)) {
+            self.set(coord, GLYPH_BY_ARMS[existing | arms]);
+        }
+    }
+
+    /// Copies every cell of `other`, spaces included, over what is there.
+    pub fn paste(&mut self, other: &Drawing, offset: DrawingCoord) {
+        let start_x = offset.x.max(0) as usize;
+        let start_y = offset.y.max(0) as usize;
+        let (other_max_x, other_max_y) = other.size();
+        self.ensure_size(start_x + other_max_x, start_y + other_max_y);
+        for x in 0..=other_max_x {
+            for y in 0..=other_max_y {
+                self.cells[start_x + x][start_y + y] = other.cells[x][y].clone();
+                self.styles.remove(&(start_x + x, start_y + y));
+            }
+        }
+        for ((x, y), style) in &other.styles {
+            self.styles.insert((start_x + x, start_y + y), *style);
+        }
+    }
+
+    /// Copies the non-space cells of `other`, merging crossing box-drawing
+    /// lines into junctions unless `use_ascii` is set.
     pub fn overlay(&mut self, other: &Drawing, offset: DrawingCoord, use_ascii: bool) {
         let start_x = offset.x.max(0) as usize;
         let start_y = offset.y.max(0) as usize;
@@ -224,22 +506,26 @@ impl Drawing {
                 } else {
                     self.set(target_coord, value.clone());
                 }
+                if let Some(style) = other.styles.get(&(x, y)) {
+                    self.styles.insert((start_x + x, start_y + y), *style);
+                }
             }
         }
     }
+}
 
-    pub fn to_string(&self) -> String {
+impl fmt::Display for Drawing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let (max_x, max_y) = self.size();
-        let mut builder = String::new();
         for y in 0..=max_y {
             for x in 0..=max_x {
-                builder.push_str(&self.cells[x][y]);
+                f.write_str(&self.cells[x][y])?;
             }
             if y != max_y {
-                builder.push('\n');
+                f.write_str("\n")?;
             }
         }
-        builder
+        Ok(())
     }
 }
 
@@ -247,164 +533,118 @@ const JUNCTION_CHARS: [&str; 15] = [
     "─", "│", "┌", "┐", "└", "┘", "├", "┤", "┬", "┴", "┼", "╴", "╵", "╶", "╷",
 ];
 
+/// Reorders right-to-left runs (Hebrew, Arabic) into display order, since
+/// the drawing is laid out one cell at a time from left to right. Text with
+/// no RTL characters is returned unchanged.
+#[cfg_attr(not(feature = "flowchart"), allow(dead_code))]
+pub(crate) fn visual_order(text: &str) -> String {
+    let info = BidiInfo::new(text, None);
+    if !info.has_rtl() {
+        return text.to_string();
+    }
+    info.paragraphs
+        .iter()
+        .map(|paragraph| info.reorder_line(paragraph, paragraph.range.clone()))
+        .collect()
+}
+
+/// Where text `width` cells wide starts when centered on `line`.
+pub(crate) fn text_start_on_line(line: &[DrawingCoord], width: usize) -> Option<DrawingCoord> {
+    if line.len() < 2 {
+        return None;
+    }
+    let first = line[0];
+    let last = line[line.len() - 1];
+    let (min_x, max_x) = if first.x > last.x {
+        (last.x, first.x)
+    } else {
+        (first.x, last.x)
+    };
+    let (min_y, max_y) = if first.y > last.y {
+        (last.y, first.y)
+    } else {
+        (first.y, last.y)
+    };
+    let middle_x = min_x + (max_x - min_x) / 2;
+    let middle_y = min_y + (max_y - min_y) / 2;
+    let start = DrawingCoord {
+        x: middle_x - width as i32 / 2,
+        y: middle_y,
+    };
+    Some(start)
+}
+
+/// Terminal columns `text` takes up, counted the way [`Drawing::draw_text`]
+/// lays it out.
+pub(crate) fn text_width(text: &str) -> usize {
+    text.graphemes(true).map(grapheme_width).sum()
+}
+
+/// Columns and rows a label takes up, one row per `\n`-separated line.
+#[cfg_attr(not(feature = "flowchart"), allow(dead_code))]
+pub(crate) fn label_size(text: &str) -> (usize, usize) {
+    let width = text.split('\n').map(text_width).max().unwrap_or(0);
+    (width, text.split('\n').count())
+}
+
+fn grapheme_width(grapheme: &str) -> usize {
+    UnicodeWidthStr::width(grapheme)
+}
+
+fn is_line_char(c: &str) -> bool {
+    matches!(
+        c,
+        "─" | "│" | "╌" | "╎" | "━" | "┃" | "═" | "║" | "-" | "|" | "="
+    ) || c.chars().next().is_some_and(|ch| ('\u{2801}'..='\u{28ff}').contains(&ch))
+}
+
 fn is_junction_char(c: &str) -> bool {
     JUNCTION_CHARS.iter().any(|jc| jc == &c)
 }
 
+/// Light junction glyphs indexed by their arms: bit 0 up, 1 down, 2 left,
+/// 3 right.
+const GLYPH_BY_ARMS: [&str; 16] = [
+    " ", "╵", "╷", "│", "╴", "┘", "┐", "┤", "╶", "└", "┌", "├", "─", "┴", "┬", "┼",
+];
+
+/// The arm bit for a line leaving a cell towards `dir`; diagonals have none.
+#[cfg_attr(not(feature = "flowchart"), allow(dead_code))]
+pub(crate) fn arm_towards(dir: Direction) -> usize {
+    match dir {
+        Direction::Up => 1,
+        Direction::Down => 2,
+        Direction::Left => 4,
+        Direction::Right => 8,
+        _ => 0,
+    }
+}
+
+fn junction_arms(glyph: &str) -> Option<usize> {
+    GLYPH_BY_ARMS
+        .iter()
+        .skip(1)
+        .position(|g| *g == glyph)
+        .map(|idx| idx + 1)
+}
+
+/// Combines two junction glyphs into the one with both their arms, so a
+/// line crossing a corner becomes a tee and two tees a cross.
 fn merge_junctions(current: &str, new_char: &str) -> String {
-    let mut map: HashMap<&str, HashMap<&str, &str>> = HashMap::new();
-    let mut insert = |base: &str, pairs: &[(&str, &str)]| {
-        let entry = map.entry(base).or_insert_with(HashMap::new);
-        for (with, result) in pairs {
-            entry.insert(*with, *result);
-        }
-    };
+    match (junction_arms(current), junction_arms(new_char)) {
+        (Some(a), Some(b)) => GLYPH_BY_ARMS[a | b].to_string(),
+        _ => current.to_string(),
+    }
+}
 
-    insert(
-        "─",
-        &[
-            ("│", "┼"),
-            ("┌", "┬"),
-            ("┐", "┬"),
-            ("└", "┴"),
-            ("┘", "┴"),
-            ("├", "┼"),
-            ("┤", "┼"),
-            ("┬", "┬"),
-            ("┴", "┴"),
-        ],
-    );
-    insert(
-        "│",
-        &[
-            ("─", "┼"),
-            ("┌", "├"),
-            ("┐", "┤"),
-            ("└", "├"),
-            ("┘", "┤"),
-            ("├", "├"),
-            ("┤", "┤"),
-            ("┬", "┼"),
-            ("┴", "┼"),
-        ],
-    );
-    insert(
-        "┌",
-        &[
-            ("─", "┬"),
-            ("│", "├"),
-            ("┐", "┬"),
-            ("└", "├"),
-            ("┘", "┼"),
-            ("├", "├"),
-            ("┤", "┼"),
-            ("┬", "┬"),
-            ("┴", "┼"),
-        ],
-    );
-    insert(
-        "┐",
-        &[
-            ("─", "┬"),
-            ("│", "┤"),
-            ("┌", "┬"),
-            ("└", "┼"),
-            ("┘", "┤"),
-            ("├", "┼"),
-            ("┤", "┤"),
-            ("┬", "┬"),
-            ("┴", "┼"),
-        ],
-    );
-    insert(
-        "└",
-        &[
-            ("─", "┴"),
-            ("│", "├"),
-            ("┌", "├"),
-            ("┐", "┼"),
-            ("┘", "┴"),
-            ("├", "├"),
-            ("┤", "┼"),
-            ("┬", "┼"),
-            ("┴", "┴"),
-        ],
-    );
-    insert(
-        "┘",
-        &[
-            ("─", "┴"),
-            ("│", "┤"),
-            ("┌", "┼"),
-            ("┐", "┤"),
-            ("└", "┴"),
-            ("├", "┼"),
-            ("┤", "┤"),
-            ("┬", "┼"),
-            ("┴", "┴"),
-        ],
-    );
-    insert(
-        "├",
-        &[
-            ("─", "┼"),
-            ("│", "├"),
-            ("┌", "├"),
-            ("┐", "┼"),
-            ("└", "├"),
-            ("┘", "┼"),
-            ("┤", "┼"),
-            ("┬", "┼"),
-            ("┴", "┼"),
-        ],
-    );
-    insert(
-        "┤",
-        &[
-            ("─", "┼"),
-            ("│", "┤"),
-            ("┌", "┼"),
-            ("┐", "┤"),
-            ("└", "┼"),
-            ("┘", "┤"),
-            ("├", "┼"),
-            ("┬", "┼"),
-            ("┴", "┼"),
-        ],
-    );
-    insert(
-        "┬",
-        &[
-            ("─", "┬"),
-            ("│", "┼"),
-            ("┌", "┬"),
-            ("┐", "┬"),
-            ("└", "┼"),
-            ("┘", "┼"),
-            ("├", "┼"),
-            ("┤", "┼"),
-            ("┴", "┼"),
-        ],
-    );
-    insert(
-        "┴",
-        &[
-            ("─", "┴"),
-            ("│", "┼"),
-            ("┌", "┼"),
-            ("┐", "┼"),
-            ("└", "┴"),
-            ("┘", "┴"),
-            ("├", "┼"),
-            ("┤", "┼"),
-            ("┬", "┼"),
-        ],
-    );
-
-    map.get(current)
-        .and_then(|inner| inner.get(new_char))
-        .map(|s| s.to_string())
-        .unwrap_or_else(|| current.to_string())
+fn is_dash_on(dashes: &[usize], mut position: usize) -> bool {
+    for (idx, run) in dashes.iter().enumerate() {
+        if position < *run {
+            return idx % 2 == 0;
+        }
+        position -= run;
+    }
+    true
 }
 
 fn to_sign(value: i32) -> i32 {
@@ -7,12 +7,15 @@ pub struct GenericCoord {
     pub y: i32,
 }
 
+/// A cell of the layout grid. A node at `(x, y)` covers `x..=x + 2` by
+/// `y..=y + 2`; the outer ring is its border and `(x + 1, y + 1)` its label.
 #[derive(Clone, Copy)]
 pub struct GridCoord {
     pub x: i32,
     pub y: i32,
 }
 
+/// A character position in a [`Drawing`](super::Drawing).
 #[derive(Clone, Copy)]
 pub struct DrawingCoord {
     pub x: i32,
@@ -60,6 +63,13 @@ impl PartialEq for DrawingCoord {
 
 impl Eq for DrawingCoord {}
 
+impl Hash for DrawingCoord {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.x.hash(state);
+        self.y.hash(state);
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Direction {
     Up,
@@ -90,6 +100,7 @@ impl Direction {
 }
 
 impl GridCoord {
+    /// The border cell on the `dir` side of a node whose top-left is `self`.
     pub fn direction(self, dir: Direction) -> GridCoord {
         match dir {
             Direction::Up => GridCoord {
@@ -129,46 +140,6 @@ impl GridCoord {
     }
 }
 
-impl DrawingCoord {
-    pub fn direction(self, dir: Direction) -> DrawingCoord {
-        match dir {
-            Direction::Up => DrawingCoord {
-                x: self.x + 1,
-                y: self.y,
-            },
-            Direction::Down => DrawingCoord {
-                x: self.x + 1,
-                y: self.y + 2,
-            },
-            Direction::Left => DrawingCoord {
-                x: self.x,
-                y: self.y + 1,
-            },
-            Direction::Right => DrawingCoord {
-                x: self.x + 2,
-                y: self.y + 1,
-            },
-            Direction::UpperRight => DrawingCoord {
-                x: self.x + 2,
-                y: self.y,
-            },
-            Direction::UpperLeft => DrawingCoord {
-                x: self.x,
-                y: self.y,
-            },
-            Direction::LowerRight => DrawingCoord {
-                x: self.x + 2,
-                y: self.y + 2,
-            },
-            Direction::LowerLeft => DrawingCoord {
-                x: self.x,
-                y: self.y + 2,
-            },
-            Direction::Middle => self,
-        }
-    }
-}
-
 pub fn determine_direction(from: GenericCoord, to: GenericCoord) -> Direction {
     if from.x == to.x {
         if from.y < to.y {
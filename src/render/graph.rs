@@ -1,20 +1,41 @@
 use std::cmp::max;
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
 use std::cmp::Ordering;
+use std::io;
+use std::ops::Range;
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
-
-use crate::parser::{GraphDirection, GraphProperties, StyleClass};
-use crate::render::drawing::Drawing;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::error::{tagged, ErrorCategory};
+use crate::parser::{GraphDirection, GraphProperties, LineStyle, NodeShape, Port, StyleClass, Terminator};
+use crate::render::braille::BrailleCanvas;
+use crate::render::drawing::{
+    arm_towards, label_size, text_start_on_line, text_width, visual_order, Color, Drawing, Stroke, TextStyle,
+};
+use crate::render::hooks::{LayoutHooks, NoHooks};
+use crate::render::options::{AsciiStyle, AutoStyle, RenderOptions, Sides};
 use crate::render::geom::{
     determine_direction, Direction, DrawingCoord, GenericCoord, GridCoord,
 };
+use crate::trace::Phase;
+
+fn port_side(port: Port) -> Direction {
+    match port {
+        Port::North => Direction::Up,
+        Port::South => Direction::Down,
+        Port::East => Direction::Right,
+        Port::West => Direction::Left,
+    }
+}
 
-#[derive(Clone, Debug)]
-pub struct RenderOptions {
-    pub border_padding: i32,
-    pub use_ascii: bool,
-    pub show_coords: bool,
+/// Edge labels break onto a new line at each `<br>`, as in Mermaid.
+fn line_breaks(label: &str) -> String {
+    static BREAK_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)<br\s*/?>").unwrap());
+    BREAK_REGEX.replace_all(label, "\n").into_owned()
 }
 
 pub fn render_properties(
@@ -23,33 +44,54 @@ pub fn render_properties(
 ) -> Result<String> {
     let mut graph = Graph::new(properties, options.clone());
     graph.layout()?;
-    let mut drawing = graph.draw();
-    if options.show_coords {
-        drawing = graph.with_coords_overlay(drawing);
-    }
-    Ok(drawing.to_string())
+    Ok(graph.render())
+}
+
+/// Like [`render_properties`] but writes the rendering to `writer` row by
+/// row instead of collecting it into a `String`.
+pub fn render_to<W: io::Write>(properties: &GraphProperties, options: &RenderOptions, writer: W) -> Result<()> {
+    let mut graph = Graph::new(properties, options.clone());
+    graph.layout()?;
+    graph.render_to(writer)?;
+    Ok(())
 }
 
+const DOTTED_DASHES: [usize; 2] = [1, 1];
+
 #[derive(Clone, Debug)]
 struct Node {
     name: String,
+    label: String,
+    shape: NodeShape,
     drawing: Option<Drawing>,
     drawing_coord: Option<DrawingCoord>,
     grid_coord: Option<GridCoord>,
-    index: usize,
-    style_class_name: Option<String>,
+    /// Grid cells the node covers, columns by rows: 3×3, or taller for
+    /// records with more rows than fit one cell.
+    footprint: (i32, i32),
+    /// Matched by `--highlight` or picked by `--auto-style`.
+    highlighted: bool,
+    /// Not reachable from the `--reachable-from` node.
+    dimmed: bool,
+    /// Its classes in the order they were applied, and the style they
+    /// resolve to.
+    class_names: Vec<String>,
     style_class: Option<StyleClass>,
 }
 
 impl Node {
-    fn new(name: String, index: usize) -> Node {
+    fn new(name: String) -> Node {
         Node {
+            label: name.clone(),
             name,
+            shape: NodeShape::Rectangle,
             drawing: None,
             drawing_coord: None,
             grid_coord: None,
-            index,
-            style_class_name: None,
+            footprint: (3, 3),
+            highlighted: false,
+            dimmed: false,
+            class_names: Vec::new(),
             style_class: None,
         }
     }
@@ -60,28 +102,74 @@ struct Edge {
     from: usize,
     to: usize,
     text: String,
+    order: usize,
+    min_length: usize,
+    /// Sides fixed by `A:e --> B:w`, overriding the ones the layout picks.
+    ports: (Option<Direction>, Option<Direction>),
+    terminator: Terminator,
+    start_terminator: Terminator,
+    line_style: LineStyle,
+    stroke: Stroke,
     path: Vec<GridCoord>,
     label_line: Vec<GridCoord>,
     start_dir: Direction,
     end_dir: Direction,
+    route: Option<RouteChoice>,
+}
+
+/// Where a label sits among those stacked on one stretch of line.
+struct LabelStack {
+    /// Its place in the stack, and how many labels there are.
+    slot: usize,
+    count: usize,
+    /// The first row it takes, counting down from the line, and the rows
+    /// all of them take.
+    row: usize,
+    rows: usize,
+    widest: usize,
+}
+
+/// How `determine_path` picked an edge's route, for `--explain`.
+#[derive(Clone, Debug)]
+struct RouteChoice {
+    placement: Direction,
+    preferred: (Direction, Direction, usize),
+    alternative: (Direction, Direction, usize),
+    used_preferred: bool,
+    /// Routed through the return channel instead of either search.
+    return_channel: bool,
 }
 
 impl Edge {
-    fn new(from: usize, to: usize, text: String) -> Edge {
+    fn new(from: usize, to: usize, text: String, order: usize, terminator: Terminator) -> Edge {
         Edge {
             from,
             to,
             text,
+            order,
+            min_length: 1,
+            ports: (None, None),
+            terminator,
+            start_terminator: Terminator::None,
+            line_style: LineStyle::Solid,
+            stroke: Stroke::Normal,
             path: Vec::new(),
             label_line: Vec::new(),
             start_dir: Direction::Right,
             end_dir: Direction::Left,
+            route: None,
         }
     }
 }
 
+/// The start and end of a route search, and the nodes the edge joins.
+type PathQuery = (GridCoord, GridCoord, (usize, usize));
+
+/// A diagram being rendered. [`render_properties`] runs the steps in one go;
+/// tools that want to inspect or adjust the layout call [`Graph::layout_with`]
+/// and then [`Graph::draw`] or [`Graph::render`] themselves.
 #[derive(Clone, Debug)]
-struct Graph {
+pub struct Graph {
     nodes: Vec<Node>,
     edges: Vec<Edge>,
     drawing: Drawing,
@@ -91,15 +179,66 @@ struct Graph {
     padding_x: i32,
     padding_y: i32,
     style_classes: HashMap<String, StyleClass>,
-    style_type: String,
     direction: GraphDirection,
+    title: Option<String>,
     options: RenderOptions,
     offset_x: i32,
     offset_y: i32,
+    /// Backward edges routed through the return channel so far; each gets
+    /// its own lane.
+    return_lanes: i32,
+    /// Routes already searched for; `None` when there is none. Routing only
+    /// depends on where the nodes are, so this holds until they move.
+    path_cache: HashMap<PathQuery, Option<Vec<GridCoord>>>,
+    /// The cells edges may cross at each clearance searched with, worked out
+    /// once per layout for every search.
+    clearance_maps: Vec<ClearanceMap>,
+    search_scratch: SearchScratch,
+    /// How long each layout phase took.
+    timings: Vec<(&'static str, Duration)>,
+    /// Where nodes and edges were declared, for [`Graph::positions`].
+    node_spans: HashMap<String, Range<usize>>,
+    edge_spans: HashMap<usize, Range<usize>>,
+    /// Where nodes were declared, for `source_map`.
+    node_lines: HashMap<String, usize>,
+}
+
+/// Sizes and timings of a finished layout, from [`Graph::stats`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LayoutStats {
+    pub nodes: usize,
+    pub edges: usize,
+    /// Grid columns and rows in use.
+    pub grid: (usize, usize),
+    /// The mapping and routing phases with how long they took.
+    pub phases: Vec<(&'static str, Duration)>,
+}
+
+/// A node or edge declaration and where it was drawn, from
+/// [`Graph::positions`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourcePosition {
+    pub item: SourceItem,
+    /// Bytes of the parsed text declaring it.
+    pub span: Range<usize>,
+    /// Cells the box or the edge's line covers in [`Graph::draw`], both
+    /// corners included.
+    pub top_left: DrawingCoord,
+    pub bottom_right: DrawingCoord,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SourceItem {
+    Node(String),
+    /// An edge by its end nodes and its position in the source, counting
+    /// from zero.
+    Edge { from: String, to: String, order: usize },
 }
 
 impl Graph {
-    fn new(properties: &GraphProperties, options: RenderOptions) -> Graph {
+    pub fn new(properties: &GraphProperties, mut options: RenderOptions) -> Graph {
+        options.border_padding = options.border_padding.max(0);
+        options.edge_clearance = options.edge_clearance.max(0);
         let mut nodes: Vec<Node> = Vec::new();
         let mut node_lookup: HashMap<String, usize> = HashMap::new();
         let mut edges: Vec<Edge> = Vec::new();
@@ -107,7 +246,7 @@ impl Graph {
         for (node_name, children) in properties.data.iter() {
             let parent_index = *node_lookup.entry(node_name.clone()).or_insert_with(|| {
                 let idx = nodes.len();
-                nodes.push(Node::new(node_name.clone(), idx));
+                nodes.push(Node::new(node_name.clone()));
                 idx
             });
 
@@ -116,122 +255,440 @@ impl Graph {
                     .entry(text_edge.child.name.clone())
                     .or_insert_with(|| {
                         let idx = nodes.len();
-                        let mut child =
-                            Node::new(text_edge.child.name.clone(), idx);
-                        if let Some(class) = &text_edge.child.style_class {
-                            if !class.is_empty() {
-                                child.style_class_name = Some(class.clone());
-                            }
-                        }
-                        nodes.push(child);
+                        nodes.push(Node::new(text_edge.child.name.clone()));
                         idx
                     });
 
-                if let Some(class) = &text_edge.parent.style_class {
-                    if !class.is_empty() {
-                        nodes[parent_index].style_class_name = Some(class.clone());
-                    }
-                }
-                if let Some(class) = &text_edge.child.style_class {
-                    if !class.is_empty() {
-                        nodes[child_index].style_class_name = Some(class.clone());
-                    }
-                }
-
-                edges.push(Edge::new(
+                let mut edge = Edge::new(
                     parent_index,
                     child_index,
-                    text_edge.label.clone(),
-                ));
+                    line_breaks(&text_edge.label),
+                    text_edge.order,
+                    text_edge.terminator,
+                );
+                edge.start_terminator = text_edge.start_terminator;
+                edge.line_style = text_edge.line_style;
+                edge.min_length = text_edge.min_length;
+                edge.ports = (text_edge.parent_port.map(port_side), text_edge.child_port.map(port_side));
+                if let Some(weight) = text_edge.weight {
+                    edge.stroke = Stroke::from_weight(weight);
+                }
+                edges.push(edge);
             }
+        }
 
-            // Ensure standalone nodes (with no outgoing edges) still record style class
-            if let Some(style_name) = &nodes[parent_index].style_class_name {
-                if style_name.is_empty() {
-                    nodes[parent_index].style_class_name = None;
-                }
+        for node in &mut nodes {
+            let label = properties.labels.get(&node.name).unwrap_or(&node.name);
+            node.label = options.icons.expand(label, options.use_ascii);
+            node.shape = properties.shapes.get(&node.name).copied().unwrap_or_default();
+            node.class_names = properties.classes.get(&node.name).cloned().unwrap_or_default();
+        }
+
+        if let Some(highlight) = options.highlight.as_deref().and_then(|pattern| Regex::new(pattern).ok()) {
+            for node in &mut nodes {
+                node.highlighted = highlight.is_match(&node.name) || highlight.is_match(&node.label);
+            }
+        }
+        if options.auto_style == Some(AutoStyle::Degree) {
+            let mut degree = vec![0usize; nodes.len()];
+            for edge in edges.iter().filter(|edge| edge.line_style != LineStyle::Invisible) {
+                degree[edge.from] += 1;
+                degree[edge.to] += 1;
+            }
+            let mut hubs: Vec<usize> = (0..nodes.len()).filter(|idx| degree[*idx] > 0).collect();
+            hubs.sort_by_key(|idx| std::cmp::Reverse(degree[*idx]));
+            for idx in hubs.into_iter().take(options.auto_style_top) {
+                nodes[idx].highlighted = true;
+            }
+        }
+
+        if options.shorten_labels {
+            let names: Vec<String> = nodes.iter().map(|node| node.label.clone()).collect();
+            for (node, short) in nodes.iter_mut().zip(shorten_names(&names)) {
+                node.label = short;
+            }
+        }
+
+        if options.number_edges {
+            let mut by_order: Vec<&mut Edge> =
+                edges.iter_mut().filter(|edge| edge.line_style != LineStyle::Invisible).collect();
+            by_order.sort_by_key(|edge| edge.order);
+            for (number, edge) in by_order.into_iter().enumerate() {
+                edge.text = if edge.text.is_empty() {
+                    format!("{}.", number + 1)
+                } else {
+                    format!("{}. {}", number + 1, edge.text)
+                };
+            }
+        }
+
+        if options.reorder_bidi {
+            for node in &mut nodes {
+                node.label = visual_order(&node.label);
+            }
+            for edge in &mut edges {
+                edge.text = visual_order(&edge.text);
             }
         }
 
-        Graph {
+        let mut graph = Graph {
             nodes,
             edges,
             drawing: Drawing::empty(),
             grid: HashMap::new(),
             column_width: HashMap::new(),
             row_height: HashMap::new(),
-            padding_x: properties.padding_x,
-            padding_y: properties.padding_y,
+            padding_x: options.padding_x.unwrap_or(properties.padding_x).max(0),
+            padding_y: match options.padding_y.unwrap_or(properties.padding_y).max(0) {
+                padding if options.aspect_compensate => (padding + 1) / 2,
+                padding => padding,
+            },
             style_classes: properties.style_classes.clone(),
-            style_type: properties.style_type.clone(),
-            direction: properties.graph_direction,
+            direction: options.direction.unwrap_or(properties.graph_direction),
+            title: options.title.clone().or_else(|| properties.title.clone()),
             options,
             offset_x: 0,
             offset_y: 0,
+            return_lanes: 0,
+            path_cache: HashMap::new(),
+            clearance_maps: Vec::new(),
+            search_scratch: SearchScratch::default(),
+            timings: Vec::new(),
+            node_spans: properties.node_spans.clone(),
+            edge_spans: properties.edge_spans.clone(),
+            node_lines: properties.node_lines.clone(),
+        };
+        for node in &mut graph.nodes {
+            node.footprint = footprint(node, graph.options.border_padding, graph.padding_y);
+        }
+        if let (None, Some(area)) = (graph.options.direction, graph.options.fit_to) {
+            graph.direction = graph.best_fitting_direction(area);
+        }
+        graph
+    }
+
+    /// Compares the header's direction with the other one by how much each
+    /// estimated drawing would have to shrink to fit in `area`; ties keep
+    /// the header's.
+    fn best_fitting_direction(&self, (columns, rows): (usize, usize)) -> GraphDirection {
+        let other = match self.direction {
+            GraphDirection::Lr => GraphDirection::Td,
+            GraphDirection::Td => GraphDirection::Lr,
+        };
+        let overflow = |direction| {
+            let mut probe = self.clone();
+            probe.direction = direction;
+            let (width, height) = probe.estimated_size();
+            (width as f64 / columns as f64).max(height as f64 / rows as f64)
+        };
+        if overflow(other) < overflow(self.direction) {
+            other
+        } else {
+            self.direction
+        }
+    }
+
+    /// Drawing size from node placement alone, before edges are routed.
+    fn estimated_size(&mut self) -> (usize, usize) {
+        if self.nodes.is_empty() {
+            return (0, 0);
         }
+        self.set_style_classes();
+        self.create_mapping();
+        for idx in 0..self.nodes.len() {
+            if let Some(coord) = self.nodes[idx].grid_coord {
+                self.set_column_width(idx, coord);
+            }
+        }
+        self.widen_skipped_levels();
+        let width: i32 = self.column_width.values().sum();
+        let height: i32 = self.row_height.values().sum();
+        (width.max(0) as usize, height.max(0) as usize)
+    }
+
+    /// Places the nodes and routes the edges. Errors are tagged
+    /// [`ErrorCategory::Layout`].
+    pub fn layout(&mut self) -> Result<()> {
+        self.layout_with(&mut NoHooks)
+    }
+
+    /// Like [`Graph::layout`], calling `hooks` as nodes are placed and edges
+    /// routed.
+    pub fn layout_with(&mut self, hooks: &mut dyn LayoutHooks) -> Result<()> {
+        self.run_layout(hooks)
+            .map_err(|err| tagged(ErrorCategory::Layout, err))
     }
 
-    fn layout(&mut self) -> Result<()> {
+    fn run_layout(&mut self, hooks: &mut dyn LayoutHooks) -> Result<()> {
         if self.nodes.is_empty() {
             return Err(anyhow!("no nodes to render"));
         }
+        if self.nodes.len() > self.options.max_nodes {
+            return Err(anyhow!(
+                "graph has {} nodes, more than the limit of {}; split it up or raise the limit",
+                self.nodes.len(),
+                self.options.max_nodes
+            ));
+        }
+        if let Some(name) = self.options.reachable_from.clone() {
+            self.dim_unreachable(&name)?;
+        }
 
+        let mut phase = Phase::start("mapping");
         self.set_style_classes();
         self.create_mapping();
+        self.apply_placement_hooks(hooks)?;
+        let lanes = self.make_room_for_ports();
 
         for idx in 0..self.nodes.len() {
             if let Some(coord) = self.nodes[idx].grid_coord {
                 self.set_column_width(idx, coord);
             }
         }
-
-        for edge in &mut self.edges {
+        // A port lane is drawn in the middle of its gap; the half outside it
+        // would only be blank.
+        self.offset_x = -lanes.x * self.column_width.get(&0).copied().unwrap_or(0) / 2;
+        self.offset_y = -lanes.y * self.row_height.get(&0).copied().unwrap_or(0) / 2;
+        self.widen_skipped_levels();
+        self.check_canvas_size()?;
+        phase.count("nodes", self.nodes.len());
+        phase.count("grid_cells", self.grid.len());
+        self.timings.push(("mapping", phase.finish()));
+
+        let mut phase = Phase::start("routing");
+        self.path_cache.clear();
+        let clearance = self.options.edge_clearance.max(0);
+        self.clearance_maps = std::iter::once(0)
+            .chain((clearance > 0).then_some(clearance))
+            .map(|clearance| ClearanceMap::new(&self.grid, clearance))
+            .collect();
+        let mut edges = std::mem::take(&mut self.edges);
+        for edge in edges.iter_mut().filter(|edge| edge.line_style != LineStyle::Invisible) {
             self.determine_path(edge)?;
+            hooks.on_edge_routed(&self.nodes[edge.from].name, &self.nodes[edge.to].name, &mut edge.path)?;
+            if edge.path.is_empty() {
+                return Err(anyhow!(
+                    "on_edge_routed left no path from {} to {}",
+                    self.nodes[edge.from].name,
+                    self.nodes[edge.to].name
+                ));
+            }
             self.increase_grid_size_for_path(&edge.path);
             self.determine_label_line(edge);
         }
-
+        self.edges = edges;
+        self.make_room_for_label_stacks();
+        phase.count("edges", self.edges.len());
+        phase.count("path_points", self.edges.iter().map(|e| e.path.len()).sum());
+        phase.count("columns", self.column_width.len());
+        phase.count("rows", self.row_height.len());
+        self.timings.push(("routing", phase.finish()));
+
+        self.check_canvas_size()?;
         self.set_drawing_size_to_grid_constraints();
 
-        for node in &mut self.nodes {
-            if let Some(coord) = node.grid_coord {
+        for idx in 0..self.nodes.len() {
+            if self.nodes[idx].shape == NodeShape::Spacer {
+                continue;
+            }
+            if let Some(coord) = self.nodes[idx].grid_coord {
                 let drawing_coord = self.grid_to_drawing_coord(coord, None);
-                node.drawing_coord = Some(drawing_coord);
-                node.drawing = Some(draw_box(node, self));
+                let mut drawing = draw_box(&self.nodes[idx], coord, self);
+                if self.nodes[idx].highlighted {
+                    emphasize_border(&mut drawing, self);
+                }
+                if self.nodes[idx].dimmed {
+                    dim_border(&mut drawing, self);
+                }
+                self.nodes[idx].drawing_coord = Some(drawing_coord);
+                self.nodes[idx].drawing = Some(drawing);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Marks the nodes no path of edges leads to from the node `name`.
+    fn dim_unreachable(&mut self, name: &str) -> Result<()> {
+        let start = self
+            .nodes
+            .iter()
+            .position(|node| node.name == name)
+            .ok_or_else(|| anyhow!("reachable_from names no node: {}", name))?;
+        let mut reached = vec![false; self.nodes.len()];
+        reached[start] = true;
+        let mut queue = VecDeque::from([start]);
+        while let Some(idx) = queue.pop_front() {
+            for edge in self.edges.iter().filter(|edge| edge.from == idx && edge.line_style != LineStyle::Invisible) {
+                if !reached[edge.to] {
+                    reached[edge.to] = true;
+                    queue.push_back(edge.to);
+                }
+            }
+        }
+        for (node, reached) in self.nodes.iter_mut().zip(reached) {
+            node.dimmed = !reached;
+        }
+        Ok(())
+    }
+
+    fn apply_placement_hooks(&mut self, hooks: &mut dyn LayoutHooks) -> Result<()> {
+        let mut moved = false;
+        for idx in 0..self.nodes.len() {
+            let Some(placed) = self.nodes[idx].grid_coord else {
+                continue;
+            };
+            let mut coord = placed;
+            hooks.on_node_placed(&self.nodes[idx].name, &mut coord)?;
+            if coord != placed {
+                if coord.x < 0 || coord.y < 0 {
+                    return Err(anyhow!(
+                        "on_node_placed moved {} off the grid to {:?}",
+                        self.nodes[idx].name,
+                        coord
+                    ));
+                }
+                self.nodes[idx].grid_coord = Some(coord);
+                moved = true;
             }
         }
+        if !moved {
+            return Ok(());
+        }
 
+        self.rebuild_grid();
+        let cells: i32 = self
+            .nodes
+            .iter()
+            .filter(|node| node.grid_coord.is_some())
+            .map(|node| node.footprint.0 * node.footprint.1)
+            .sum();
+        if (self.grid.len() as i32) < cells {
+            let (a, b) = self.overlapping_nodes();
+            return Err(anyhow!(
+                "on_node_placed left {} overlapping {}",
+                self.nodes[a].name,
+                self.nodes[b].name
+            ));
+        }
         Ok(())
     }
 
+    fn overlapping_nodes(&self) -> (usize, usize) {
+        let mut owner: HashMap<GridCoord, usize> = HashMap::new();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let Some(coord) = node.grid_coord else {
+                continue;
+            };
+            for cell in footprint_cells(coord, node.footprint) {
+                if let Some(other) = owner.insert(cell, idx) {
+                    return (other, idx);
+                }
+            }
+        }
+        (0, 0)
+    }
+
     fn set_style_classes(&mut self) {
         for node in &mut self.nodes {
-            if let Some(name) = &node.style_class_name {
-                node.style_class = self.style_classes.get(name).cloned();
-            }
+            node.style_class = StyleClass::resolve(&node.class_names, &self.style_classes);
         }
     }
 
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "info", skip_all))]
     fn create_mapping(&mut self) {
+        let all: Vec<usize> = (0..self.nodes.len()).collect();
+        let components = self.components();
+        if self.direction != GraphDirection::Lr || components.len() < 2 {
+            self.place(&all, 0);
+            if self.options.center_hubs {
+                self.center_hubs(&all);
+            }
+            return;
+        }
+        let mut origin = 0;
+        for component in &components {
+            self.place(component, origin);
+            if self.options.center_hubs {
+                self.center_hubs(component);
+            }
+            let bottom = component
+                .iter()
+                .filter_map(|idx| self.nodes[*idx].grid_coord)
+                .map(|coord| coord.y)
+                .max()
+                .unwrap_or(origin);
+            origin = bottom + 4;
+        }
+        self.pack_rows(&components);
+    }
+
+    fn place(&mut self, nodes: &[usize], origin: i32) {
+        if self.options.undirected {
+            self.place_bfs_tree(nodes, origin);
+        } else {
+            self.place_nodes(nodes, origin);
+        }
+    }
+
+    /// Ignores edge direction: each connected group is laid out as the BFS
+    /// tree from its node with most edges, one level per hop.
+    fn place_bfs_tree(&mut self, nodes: &[usize], origin: i32) {
+        let members: HashSet<usize> = nodes.iter().copied().collect();
+        let mut neighbours: Vec<Vec<usize>> = vec![Vec::new(); self.nodes.len()];
+        for edge in &self.edges {
+            if edge.from != edge.to && members.contains(&edge.from) && members.contains(&edge.to) {
+                neighbours[edge.from].push(edge.to);
+                neighbours[edge.to].push(edge.from);
+            }
+        }
+
+        let mut highest_per_level: HashMap<i32, i32> = HashMap::new();
+        let mut visited: HashSet<usize> = HashSet::new();
+        while let Some(root) = nodes
+            .iter()
+            .copied()
+            .filter(|idx| !visited.contains(idx))
+            .max_by_key(|idx| (neighbours[*idx].len(), std::cmp::Reverse(*idx)))
+        {
+            visited.insert(root);
+            // Children start level with their parent so chains stay straight.
+            let mut queue = std::collections::VecDeque::from([(root, 0, origin)]);
+            while let Some((idx, level, parent_cross)) = queue.pop_front() {
+                let next_free = highest_per_level.entry(level).or_insert(origin);
+                let cross = (*next_free).max(parent_cross);
+                *next_free = cross + 4;
+                let requested = if self.direction == GraphDirection::Lr {
+                    GridCoord { x: level, y: cross }
+                } else {
+                    GridCoord { x: cross, y: level }
+                };
+                let reserved = self.reserve_spot_in_grid(idx, requested);
+                self.nodes[idx].grid_coord = Some(reserved);
+                for &next in &neighbours[idx] {
+                    if visited.insert(next) {
+                        queue.push_back((next, level + 4, cross));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Places `nodes` level by level, starting the cross axis at `origin`.
+    fn place_nodes(&mut self, nodes: &[usize], origin: i32) {
         let mut highest_per_level: HashMap<i32, i32> = HashMap::new();
         let mut has_incoming = vec![false; self.nodes.len()];
         for edge in &self.edges {
             has_incoming[edge.to] = true;
         }
 
-        let root_nodes: Vec<usize> = if has_incoming.iter().all(|x| *x) {
-            (0..self.nodes.len()).collect()
+        let root_nodes: Vec<usize> = if nodes.iter().all(|idx| has_incoming[*idx]) {
+            nodes.to_vec()
         } else {
-            has_incoming
+            nodes
                 .iter()
-                .enumerate()
-                .filter_map(|(idx, has_parent)| {
-                    if !has_parent {
-                        Some(idx)
-                    } else {
-                        None
-                    }
-                })
+                .copied()
+                .filter(|idx| !has_incoming[*idx])
                 .collect()
         };
 
@@ -239,48 +696,268 @@ impl Graph {
             let coord = if self.direction == GraphDirection::Lr {
                 GridCoord {
                     x: 0,
-                    y: *highest_per_level.entry(0).or_insert(0),
+                    y: *highest_per_level.entry(0).or_insert(origin),
                 }
             } else {
                 GridCoord {
-                    x: *highest_per_level.entry(0).or_insert(0),
+                    x: *highest_per_level.entry(0).or_insert(origin),
                     y: 0,
                 }
             };
             let reserved = self.reserve_spot_in_grid(*idx, coord);
             self.nodes[*idx].grid_coord = Some(reserved);
-            let entry = highest_per_level.entry(0).or_insert(0);
+            let entry = highest_per_level.entry(0).or_insert(origin);
             *entry += 4;
         }
 
-        for idx in 0..self.nodes.len() {
-            let Some(coord) = self.nodes[idx].grid_coord else {
-                continue;
-            };
-            let child_level = if self.direction == GraphDirection::Lr {
-                coord.x + 4
-            } else {
-                coord.y + 4
-            };
-            let entry = highest_per_level.entry(child_level).or_insert(0);
-            for child in self.get_children(idx) {
-                if self.nodes[child].grid_coord.is_some() {
+        // Children can be placed after their own children were visited (when
+        // they have a lower index), so keep sweeping until nothing moves.
+        let mut placed_any = true;
+        while placed_any {
+            placed_any = false;
+            for &idx in nodes {
+                let Some(coord) = self.nodes[idx].grid_coord else {
                     continue;
+                };
+                let level = if self.direction == GraphDirection::Lr {
+                    coord.x
+                } else {
+                    coord.y
+                };
+                for child in self.get_children(idx) {
+                    if self.nodes[child].grid_coord.is_some() {
+                        continue;
+                    }
+                    // `A ----> B` puts B further along than the next level.
+                    let length = self
+                        .edges
+                        .iter()
+                        .filter(|edge| edge.from == idx && edge.to == child)
+                        .map(|edge| edge.min_length)
+                        .max()
+                        .unwrap_or(1);
+                    // Levels stay four cells apart, so a node taller than
+                    // one level pushes its children down a level more.
+                    let (cols, rows) = self.nodes[idx].footprint;
+                    let extent = if self.direction == GraphDirection::Lr { cols } else { rows };
+                    let child_level = level + 4 * (length as i32 + extent / 4);
+                    let entry = *highest_per_level.entry(child_level).or_insert(origin);
+                    let requested = if self.direction == GraphDirection::Lr {
+                        GridCoord {
+                            x: child_level,
+                            y: entry,
+                        }
+                    } else {
+                        GridCoord {
+                            x: entry,
+                            y: child_level,
+                        }
+                    };
+                    let reserved = self.reserve_spot_in_grid(child, requested);
+                    self.nodes[child].grid_coord = Some(reserved);
+                    *highest_per_level.entry(child_level).or_insert(origin) += 4;
+                    placed_any = true;
                 }
-                let requested = if self.direction == GraphDirection::Lr {
-                    GridCoord {
-                        x: child_level,
-                        y: *entry,
+            }
+
+            // Cycles that are unreachable from any root get seeded on level 0.
+            if !placed_any {
+                if let Some(&idx) = nodes.iter().find(|idx| self.nodes[**idx].grid_coord.is_none()) {
+                    let entry = *highest_per_level.entry(0).or_insert(origin);
+                    let requested = if self.direction == GraphDirection::Lr {
+                        GridCoord { x: 0, y: entry }
+                    } else {
+                        GridCoord { x: entry, y: 0 }
+                    };
+                    let reserved = self.reserve_spot_in_grid(idx, requested);
+                    self.nodes[idx].grid_coord = Some(reserved);
+                    *highest_per_level.entry(0).or_insert(origin) += 4;
+                    placed_any = true;
+                }
+            }
+        }
+    }
+
+    /// Groups nodes into weakly connected components, in node order.
+    fn components(&self) -> Vec<Vec<usize>> {
+        let mut neighbours: Vec<Vec<usize>> = vec![Vec::new(); self.nodes.len()];
+        for edge in &self.edges {
+            neighbours[edge.from].push(edge.to);
+            neighbours[edge.to].push(edge.from);
+        }
+        let mut seen = vec![false; self.nodes.len()];
+        let mut components = Vec::new();
+        for start in 0..self.nodes.len() {
+            if seen[start] {
+                continue;
+            }
+            seen[start] = true;
+            let mut component = vec![start];
+            let mut next = 0;
+            while next < component.len() {
+                for &other in &neighbours[component[next]] {
+                    if !seen[other] {
+                        seen[other] = true;
+                        component.push(other);
                     }
+                }
+                next += 1;
+            }
+            component.sort_unstable();
+            components.push(component);
+        }
+        components
+    }
+
+    /// Packs components that were stacked one below the other. Each one
+    /// moves to the highest, then leftmost, offset where none of its columns
+    /// overlaps the rows an earlier component uses in that column, without
+    /// growing wider than the widest component.
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "info", skip_all))]
+    fn pack_rows(&mut self, components: &[Vec<usize>]) {
+        let profiles: Vec<HashMap<i32, (i32, i32)>> = components
+            .iter()
+            .map(|component| {
+                let coords: Vec<GridCoord> = component
+                    .iter()
+                    .filter_map(|idx| self.nodes[*idx].grid_coord)
+                    .collect();
+                let min_x = coords.iter().map(|c| c.x).min().unwrap_or(0);
+                let min_y = coords.iter().map(|c| c.y).min().unwrap_or(0);
+                let mut profile: HashMap<i32, (i32, i32)> = HashMap::new();
+                for (idx, coord) in component.iter().filter_map(|idx| self.nodes[*idx].grid_coord.map(|coord| (*idx, coord))) {
+                    let (x, y) = (coord.x - min_x, coord.y - min_y);
+                    let bottom = y + self.nodes[idx].footprint.1 - 3;
+                    let range = profile.entry(x).or_insert((y, bottom));
+                    range.0 = range.0.min(y);
+                    range.1 = range.1.max(bottom);
+                }
+                profile
+            })
+            .collect();
+        let width = profiles
+            .iter()
+            .filter_map(|profile| profile.keys().max())
+            .max()
+            .copied()
+            .unwrap_or(0);
+
+        let mut used: HashMap<i32, Vec<(i32, i32)>> = HashMap::new();
+        for (component, profile) in components.iter().zip(&profiles) {
+            let span = profile.keys().max().copied().unwrap_or(0);
+            let fits = |dx: i32, dy: i32| {
+                profile.iter().all(|(x, (lo, hi))| {
+                    used.get(&(x + dx)).is_none_or(|ranges| {
+                        ranges
+                            .iter()
+                            .all(|(other_lo, other_hi)| lo + dy > *other_hi || hi + dy < *other_lo)
+                    })
+                })
+            };
+            let (dx, dy) = (0..)
+                .step_by(4)
+                .find_map(|dy| {
+                    (0..=width - span)
+                        .step_by(4)
+                        .find(|dx| fits(*dx, dy))
+                        .map(|dx| (dx, dy))
+                })
+                .unwrap_or((0, 0));
+            for (x, (lo, hi)) in profile {
+                used.entry(x + dx).or_default().push((lo + dy, hi + dy));
+            }
+
+            let coords: Vec<GridCoord> = component
+                .iter()
+                .filter_map(|idx| self.nodes[*idx].grid_coord)
+                .collect();
+            let min_x = coords.iter().map(|c| c.x).min().unwrap_or(0);
+            let min_y = coords.iter().map(|c| c.y).min().unwrap_or(0);
+            for &idx in component {
+                if let Some(coord) = self.nodes[idx].grid_coord.as_mut() {
+                    coord.x += dx - min_x;
+                    coord.y += dy - min_y;
+                }
+            }
+        }
+
+        self.rebuild_grid();
+    }
+
+    /// Moves the best-connected nodes of each level to the middle of it, so
+    /// their edges fan out both ways instead of all running to one side.
+    /// Nodes keep the level and the set of slots they were given.
+    fn center_hubs(&mut self, nodes: &[usize]) {
+        let mut degree = vec![0; self.nodes.len()];
+        for edge in &self.edges {
+            degree[edge.from] += 1;
+            degree[edge.to] += 1;
+        }
+        let horizontal = self.direction == GraphDirection::Lr;
+        let mut levels: BTreeMap<i32, Vec<(usize, GridCoord)>> = BTreeMap::new();
+        for &idx in nodes {
+            if let Some(coord) = self.nodes[idx].grid_coord {
+                let level = if horizontal { coord.x } else { coord.y };
+                levels.entry(level).or_default().push((idx, coord));
+            }
+        }
+        for mut members in levels.into_values() {
+            let cross = |coord: &GridCoord| if horizontal { coord.y } else { coord.x };
+            members.sort_by_key(|(_, coord)| cross(coord));
+            let slots: Vec<GridCoord> = members.iter().map(|(_, coord)| *coord).collect();
+            let mut by_degree: Vec<usize> = members.iter().map(|(idx, _)| *idx).collect();
+            by_degree.sort_by_key(|idx| std::cmp::Reverse(degree[*idx]));
+            let middle = (slots.len() - 1) / 2;
+            let order = (0..slots.len()).map(|step| {
+                let offset = (step as i32 + 1) / 2;
+                if step % 2 == 1 {
+                    middle as i32 + offset
                 } else {
-                    GridCoord {
-                        x: *entry,
-                        y: child_level,
-                    }
-                };
-                let reserved = self.reserve_spot_in_grid(child, requested);
-                self.nodes[child].grid_coord = Some(reserved);
-                *entry += 4;
+                    middle as i32 - offset
+                }
+            });
+            for (idx, slot) in by_degree.into_iter().zip(order) {
+                self.nodes[idx].grid_coord = Some(slots[slot as usize]);
+            }
+        }
+        self.rebuild_grid();
+    }
+
+    /// An edge fixed to the top or left side of a node in the first row or
+    /// column has nowhere to go, so move every node one cell away on that
+    /// axis. Returns how far the nodes moved.
+    fn make_room_for_ports(&mut self) -> GridCoord {
+        let on_first = |side: Direction, first: fn(GridCoord) -> bool| {
+            self.edges.iter().any(|edge| {
+                [(edge.from, edge.ports.0), (edge.to, edge.ports.1)]
+                    .iter()
+                    .any(|(idx, port)| *port == Some(side) && self.nodes[*idx].grid_coord.is_some_and(first))
+            })
+        };
+        let shift = GridCoord {
+            x: on_first(Direction::Left, |coord| coord.x == 0) as i32,
+            y: on_first(Direction::Up, |coord| coord.y == 0) as i32,
+        };
+        if shift == (GridCoord { x: 0, y: 0 }) {
+            return shift;
+        }
+        for node in &mut self.nodes {
+            if let Some(coord) = &mut node.grid_coord {
+                coord.x += shift.x;
+                coord.y += shift.y;
+            }
+        }
+        self.rebuild_grid();
+        shift
+    }
+
+    fn rebuild_grid(&mut self) {
+        self.grid.clear();
+        for idx in 0..self.nodes.len() {
+            if let Some(coord) = self.nodes[idx].grid_coord {
+                for cell in footprint_cells(coord, self.nodes[idx].footprint) {
+                    self.grid.insert(cell, idx);
+                }
             }
         }
     }
@@ -298,12 +975,34 @@ impl Graph {
             .collect()
     }
 
+    /// The border cell on the `dir` side of node `idx`, or the corner for
+    /// diagonals. Left and right sides attach at the text row.
+    fn side_cell(&self, idx: usize, dir: Direction) -> GridCoord {
+        let coord = self.nodes[idx].grid_coord.unwrap_or(GridCoord { x: 0, y: 0 });
+        let (cols, rows) = self.nodes[idx].footprint;
+        let (middle_x, middle_y) = (coord.x + (cols - 1) / 2, coord.y + 1);
+        let (right, bottom) = (coord.x + cols - 1, coord.y + rows - 1);
+        let (x, y) = match dir {
+            Direction::Up => (middle_x, coord.y),
+            Direction::Down => (middle_x, bottom),
+            Direction::Left => (coord.x, middle_y),
+            Direction::Right => (right, middle_y),
+            Direction::UpperRight => (right, coord.y),
+            Direction::UpperLeft => (coord.x, coord.y),
+            Direction::LowerRight => (right, bottom),
+            Direction::LowerLeft => (coord.x, bottom),
+            Direction::Middle => (middle_x, middle_y),
+        };
+        GridCoord { x, y }
+    }
+
     fn reserve_spot_in_grid(
         &mut self,
         node_index: usize,
         requested: GridCoord,
     ) -> GridCoord {
-        if self.grid.contains_key(&requested) {
+        let footprint = self.nodes[node_index].footprint;
+        if footprint_cells(requested, footprint).any(|cell| self.grid.contains_key(&cell)) {
             let next = if self.direction == GraphDirection::Lr {
                 GridCoord {
                     x: requested.x,
@@ -318,31 +1017,50 @@ impl Graph {
             return self.reserve_spot_in_grid(node_index, next);
         }
 
-        for dx in 0..3 {
-            for dy in 0..3 {
-                let coord = GridCoord {
-                    x: requested.x + dx,
-                    y: requested.y + dy,
-                };
-                self.grid.insert(coord, node_index);
-            }
+        for cell in footprint_cells(requested, footprint) {
+            self.grid.insert(cell, node_index);
         }
 
         requested
     }
 
     fn set_column_width(&mut self, node_index: usize, coord: GridCoord) {
-        let text_len = self.nodes[node_index].name.chars().count() as i32;
-        let cols = [
-            1,
-            2 * self.options.border_padding + text_len,
-            1,
-        ];
-        let rows = [
+        let node = &self.nodes[node_index];
+        let record = (node.shape == NodeShape::Record).then(|| record_lines(&node.label));
+        let text_len = match &record {
+            Some(lines) => lines.iter().map(|line| text_width(line)).max().unwrap_or(0) as i32,
+            None => text_width(&node.label) as i32,
+        };
+        let min_width = node
+            .style_class
+            .as_ref()
+            .and_then(|class| class.styles.get("min-width"))
+            .and_then(|width| width.trim().trim_end_matches("px").parse().ok())
+            .unwrap_or(self.options.min_node_width);
+        // Sloped and notched sides take a cell from the text on their row.
+        let sides = match node.shape {
+            NodeShape::Rectangle | NodeShape::Record | NodeShape::Spacer => 0,
+            NodeShape::Flag => 1,
+            NodeShape::Trapezoid | NodeShape::ReverseTrapezoid => 2,
+        };
+        let mut cols = [
             1,
-            1 + 2 * self.options.border_padding,
+            (2 * self.options.border_padding + text_len + sides).max(min_width - 2),
             1,
         ];
+        // A record's rows sit under its title and a separator line. The text
+        // of a node more than one cell tall still goes in its first inner
+        // row; the rest only reserve room for routing.
+        let text_rows = record.map_or(1, |lines| lines.len() as i32 + 1);
+        let mut rows = vec![0; node.footprint.1 as usize];
+        rows[0] = 1;
+        rows[1] = text_rows + 2 * self.options.border_padding;
+        rows[node.footprint.1 as usize - 1] = 1;
+        // Spacers keep only the padding around their cells.
+        if node.shape == NodeShape::Spacer {
+            cols = [0; 3];
+            rows.fill(0);
+        }
 
         for (idx, col) in cols.iter().enumerate() {
             let x_coord = coord.x + idx as i32;
@@ -370,6 +1088,27 @@ impl Graph {
         }
     }
 
+    /// Levels that only a long edge (`A ----> B`) passes through hold no
+    /// nodes, so give each of them the gap a level would have.
+    fn widen_skipped_levels(&mut self) {
+        let lr = self.direction == GraphDirection::Lr;
+        for edge in self.edges.iter().filter(|edge| edge.min_length > 1) {
+            let (Some(from), Some(to)) = (self.nodes[edge.from].grid_coord, self.nodes[edge.to].grid_coord) else {
+                continue;
+            };
+            let (from, to) = if lr { (from.x, to.x) } else { (from.y, to.y) };
+            let (sizes, padding) = if lr {
+                (&mut self.column_width, self.padding_x)
+            } else {
+                (&mut self.row_height, self.padding_y)
+            };
+            for level in (from.min(to) + 4..from.max(to)).step_by(4) {
+                sizes.entry(level - 1).or_insert(padding);
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "info", skip_all))]
     fn determine_path(&mut self, edge: &mut Edge) -> Result<()> {
         let from_coord = self.nodes[edge.from]
             .grid_coord
@@ -378,21 +1117,38 @@ impl Graph {
             .grid_coord
             .ok_or_else(|| anyhow!("missing grid coord for node {}", edge.to))?;
 
-        let (preferred_dir, preferred_opposite, alt_dir, alt_opposite) =
+        let (mut preferred_dir, mut preferred_opposite, mut alt_dir, mut alt_opposite) =
             self.determine_start_and_end_dir(edge);
+        if let Some(side) = edge.ports.0 {
+            (preferred_dir, alt_dir) = (side, side);
+        }
+        if let Some(side) = edge.ports.1 {
+            (preferred_opposite, alt_opposite) = (side, side);
+        }
+        let preferred_from = self.side_cell(edge.from, preferred_dir);
+        let preferred_to = self.side_cell(edge.to, preferred_opposite);
+        let alt_from = self.side_cell(edge.from, alt_dir);
+        let alt_to = self.side_cell(edge.to, alt_opposite);
 
-        let preferred_from = from_coord.direction(preferred_dir);
-        let preferred_to = to_coord.direction(preferred_opposite);
-        let alt_from = from_coord.direction(alt_dir);
-        let alt_to = to_coord.direction(alt_opposite);
-
-        let preferred_path = self.get_path(preferred_from, preferred_to)?;
+        let endpoints = (edge.from, edge.to);
+        let preferred_path = self.get_clear_path(preferred_from, preferred_to, endpoints)?;
         let preferred_path = merge_path(preferred_path);
 
-        let alternative_path = self.get_path(alt_from, alt_to)?;
+        let alternative_path = self.get_clear_path(alt_from, alt_to, endpoints)?;
         let alternative_path = merge_path(alternative_path);
 
-        if preferred_path.len() <= alternative_path.len() {
+        let used_preferred = preferred_path.len() <= alternative_path.len();
+        edge.route = Some(RouteChoice {
+            placement: determine_direction(
+                GenericCoord { x: from_coord.x, y: from_coord.y },
+                GenericCoord { x: to_coord.x, y: to_coord.y },
+            ),
+            preferred: (preferred_dir, preferred_opposite, preferred_path.len()),
+            alternative: (alt_dir, alt_opposite, alternative_path.len()),
+            used_preferred,
+            return_channel: false,
+        });
+        if used_preferred {
             edge.start_dir = preferred_dir;
             edge.end_dir = preferred_opposite;
             edge.path = preferred_path;
@@ -402,6 +1158,23 @@ impl Graph {
             edge.path = alternative_path;
         }
 
+        // A backward edge takes the return channel unless searching found a
+        // shorter loop.
+        if self.is_backwards(edge) && edge.ports == (None, None) {
+            if let Some(path) = self.return_channel_path(edge) {
+                if path_cells(&path) <= path_cells(&edge.path) {
+                    let side = if self.direction == GraphDirection::Lr { Direction::Down } else { Direction::Right };
+                    edge.start_dir = side;
+                    edge.end_dir = side;
+                    edge.path = path;
+                    self.return_lanes += 1;
+                    if let Some(route) = &mut edge.route {
+                        route.return_channel = true;
+                    }
+                }
+            }
+        }
+
         Ok(())
     }
 
@@ -427,17 +1200,7 @@ impl Graph {
             },
             GenericCoord { x: to.x, y: to.y },
         );
-
-        let is_backwards = match self.direction {
-            GraphDirection::Lr => matches!(
-                dir,
-                Direction::Left | Direction::UpperLeft | Direction::LowerLeft
-            ),
-            GraphDirection::Td => matches!(
-                dir,
-                Direction::Up | Direction::UpperLeft | Direction::UpperRight
-            ),
-        };
+        let is_backwards = self.is_backwards(edge);
 
         match dir {
             Direction::LowerRight => {
@@ -507,8 +1270,56 @@ impl Graph {
         }
     }
 
-    fn self_reference_direction(&self) -> (Direction, Direction, Direction, Direction) {
-        match self.direction {
+    /// Whether `edge` runs against the flow: leftwards in LR, upwards in TD.
+    fn is_backwards(&self, edge: &Edge) -> bool {
+        let (Some(from), Some(to)) = (self.nodes[edge.from].grid_coord, self.nodes[edge.to].grid_coord) else {
+            return false;
+        };
+        if edge.from == edge.to {
+            return false;
+        }
+        let dir = determine_direction(GenericCoord { x: from.x, y: from.y }, GenericCoord { x: to.x, y: to.y });
+        match self.direction {
+            GraphDirection::Lr => matches!(dir, Direction::Left | Direction::UpperLeft | Direction::LowerLeft),
+            GraphDirection::Td => matches!(dir, Direction::Up | Direction::UpperLeft | Direction::UpperRight),
+        }
+    }
+
+    /// Routes a backward edge through the return channel: a lane of its own
+    /// below every node in LR, or right of every node in TD. The legs from
+    /// the bottom (right) side of each end to the lane go around any node in
+    /// the way. `None` when no leg can be found.
+    fn return_channel_path(&mut self, edge: &Edge) -> Option<Vec<GridCoord>> {
+        let lr = self.direction == GraphDirection::Lr;
+        let far_side = self
+            .nodes
+            .iter()
+            .filter_map(|node| node.grid_coord.map(|coord| (coord, node.footprint)))
+            .map(|(coord, (cols, rows))| if lr { coord.y + rows } else { coord.x + cols } - 1)
+            .max()?;
+        let lane = far_side + 1 + self.return_lanes;
+        let side = if lr { Direction::Down } else { Direction::Right };
+        let (start, end) = (self.side_cell(edge.from, side), self.side_cell(edge.to, side));
+        let on_lane = |border: GridCoord| if lr { GridCoord { x: border.x, y: lane } } else { GridCoord { x: lane, y: border.y } };
+        let endpoints = (edge.from, edge.to);
+        let out = self.get_clear_path(start, on_lane(start), endpoints).ok()?;
+        let back = self.get_clear_path(on_lane(end), end, endpoints).ok()?;
+
+        let mut cells = out;
+        let mut current = on_lane(start);
+        while current != on_lane(end) {
+            current = GridCoord {
+                x: current.x + (on_lane(end).x - current.x).signum(),
+                y: current.y + (on_lane(end).y - current.y).signum(),
+            };
+            cells.push(current);
+        }
+        cells.extend(back.into_iter().skip(1));
+        Some(merge_path(without_loops(cells)))
+    }
+
+    fn self_reference_direction(&self) -> (Direction, Direction, Direction, Direction) {
+        match self.direction {
             GraphDirection::Lr => (
                 Direction::Right,
                 Direction::Down,
@@ -540,13 +1351,15 @@ impl Graph {
             return;
         }
 
+        let (label_width, label_rows) = label_size(&edge.text);
+        let (mut label_width, mut label_rows) = (label_width as i32, label_rows as i32);
         let mut prev_step = edge.path[0];
         let mut largest_line = vec![edge.path[0], edge.path[1]];
         let mut largest_size = 0;
         for step in edge.path.iter().skip(1) {
             let line = vec![prev_step, *step];
             let width = self.calculate_line_width(&line);
-            if width >= edge.text.len() as i32 {
+            if width >= label_width || self.label_beside(&line) {
                 largest_line = line;
                 break;
             } else if width > largest_size {
@@ -556,26 +1369,136 @@ impl Graph {
             prev_step = *step;
         }
 
+        if self.label_beside(&largest_line) {
+            // The text starts two cells right of the line. Whatever overhangs
+            // the line's own column goes into the columns to its right; box
+            // borders there keep their width, the first other column grows.
+            let x = largest_line[0].x;
+            // Growing a column by more than the gap between columns is what
+            // `vertical_labels` avoids.
+            let growth = self.label_overhang(x, label_width).map_or(0, |(column, overhang)| {
+                overhang - self.column_width.get(&column).copied().unwrap_or(0)
+            });
+            if self.options.vertical_labels && growth > self.padding_x {
+                // One character per row, with a blank row between lines.
+                let text = edge.text.replace('\n', " ");
+                edge.text = text.graphemes(true).collect::<Vec<_>>().join("\n");
+                let (width, rows) = label_size(&edge.text);
+                (label_width, label_rows) = (width as i32, rows as i32);
+            }
+            if let Some((column, overhang)) = self.label_overhang(x, label_width) {
+                let width = self.column_width.entry(column).or_insert(0);
+                *width = max(*width, overhang);
+            }
+            // Lines beyond the first are centred on the line's middle row.
+            if label_rows > 1 {
+                let middle_y = largest_line[0].y.min(largest_line[1].y) + (largest_line[0].y - largest_line[1].y).abs() / 2;
+                let height = self.row_height.entry(middle_y).or_insert(0);
+                *height = max(*height, label_rows + 2);
+            }
+            edge.label_line = largest_line;
+            return;
+        }
+
         let middle_x = if largest_line[0].x > largest_line[1].x {
             largest_line[1].x + (largest_line[0].x - largest_line[1].x) / 2
         } else {
             largest_line[0].x + (largest_line[1].x - largest_line[0].x) / 2
         };
         let column_entry = self.column_width.entry(middle_x).or_insert(0);
-        *column_entry = max(*column_entry, edge.text.len() as i32 + 2);
+        let horizontal = largest_line[0].y == largest_line[1].y;
+        let margin = if self.options.clear_label_background && horizontal { 4 } else { 2 };
+        *column_entry = max(*column_entry, label_width + margin);
+        // Lines beyond the first go under the line, in the lower half of its
+        // row.
+        if horizontal && label_rows > 1 {
+            let row_entry = self.row_height.entry(largest_line[0].y).or_insert(0);
+            *row_entry = max(*row_entry, 2 * label_rows - 1);
+        }
 
         edge.label_line = largest_line;
     }
 
+    /// The first column other than a box border that a label `label_width`
+    /// wide beside the vertical line in column `x` runs into, and how wide
+    /// that column has to be to hold the rest of it.
+    fn label_overhang(&self, x: i32, label_width: i32) -> Option<(i32, i32)> {
+        let own = self.column_width.get(&x).copied().unwrap_or(0);
+        let mut overhang = label_width - (own - own / 2 - 2);
+        let mut column = x + 1;
+        while overhang > 0 {
+            if !self.is_border_column(column) {
+                return Some((column, overhang));
+            }
+            overhang -= self.column_width.get(&column).copied().unwrap_or(0);
+            column += 1;
+        }
+        None
+    }
+
+    /// Stacked labels need a column two cells wider for their leader, or
+    /// beside a vertical line, a row for each label.
+    fn make_room_for_label_stacks(&mut self) {
+        let mut room = Vec::new();
+        for edge in &self.edges {
+            let stack = self.label_stack(edge);
+            if edge.label_line.len() < 2 || stack.count < 2 || stack.slot > 0 {
+                continue;
+            }
+            let (from, to) = (edge.label_line[0], edge.label_line[1]);
+            if self.label_beside(&edge.label_line) {
+                room.push((false, from.y.min(to.y) + (from.y - to.y).abs() / 2, stack.rows as i32 + 2));
+            } else {
+                let margin = if self.options.clear_label_background && from.y == to.y { 4 } else { 2 };
+                room.push((true, from.x.min(to.x) + (from.x - to.x).abs() / 2, stack.widest as i32 + margin + 2));
+            }
+        }
+        for (column, at, size) in room {
+            let entry = if column { self.column_width.entry(at) } else { self.row_height.entry(at) };
+            let size_entry = entry.or_insert(0);
+            *size_entry = max(*size_entry, size);
+        }
+    }
+
+    fn is_border_column(&self, column: i32) -> bool {
+        self.nodes
+            .iter()
+            .filter_map(|node| node.grid_coord)
+            .any(|coord| column == coord.x || column == coord.x + 2)
+    }
+
+    /// Labels on vertical runs of TD graphs go next to the line instead of
+    /// over it.
+    fn label_beside(&self, line: &[GridCoord]) -> bool {
+        self.direction == GraphDirection::Td && line[0].x == line[1].x && line[0].y != line[1].y
+    }
+
     fn calculate_line_width(&self, line: &[GridCoord]) -> i32 {
         line.iter()
             .map(|coord| self.column_width.get(&coord.x).copied().unwrap_or(0))
             .sum()
     }
 
+    /// Fails when the canvas the grid asks for would have more than
+    /// `max_canvas_cells` cells, before anything that size is allocated.
+    fn check_canvas_size(&self) -> Result<()> {
+        let width: i64 = self.column_width.values().map(|width| *width as i64).sum();
+        let height: i64 = self.row_height.values().map(|height| *height as i64).sum();
+        let cells = width.saturating_mul(height);
+        if cells > self.options.max_canvas_cells as i64 {
+            return Err(anyhow!(
+                "drawing would be {}×{} cells, more than the limit of {}; split it up or raise the limit",
+                width,
+                height,
+                self.options.max_canvas_cells
+            ));
+        }
+        Ok(())
+    }
+
     fn set_drawing_size_to_grid_constraints(&mut self) {
-        let total_x: i32 = self.column_width.values().sum();
-        let total_y: i32 = self.row_height.values().sum();
+        let total_x = self.column_width.values().sum::<i32>() + self.offset_x;
+        let total_y = self.row_height.values().sum::<i32>() + self.offset_y;
         self.drawing
             .increase_size(total_x.saturating_sub(1) as usize, total_y.saturating_sub(1) as usize);
     }
@@ -622,81 +1545,326 @@ impl Graph {
             .collect()
     }
 
-    fn draw(&mut self) -> Drawing {
-        let mut base = self.drawing.clone();
+    /// Draws the laid-out diagram, including any rulers or overlays the
+    /// options ask for. Call [`Graph::layout`] first.
+    pub fn draw(&self) -> Drawing {
+        let mut phase = Phase::start("drawing");
+        let mut drawing = self.drawing.clone();
+        self.draw_shadows(&mut drawing);
+        if !self.options.edges_under_nodes {
+            self.draw_nodes(&mut drawing);
+        }
+        self.draw_edges(&mut drawing);
+        if self.options.show_ranks {
+            drawing = self.with_rank_ruler(drawing);
+        }
+        if self.options.show_coords {
+            drawing = self.with_coords_overlay(drawing);
+        }
+        if self.options.frame {
+            drawing = self.with_frame(drawing);
+        }
+        let sides = self.options.outer_padding;
+        if sides != Sides::default() {
+            let (max_x, max_y) = drawing.size();
+            let mut padded = Drawing::new(max_x + sides.left + sides.right, max_y + sides.top + sides.bottom);
+            padded.paste(&drawing, DrawingCoord { x: sides.left as i32, y: sides.top as i32 });
+            drawing = padded;
+        }
+        if self.options.source_map {
+            drawing = self.with_source_gutter(drawing);
+        }
+        if self.options.use_ascii && self.options.ascii_style == AsciiStyle::Fancy {
+            drawing.transliterate_to_ascii();
+        }
+        let (width, height) = drawing.size();
+        phase.count("width", width + 1);
+        phase.count("height", height + 1);
+        phase.finish();
+        drawing
+    }
+
+    /// The drawing as text, followed by the edge and label legends.
+    pub fn render(&self) -> String {
+        let mut output = Vec::new();
+        self.render_to(&mut output).expect("writing to a Vec cannot fail");
+        String::from_utf8(output).expect("drawings are UTF-8")
+    }
+
+    /// Writes what [`Graph::render`] returns to `writer`, one row at a time.
+    pub fn render_to<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        self.draw().write_to(&mut writer, self.options.color)?;
+        for legend in [self.edge_legend(), self.label_legend(), self.class_legend(), self.explain_table()] {
+            if !legend.is_empty() {
+                writer.write_all(b"\n\n")?;
+                writer.write_all(legend.join("\n").as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// For each node and edge parsed from Mermaid text, the bytes that
+    /// declare it and the cells it covers in [`Graph::draw`], in source
+    /// order, so editors can jump between the two. Items that were not drawn
+    /// or not parsed from text are left out. Call [`Graph::layout`] first.
+    pub fn positions(&self) -> Vec<SourcePosition> {
+        let offset = self.drawing_offset();
+        let shifted = |coord: DrawingCoord| DrawingCoord { x: coord.x + offset.x, y: coord.y + offset.y };
+        let mut positions = Vec::new();
+        for node in &self.nodes {
+            let (Some(span), Some(coord), Some(drawing)) =
+                (self.node_spans.get(&node.name), node.drawing_coord, &node.drawing)
+            else {
+                continue;
+            };
+            let (width, height) = drawing.size();
+            positions.push(SourcePosition {
+                item: SourceItem::Node(node.name.clone()),
+                span: span.clone(),
+                top_left: shifted(coord),
+                bottom_right: shifted(DrawingCoord { x: coord.x + width as i32, y: coord.y + height as i32 }),
+            });
+        }
+        for edge in &self.edges {
+            let Some(span) = self.edge_spans.get(&edge.order) else {
+                continue;
+            };
+            let cells: Vec<DrawingCoord> = self.line_to_drawing(&edge.path);
+            let (Some(min_x), Some(max_x)) = (cells.iter().map(|c| c.x).min(), cells.iter().map(|c| c.x).max()) else {
+                continue;
+            };
+            let (min_y, max_y) = (cells.iter().map(|c| c.y).min().unwrap(), cells.iter().map(|c| c.y).max().unwrap());
+            positions.push(SourcePosition {
+                item: SourceItem::Edge {
+                    from: self.nodes[edge.from].name.clone(),
+                    to: self.nodes[edge.to].name.clone(),
+                    order: edge.order,
+                },
+                span: span.clone(),
+                top_left: shifted(DrawingCoord { x: min_x, y: min_y }),
+                bottom_right: shifted(DrawingCoord { x: max_x, y: max_y }),
+            });
+        }
+        positions.sort_by_key(|position| (position.span.start, position.span.end));
+        positions
+    }
+
+    /// How far [`Graph::draw`] moves the laid-out diagram right and down to
+    /// make room for rulers, the frame, outer padding and the source gutter.
+    fn drawing_offset(&self) -> DrawingCoord {
+        let offset = self.margin_offset();
+        DrawingCoord { x: offset.x + self.source_gutter_width(), y: offset.y }
+    }
+
+    /// [`Graph::drawing_offset`] without the source gutter.
+    fn margin_offset(&self) -> DrawingCoord {
+        let mut offset = DrawingCoord { x: 0, y: 0 };
+        if self.options.show_ranks {
+            if self.direction == GraphDirection::Lr {
+                offset.y += 2;
+            } else {
+                offset.x += self.rank_ruler_width() + 1;
+            }
+        }
+        if self.options.show_coords {
+            offset.x += 1;
+            offset.y += 1;
+        }
+        if self.options.frame {
+            offset.x += 2;
+            offset.y += 1;
+        }
+        offset.x += self.options.outer_padding.left as i32;
+        offset.y += self.options.outer_padding.top as i32;
+        offset
+    }
+
+    /// Node, edge and grid counts with the phase timings, for tuning options
+    /// on large diagrams. Call [`Graph::layout`] first.
+    pub fn stats(&self) -> LayoutStats {
+        LayoutStats {
+            nodes: self.nodes.len(),
+            edges: self.edges.len(),
+            grid: (self.column_width.len(), self.row_height.len()),
+            phases: self.timings.clone(),
+        }
+    }
+
+    /// The layout as text for regression comparisons: each node's grid
+    /// coordinate, every occupied grid cell, and the column widths and row
+    /// heights, one fact per line in a fixed order. Call [`Graph::layout`]
+    /// first.
+    pub fn grid_snapshot(&self) -> String {
+        let direction = if self.direction == GraphDirection::Lr { "lr" } else { "td" };
+        let mut lines = vec![format!("direction {}", direction)];
+        for node in &self.nodes {
+            if let Some(coord) = node.grid_coord {
+                lines.push(format!("node {},{} {}", coord.x, coord.y, node.name));
+            }
+        }
+        let mut cells: Vec<(&GridCoord, &usize)> = self.grid.iter().collect();
+        cells.sort_by_key(|(coord, _)| (coord.y, coord.x));
+        for (coord, idx) in cells {
+            lines.push(format!("cell {},{} {}", coord.x, coord.y, self.nodes[*idx].name));
+        }
+        let columns: BTreeMap<_, _> = self.column_width.iter().collect();
+        lines.extend(columns.iter().map(|(x, width)| format!("column {} {}", x, width)));
+        let rows: BTreeMap<_, _> = self.row_height.iter().collect();
+        lines.extend(rows.iter().map(|(y, height)| format!("row {} {}", y, height)));
+        lines.join("\n") + "\n"
+    }
+
+    /// With `edges_under_nodes` the boxes are pasted whole, blanking any line
+    /// that strayed into them; otherwise they are merged like other layers.
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "info", skip_all))]
+    fn draw_nodes(&self, base: &mut Drawing) {
         for node in &self.nodes {
             if let (Some(coord), Some(node_drawing)) = (&node.drawing_coord, &node.drawing) {
-                base.overlay(node_drawing, *coord, self.options.use_ascii);
+                if self.options.edges_under_nodes {
+                    base.paste(node_drawing, *coord);
+                } else {
+                    base.overlay(node_drawing, *coord, self.ascii_lines());
+                }
             }
         }
+    }
 
-        self.draw_edges(&mut base);
-        base
+    /// Puts a `▒` shadow one cell right of and below the nodes whose class
+    /// sets `shadow:true` or an `elevation` above 0. It goes under
+    /// everything else, so edges leaving the node still show.
+    fn draw_shadows(&self, base: &mut Drawing) {
+        let shade = if self.options.use_ascii { "#" } else { "▒" };
+        for node in &self.nodes {
+            let (Some(coord), Some(node_drawing)) = (&node.drawing_coord, &node.drawing) else {
+                continue;
+            };
+            if !node.style_class.as_ref().is_some_and(has_shadow) {
+                continue;
+            }
+            let (width, height) = node_drawing.size();
+            let (right, bottom) = (coord.x + width as i32 + 1, coord.y + height as i32 + 1);
+            for y in coord.y + 1..=bottom {
+                base.set(DrawingCoord { x: right, y }, shade);
+            }
+            for x in coord.x + 1..right {
+                base.set(DrawingCoord { x, y: bottom }, shade);
+            }
+        }
     }
 
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "info", skip_all))]
     fn draw_edges(&self, drawing: &mut Drawing) {
         let mut line_layer = self.drawing.blank_like();
         let mut corner_layer = self.drawing.blank_like();
         let mut arrow_head_layer = self.drawing.blank_like();
-        let mut box_start_layer = self.drawing.blank_like();
+        // Border cells edges attach to, with the arms of all of them, so
+        // several edges on one cell combine into one junction.
+        let mut connections: HashMap<DrawingCoord, usize> = HashMap::new();
         let mut label_layer = self.drawing.blank_like();
+        let mut braille = BrailleCanvas::new();
+        let mut braille_endpoints = HashSet::new();
+        let mut label_margins = Vec::new();
 
         for edge in &self.edges {
             if edge.path.is_empty() {
                 continue;
             }
-            let (path_lines, lines_drawn, line_dirs) = self.draw_path(&edge.path);
-            line_layer.overlay(&path_lines, DrawingCoord { x: 0, y: 0 }, self.options.use_ascii);
+            let (path_lines, lines_drawn, line_dirs) = self.draw_path(edge);
+            if self.options.braille {
+                self.plot_braille_path(&edge.path, &mut braille, &mut braille_endpoints);
+            } else {
+                line_layer.overlay(&path_lines, DrawingCoord { x: 0, y: 0 }, self.ascii_lines());
+            }
 
-            if let Some(first_line) = lines_drawn.first() {
-                let box_start = self.draw_box_start(&edge.path, first_line);
-                box_start_layer.overlay(
-                    &box_start,
-                    DrawingCoord { x: 0, y: 0 },
-                    self.options.use_ascii,
-                );
+            if !self.options.braille && !self.ascii_lines() {
+                for (cell, arm) in self.border_attachments(edge) {
+                    *connections.entry(cell).or_insert(0) |= arm;
+                }
             }
 
-            if let Some(last_line) = lines_drawn.last() {
+            if let (Some(last_line), Terminator::Arrow) = (lines_drawn.last(), edge.terminator) {
                 let fallback = *line_dirs.last().unwrap_or(&Direction::Right);
                 let arrow_head = self.draw_arrow_head(last_line, fallback);
                 arrow_head_layer.overlay(
                     &arrow_head,
                     DrawingCoord { x: 0, y: 0 },
-                    self.options.use_ascii,
+                    self.ascii_lines(),
                 );
             }
+            let ends = [
+                (edge.start_terminator, lines_drawn.first().and_then(|line| line.first())),
+                (edge.terminator, lines_drawn.last().and_then(|line| line.last())),
+            ];
+            for (terminator, cell) in ends {
+                if let (Some(glyph), Some(cell)) = (self.marker_glyph(terminator), cell) {
+                    arrow_head_layer.set(*cell, glyph);
+                }
+            }
 
-            let corners = self.draw_corners(&edge.path);
-            corner_layer.overlay(
-                &corners,
-                DrawingCoord { x: 0, y: 0 },
-                self.options.use_ascii,
-            );
+            if !self.options.braille {
+                let corners = self.draw_corners(&edge.path, edge.stroke);
+                corner_layer.overlay(
+                    &corners,
+                    DrawingCoord { x: 0, y: 0 },
+                    self.ascii_lines(),
+                );
+            }
 
-            let label = self.draw_arrow_label(edge);
+            let (label, margins) = self.draw_arrow_label(edge);
+            label_margins.extend(margins);
             label_layer.overlay(
                 &label,
                 DrawingCoord { x: 0, y: 0 },
-                self.options.use_ascii,
+                self.ascii_lines(),
             );
         }
 
-        drawing.overlay(&line_layer, DrawingCoord { x: 0, y: 0 }, self.options.use_ascii);
-        drawing.overlay(&corner_layer, DrawingCoord { x: 0, y: 0 }, self.options.use_ascii);
+        for (coord, ch) in braille.cells() {
+            if !braille_endpoints.contains(&coord) {
+                line_layer.set(coord, ch);
+            }
+        }
+
+        drawing.overlay(&line_layer, DrawingCoord { x: 0, y: 0 }, self.ascii_lines());
+        drawing.overlay(&corner_layer, DrawingCoord { x: 0, y: 0 }, self.ascii_lines());
         drawing.overlay(
             &arrow_head_layer,
             DrawingCoord { x: 0, y: 0 },
-            self.options.use_ascii,
+            self.ascii_lines(),
         );
-        drawing.overlay(&box_start_layer, DrawingCoord { x: 0, y: 0 }, self.options.use_ascii);
-        drawing.overlay(&label_layer, DrawingCoord { x: 0, y: 0 }, self.options.use_ascii);
+        if self.options.edges_under_nodes {
+            self.draw_nodes(drawing);
+        }
+        for (cell, arms) in connections {
+            drawing.add_arms(cell, arms);
+        }
+        drawing.overlay(&label_layer, DrawingCoord { x: 0, y: 0 }, self.ascii_lines());
+        drawing.clear_lines_at(&label_margins);
     }
 
-    fn draw_path(
+    // The border cells a path starts and ends on are recorded so the boxes
+    // stay intact once the dots are copied onto the line layer.
+    fn plot_braille_path(
         &self,
         path: &[GridCoord],
-    ) -> (Drawing, Vec<Vec<DrawingCoord>>, Vec<Direction>) {
+        canvas: &mut BrailleCanvas,
+        endpoints: &mut HashSet<DrawingCoord>,
+    ) {
+        let points = self.line_to_drawing(path);
+        if let (Some(first), Some(last)) = (points.first(), points.last()) {
+            endpoints.insert(*first);
+            endpoints.insert(*last);
+        }
+        for pair in points.windows(2) {
+            canvas.line(
+                BrailleCanvas::cell_center(pair[0]),
+                BrailleCanvas::cell_center(pair[1]),
+            );
+        }
+    }
+
+    fn draw_path(&self, edge: &Edge) -> (Drawing, Vec<Vec<DrawingCoord>>, Vec<Direction>) {
+        let path = &edge.path;
         let mut d = self.drawing.blank_like();
         let mut lines_drawn = Vec::new();
         let mut line_dirs = Vec::new();
@@ -719,8 +1887,26 @@ impl Graph {
                     y: next.y,
                 },
             );
-            let mut segment =
-                d.draw_line(prev_coord, next_coord, 1, -1, self.options.use_ascii);
+            let mut segment = match edge.line_style {
+                // Invisible edges are not routed, so never get here.
+                LineStyle::Solid | LineStyle::Invisible => d.draw_line(
+                    prev_coord,
+                    next_coord,
+                    1,
+                    -1,
+                    edge.stroke,
+                    self.ascii_lines(),
+                ),
+                LineStyle::Dotted => d.draw_dashed_line(
+                    prev_coord,
+                    next_coord,
+                    1,
+                    -1,
+                    &DOTTED_DASHES,
+                    edge.stroke,
+                    self.ascii_lines(),
+                ),
+            };
             if segment.is_empty() {
                 segment.push(prev_coord);
             }
@@ -732,58 +1918,39 @@ impl Graph {
         (d, lines_drawn, line_dirs)
     }
 
-    fn draw_box_start(
-        &self,
-        path: &[GridCoord],
-        first_line: &[DrawingCoord],
-    ) -> Drawing {
-        let mut d = self.drawing.blank_like();
-        if self.options.use_ascii || path.len() < 2 || first_line.is_empty() {
-            return d;
+    /// The border cells `edge` attaches to and the arm it adds to each: the
+    /// ends with no arrowhead or marker between them and the box.
+    fn border_attachments(&self, edge: &Edge) -> Vec<(DrawingCoord, usize)> {
+        let path = &edge.path;
+        let mut attachments = Vec::new();
+        if path.len() < 2 {
+            return attachments;
         }
-        let from = first_line[0];
-        let dir = determine_direction(
-            GenericCoord {
-                x: path[0].x,
-                y: path[0].y,
-            },
-            GenericCoord {
-                x: path[1].x,
-                y: path[1].y,
-            },
-        );
-        match dir {
-            Direction::Up => d.set(
-                DrawingCoord {
-                    x: from.x,
-                    y: from.y + 1,
-                },
-                "┴",
-            ),
-            Direction::Down => d.set(
-                DrawingCoord {
-                    x: from.x,
-                    y: from.y - 1,
-                },
-                "┬",
-            ),
-            Direction::Left => d.set(
-                DrawingCoord {
-                    x: from.x + 1,
-                    y: from.y,
-                },
-                "┤",
-            ),
-            Direction::Right => d.set(
-                DrawingCoord {
-                    x: from.x - 1,
-                    y: from.y,
-                },
-                "├",
-            ),
-            _ => {}
+        let towards = |from: GridCoord, to: GridCoord| {
+            arm_towards(determine_direction(
+                GenericCoord { x: from.x, y: from.y },
+                GenericCoord { x: to.x, y: to.y },
+            ))
+        };
+        if edge.start_terminator == Terminator::None {
+            attachments.push((self.grid_to_drawing_coord(path[0], None), towards(path[0], path[1])));
+        }
+        if edge.terminator == Terminator::None {
+            let (end, before) = (path[path.len() - 1], path[path.len() - 2]);
+            attachments.push((self.grid_to_drawing_coord(end, None), towards(end, before)));
+        }
+        attachments
+    }
+
+    /// The glyph drawn for a `--o` or `--x` end.
+    fn marker_glyph(&self, terminator: Terminator) -> Option<&'static str> {
+        match (terminator, self.ascii_lines()) {
+            (Terminator::Circle, true) => Some("o"),
+            (Terminator::Circle, false) => Some("●"),
+            (Terminator::Cross, true) => Some("x"),
+            (Terminator::Cross, false) => Some("✕"),
+            _ => None,
         }
-        d
     }
 
     fn draw_arrow_head(
@@ -811,7 +1978,7 @@ impl Graph {
             dir = fallback;
         }
 
-        let char = if self.options.use_ascii {
+        let char = if self.ascii_lines() {
             match dir {
                 Direction::Up => "^",
                 Direction::Down => "v",
@@ -853,7 +2020,7 @@ impl Graph {
         d
     }
 
-    fn draw_corners(&self, path: &[GridCoord]) -> Drawing {
+    fn draw_corners(&self, path: &[GridCoord], stroke: Stroke) -> Drawing {
         let mut d = self.drawing.blank_like();
         if path.len() < 3 {
             return d;
@@ -882,7 +2049,7 @@ impl Graph {
                 },
             );
 
-            let corner = if self.options.use_ascii {
+            let corner = if self.ascii_lines() {
                 "+"
             } else {
                 match (prev_dir, next_dir) {
@@ -897,20 +2064,372 @@ impl Graph {
                     _ => "+",
                 }
             };
+            let corner = stroke.corner(corner);
 
             d.set(drawing_coord, corner);
         }
         d
     }
 
-    fn draw_arrow_label(&self, edge: &Edge) -> Drawing {
+    /// Draws the edge label and returns it with the cells on either side of
+    /// the text and the spaces inside it, which get cleared of line segments
+    /// when `clear_label_background` is set. Labels of edges that share their
+    /// label line are stacked one row apart, left-aligned at the widest one,
+    /// with a leader joining each row to the line.
+    fn draw_arrow_label(&self, edge: &Edge) -> (Drawing, Vec<DrawingCoord>) {
         let mut d = self.drawing.blank_like();
         if edge.text.is_empty() || edge.label_line.len() < 2 {
-            return d;
+            return (d, Vec::new());
         }
         let drawing_line = self.line_to_drawing(&edge.label_line);
-        d.draw_text_on_line(&drawing_line, &edge.text);
-        d
+        let stack = self.label_stack(edge);
+        let rows = label_size(&edge.text).1;
+        let leader = |row: usize| match (self.ascii_lines(), row + 1 == stack.rows) {
+            (true, _) => "+",
+            (false, true) => "└",
+            (false, false) => "├",
+        };
+        if self.label_beside(&edge.label_line) {
+            let (top, bottom) = (drawing_line[0].y, drawing_line[1].y);
+            let start = DrawingCoord {
+                x: drawing_line[0].x + 2,
+                y: top.min(bottom) + (top - bottom).abs() / 2 + stack.row as i32 - (stack.rows as i32 - 1) / 2,
+            };
+            for (row, line) in edge.text.split('\n').enumerate() {
+                d.draw_text(DrawingCoord { x: start.x, y: start.y + row as i32 }, line);
+            }
+            if stack.count > 1 {
+                let dash = if self.ascii_lines() { "-" } else { "─" };
+                d.set(DrawingCoord { x: start.x - 2, y: start.y }, if self.ascii_lines() { "+" } else { "├" });
+                d.set(DrawingCoord { x: start.x - 1, y: start.y }, dash);
+            }
+            return (d, Vec::new());
+        }
+        let leader_width = if stack.count > 1 { 2 } else { 0 };
+        let Some(mut start) = text_start_on_line(&drawing_line, stack.widest + leader_width) else {
+            return (d, Vec::new());
+        };
+        start.x += leader_width as i32;
+        start.y += stack.row as i32;
+        let mut margins = Vec::new();
+        for (row, line) in edge.text.split('\n').enumerate() {
+            let mut at = DrawingCoord { x: start.x, y: start.y + row as i32 };
+            if stack.count == 1 {
+                at.x += (stack.widest - text_width(line)) as i32 / 2;
+            }
+            d.draw_text(at, line);
+            margins.push(DrawingCoord { x: at.x - 1, y: at.y });
+            margins.push(DrawingCoord { x: at.x + text_width(line) as i32, y: at.y });
+            let mut x = at.x;
+            for grapheme in line.graphemes(true) {
+                if grapheme == " " {
+                    margins.push(DrawingCoord { x, y: at.y });
+                }
+                x += text_width(grapheme) as i32;
+            }
+        }
+        if stack.count > 1 {
+            let on_line = DrawingCoord { x: start.x - 2, y: start.y - stack.row as i32 };
+            // Merges into a `┬` where the line runs under it.
+            let junction = if self.ascii_lines() { "+" } else { "┌" };
+            if stack.slot == 0 {
+                d.set(on_line, junction);
+            } else {
+                d.set(DrawingCoord { x: on_line.x, y: start.y }, leader(stack.row));
+            }
+            // The leader runs on past the label's other lines.
+            let pipe = if self.ascii_lines() { "|" } else { "│" };
+            for row in 1..rows {
+                let last = stack.row + row + 1 == stack.rows;
+                let glyph = if last && !self.ascii_lines() { "└" } else { pipe };
+                d.set(DrawingCoord { x: on_line.x, y: start.y + row as i32 }, glyph);
+            }
+        }
+        if !self.options.clear_label_background {
+            return (d, Vec::new());
+        }
+        (d, margins)
+    }
+
+    /// Where `edge`'s label goes among the labels drawn on the same line.
+    fn label_stack(&self, edge: &Edge) -> LabelStack {
+        let same_line = |other: &Edge| {
+            let (a, b) = (&edge.label_line, &other.label_line);
+            !other.text.is_empty() && (a == b || (a.len() == 2 && b.len() == 2 && a[0] == b[1] && a[1] == b[0]))
+        };
+        let stack: Vec<&Edge> = self.edges.iter().filter(|other| same_line(other)).collect();
+        let slot = stack
+            .iter()
+            .position(|other| std::ptr::eq(*other, edge))
+            .unwrap_or(0);
+        let rows_of = |other: &&Edge| label_size(&other.text).1;
+        let widest = stack.iter().map(|other| label_size(&other.text).0).max().unwrap_or(0);
+        LabelStack {
+            slot,
+            count: stack.len().max(1),
+            row: stack.iter().take(slot).map(rows_of).sum(),
+            rows: stack.iter().map(rows_of).sum::<usize>().max(label_size(&edge.text).1),
+            widest: widest.max(label_size(&edge.text).0),
+        }
+    }
+
+    /// Lists the numbered edges that carried no label of their own, so the
+    /// bare numbers on the drawing can still be tied back to their endpoints.
+    fn edge_legend(&self) -> Vec<String> {
+        if !self.options.number_edges {
+            return Vec::new();
+        }
+        let arrow = if self.options.use_ascii { "->" } else { "→" };
+        // Numbered the way `Graph::new` numbers them, skipping `~~~` links.
+        let mut edges: Vec<&Edge> =
+            self.edges.iter().filter(|edge| edge.line_style != LineStyle::Invisible).collect();
+        edges.sort_by_key(|edge| edge.order);
+        edges
+            .into_iter()
+            .enumerate()
+            .filter(|(number, edge)| edge.text == format!("{}.", number + 1))
+            .map(|(_, edge)| {
+                format!(
+                    "{} {} {} {}",
+                    edge.text, self.nodes[edge.from].label, arrow, self.nodes[edge.to].label
+                )
+            })
+            .collect()
+    }
+
+    fn label_legend(&self) -> Vec<String> {
+        if !self.options.shorten_labels {
+            return Vec::new();
+        }
+        self.nodes
+            .iter()
+            .filter(|node| node.label != node.name)
+            .map(|node| format!("{} = {}", node.label, node.name))
+            .collect()
+    }
+
+    /// One line per `classDef`, in name order: its styles, then the nodes
+    /// that use it.
+    fn class_legend(&self) -> Vec<String> {
+        if !self.options.class_legend {
+            return Vec::new();
+        }
+        let mut names: Vec<&String> = self.style_classes.keys().collect();
+        names.sort();
+        let dash = if self.options.use_ascii { "-" } else { "—" };
+        names
+            .into_iter()
+            .map(|name| {
+                let mut styles: Vec<(&String, &String)> = self.style_classes[name].styles.iter().collect();
+                styles.sort();
+                let styles: Vec<String> = styles.iter().map(|(key, value)| format!("{}:{}", key, value)).collect();
+                let nodes: Vec<&str> = self
+                    .nodes
+                    .iter()
+                    .filter(|node| node.class_names.contains(name))
+                    .map(|node| node.label.as_str())
+                    .collect();
+                let nodes = if nodes.is_empty() { "(unused)".to_string() } else { nodes.join(", ") };
+                format!("{}: {} {} {}", name, styles.join("; "), dash, nodes)
+            })
+            .collect()
+    }
+
+    /// A table of how each edge was routed: where its target sits relative to
+    /// its source, the preferred and alternative (start side, end side) pairs
+    /// with the turning points of their paths, and which one won.
+    fn explain_table(&self) -> Vec<String> {
+        if !self.options.explain {
+            return Vec::new();
+        }
+        let arrow = if self.options.use_ascii { "->" } else { "→" };
+        let pair = |(start, end, points): (Direction, Direction, usize)| {
+            format!("{}/{} ({} points)", direction_name(start), direction_name(end), points)
+        };
+        let mut rows = vec![[
+            "edge".to_string(),
+            "target is".to_string(),
+            "preferred".to_string(),
+            "alternative".to_string(),
+            "chosen".to_string(),
+            "cells".to_string(),
+        ]];
+        let mut edges: Vec<&Edge> = self.edges.iter().collect();
+        edges.sort_by_key(|edge| edge.order);
+        for edge in edges {
+            let Some(route) = &edge.route else {
+                continue;
+            };
+            let cells = path_cells(&edge.path);
+            rows.push([
+                format!("{} {} {}", self.nodes[edge.from].label, arrow, self.nodes[edge.to].label),
+                if edge.from == edge.to { "itself".to_string() } else { direction_name(route.placement).to_string() },
+                pair(route.preferred),
+                pair(route.alternative),
+                if route.return_channel {
+                    "return channel"
+                } else if route.used_preferred {
+                    "preferred"
+                } else {
+                    "alternative"
+                }
+                .to_string(),
+                cells.to_string(),
+            ]);
+        }
+        let widths: Vec<usize> = (0..6)
+            .map(|column| rows.iter().map(|row| text_width(&row[column])).max().unwrap_or(0))
+            .collect();
+        rows.iter()
+            .map(|row| {
+                let cells: Vec<String> = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| format!("{}{}", cell, " ".repeat(width - text_width(cell))))
+                    .collect();
+                cells.join("  ").trim_end().to_string()
+            })
+            .collect()
+    }
+
+    /// Whether lines and boxes are drawn with ASCII directly. The fancy ASCII
+    /// style draws them with box-drawing characters, which merge at
+    /// junctions, and transliterates the finished drawing.
+    fn ascii_lines(&self) -> bool {
+        self.options.use_ascii && self.options.ascii_style == AsciiStyle::Plain
+    }
+
+    /// Adds a dotted ruler along the main axis (above the drawing for LR,
+    /// left of it for TD) numbering the grid levels nodes were placed on.
+    fn with_rank_ruler(&self, drawing: Drawing) -> Drawing {
+        let horizontal = self.direction == GraphDirection::Lr;
+        let mut levels: Vec<i32> = self
+            .nodes
+            .iter()
+            .filter_map(|node| node.grid_coord)
+            .map(|coord| if horizontal { coord.x } else { coord.y })
+            .collect();
+        levels.sort_unstable();
+        levels.dedup();
+        let centers: Vec<(usize, i32)> = levels
+            .iter()
+            .enumerate()
+            .map(|(rank, level)| {
+                let center = self.grid_to_drawing_coord(GridCoord { x: level + 1, y: level + 1 }, None);
+                (rank, if horizontal { center.x } else { center.y })
+            })
+            .collect();
+
+        let dot = if self.options.use_ascii { "." } else { "·" };
+        let (max_x, max_y) = drawing.size();
+        if horizontal {
+            let mut ruled = Drawing::new(max_x, max_y + 2);
+            for x in 0..=max_x as i32 {
+                ruled.set(DrawingCoord { x, y: 0 }, dot);
+            }
+            for (rank, x) in centers {
+                let text = rank.to_string();
+                ruled.draw_text(DrawingCoord { x: x - text.len() as i32 / 2, y: 0 }, &text);
+            }
+            ruled.overlay(&drawing, DrawingCoord { x: 0, y: 2 }, self.ascii_lines());
+            ruled
+        } else {
+            let width = self.rank_ruler_width();
+            let mut ruled = Drawing::new(max_x + width as usize + 1, max_y);
+            for y in 0..=max_y as i32 {
+                ruled.set(DrawingCoord { x: width - 1, y }, dot);
+            }
+            for (rank, y) in centers {
+                let text = rank.to_string();
+                ruled.draw_text(DrawingCoord { x: width - text.len() as i32, y }, &text);
+            }
+            ruled.overlay(&drawing, DrawingCoord { x: width + 1, y: 0 }, self.ascii_lines());
+            ruled
+        }
+    }
+
+    /// The source lines to print beside each row holding the top of a box,
+    /// as `3→` or `3,7→` when boxes share the row. Rows are relative to the
+    /// drawing before the gutter is added.
+    fn source_gutter(&self) -> BTreeMap<i32, String> {
+        let mut rows: BTreeMap<i32, Vec<usize>> = BTreeMap::new();
+        for node in &self.nodes {
+            if let (Some(line), Some(coord)) = (self.node_lines.get(&node.name), node.drawing_coord) {
+                rows.entry(coord.y).or_default().push(*line);
+            }
+        }
+        let offset = self.margin_offset().y;
+        let marker = if self.options.use_ascii { ">" } else { "→" };
+        rows.into_iter()
+            .map(|(y, mut lines)| {
+                lines.sort_unstable();
+                lines.dedup();
+                let lines: Vec<String> = lines.iter().map(ToString::to_string).collect();
+                (y + offset, format!("{}{}", lines.join(","), marker))
+            })
+            .collect()
+    }
+
+    /// Columns the source gutter takes, including the space after it; none
+    /// when it is off or no node has a line.
+    fn source_gutter_width(&self) -> i32 {
+        if !self.options.source_map {
+            return 0;
+        }
+        let widest = self.source_gutter().values().map(|text| text_width(text)).max();
+        widest.map_or(0, |width| width as i32 + 1)
+    }
+
+    /// Adds the [`Graph::source_gutter`] left of `drawing`, the numbers
+    /// right-aligned against the diagram.
+    fn with_source_gutter(&self, drawing: Drawing) -> Drawing {
+        let width = self.source_gutter_width();
+        if width == 0 {
+            return drawing;
+        }
+        let (max_x, max_y) = drawing.size();
+        let mut guttered = Drawing::new(max_x + width as usize, max_y);
+        for (y, text) in self.source_gutter() {
+            guttered.draw_text(DrawingCoord { x: width - 1 - text_width(&text) as i32, y }, &text);
+        }
+        guttered.paste(&drawing, DrawingCoord { x: width, y: 0 });
+        guttered
+    }
+
+    /// Columns the TD rank ruler's numbers take.
+    fn rank_ruler_width(&self) -> i32 {
+        let levels: HashSet<i32> = self.nodes.iter().filter_map(|node| node.grid_coord).map(|coord| coord.y).collect();
+        levels.len().saturating_sub(1).to_string().len() as i32
+    }
+
+    /// Puts a border one cell clear of `drawing` on the sides, with the
+    /// title set into the top edge.
+    fn with_frame(&self, drawing: Drawing) -> Drawing {
+        let (max_x, max_y) = drawing.size();
+        let title = self.title.as_deref().unwrap_or("");
+        let inner = (max_x + 1).max(if title.is_empty() { 0 } else { text_width(title) + 2 });
+        let (right, bottom) = (inner as i32 + 3, max_y as i32 + 2);
+        let (horizontal, vertical) = if self.ascii_lines() { ("-", "|") } else { ("─", "│") };
+        let corners = if self.ascii_lines() { ["+"; 4] } else { ["┌", "┐", "└", "┘"] };
+
+        let mut framed = Drawing::new(right as usize, bottom as usize);
+        for x in 1..right {
+            framed.set(DrawingCoord { x, y: 0 }, horizontal);
+            framed.set(DrawingCoord { x, y: bottom }, horizontal);
+        }
+        for y in 1..bottom {
+            framed.set(DrawingCoord { x: 0, y }, vertical);
+            framed.set(DrawingCoord { x: right, y }, vertical);
+        }
+        framed.set(DrawingCoord { x: 0, y: 0 }, corners[0]);
+        framed.set(DrawingCoord { x: right, y: 0 }, corners[1]);
+        framed.set(DrawingCoord { x: 0, y: bottom }, corners[2]);
+        framed.set(DrawingCoord { x: right, y: bottom }, corners[3]);
+        if !title.is_empty() {
+            framed.draw_text(DrawingCoord { x: 2, y: 0 }, &format!(" {} ", title));
+        }
+        framed.paste(&drawing, DrawingCoord { x: 2, y: 1 });
+        framed
     }
 
     fn with_coords_overlay(&self, drawing: Drawing) -> Drawing {
@@ -931,27 +2450,59 @@ impl Graph {
         debug.overlay(
             &drawing,
             DrawingCoord { x: 1, y: 1 },
-            self.options.use_ascii,
+            self.ascii_lines(),
         );
         debug
     }
 
+    /// Routes with the configured clearance around unrelated nodes, falling
+    /// back to hugging them when the margins leave no way through.
+    /// Searches once per start, end and pair of nodes: a straight edge's
+    /// preferred and alternative routes are the same search, as are the
+    /// routes of parallel edges.
+    fn get_clear_path(
+        &mut self,
+        from: GridCoord,
+        to: GridCoord,
+        endpoints: (usize, usize),
+    ) -> Result<Vec<GridCoord>> {
+        let key = (from, to, endpoints);
+        let path = match self.path_cache.get(&key) {
+            Some(path) => path.clone(),
+            None => {
+                let clearance = self.options.edge_clearance.max(0);
+                let mut scratch = std::mem::take(&mut self.search_scratch);
+                let path = (clearance > 0)
+                    .then(|| self.get_path(from, to, endpoints, clearance, &mut scratch).ok())
+                    .flatten()
+                    .or_else(|| self.get_path(from, to, endpoints, 0, &mut scratch).ok());
+                self.search_scratch = scratch;
+                self.path_cache.insert(key, path.clone());
+                path
+            }
+        };
+        path.ok_or_else(|| anyhow!("no path found from {:?} to {:?}", from, to))
+    }
+
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "info", skip_all))]
     fn get_path(
         &self,
         from: GridCoord,
         to: GridCoord,
+        endpoints: (usize, usize),
+        clearance: i32,
+        scratch: &mut SearchScratch,
     ) -> Result<Vec<GridCoord>> {
-        let mut frontier = BinaryHeap::new();
-        frontier.push(QueueItem {
-            priority: 0,
-            coord: from,
-        });
-
-        let mut came_from: HashMap<GridCoord, GridCoord> = HashMap::new();
-        let mut cost_so_far: HashMap<GridCoord, i32> = HashMap::new();
-        came_from.insert(from, from);
-        cost_so_far.insert(from, 0);
-
+        let built;
+        let map = match self.clearance_maps.iter().find(|map| map.clearance == clearance) {
+            Some(map) => map,
+            None => {
+                built = ClearanceMap::new(&self.grid, clearance);
+                &built
+            }
+        };
+        let limit = map.search_limit(from, to);
+        let turn_penalty = self.options.turn_penalty.max(0);
         let directions = [
             GridCoord { x: 1, y: 0 },
             GridCoord { x: -1, y: 0 },
@@ -959,68 +2510,83 @@ impl Graph {
             GridCoord { x: 0, y: -1 },
         ];
 
+        // A state is a cell with the direction it was entered from, so a
+        // step that changes direction can cost the turn penalty extra. Cells
+        // left of or above the grid can only be `from` or `to`.
+        let origin = GridCoord { x: from.x.min(to.x).min(0), y: from.y.min(to.y).min(0) };
+        let width = (limit.x - origin.x + 1) as usize;
+        let state_of = |coord: GridCoord, arrival: Option<usize>| {
+            (((coord.y - origin.y) as usize * width + (coord.x - origin.x) as usize) * 5) + arrival.unwrap_or(4)
+        };
+        let coord_of = |state: usize| GridCoord {
+            x: (state / 5 % width) as i32 + origin.x,
+            y: (state / 5 / width) as i32 + origin.y,
+        };
+        scratch.start(width * (limit.y - origin.y + 1) as usize * 5);
+        let start = state_of(from, None);
+        scratch.relax(start, 0, start);
+        let mut frontier = BinaryHeap::new();
+        frontier.push(QueueItem {
+            priority: 0,
+            coord: from,
+            arrival: None,
+        });
+
         while let Some(current) = frontier.pop() {
+            let state = state_of(current.coord, current.arrival);
             if current.coord == to {
-                let mut path = Vec::new();
-                let mut curr = current.coord;
-                path.push(curr);
-                while curr != from {
-                    curr = came_from[&curr];
-                    path.push(curr);
+                let mut path = vec![current.coord];
+                let mut curr = state;
+                while curr != start {
+                    curr = scratch.came_from[curr];
+                    path.push(coord_of(curr));
                 }
                 path.reverse();
                 return Ok(path);
             }
 
-            for dir in &directions {
+            for (idx, dir) in directions.iter().enumerate() {
                 let next = GridCoord {
                     x: current.coord.x + dir.x,
                     y: current.coord.y + dir.y,
                 };
 
-                if !self.is_free_in_grid(next) && next != to {
+                if next.x > limit.x || next.y > limit.y {
+                    continue;
+                }
+                if next != to && !map.is_free(next, endpoints) {
                     continue;
                 }
 
-                let new_cost = cost_so_far[&current.coord] + 1;
-                if cost_so_far
-                    .get(&next)
-                    .map(|cost| new_cost < *cost)
-                    .unwrap_or(true)
-                {
-                    cost_so_far.insert(next, new_cost);
+                let turn = current.arrival.is_some_and(|arrival| arrival != idx);
+                let new_cost = scratch.costs[state] + 1 + if turn { turn_penalty } else { 0 };
+                let next_state = state_of(next, Some(idx));
+                if scratch.cost(next_state).is_none_or(|cost| new_cost < cost) {
+                    scratch.relax(next_state, new_cost, state);
                     let priority = new_cost + heuristic(next, to);
-                    frontier.push(QueueItem { priority, coord: next });
-                    came_from.insert(next, current.coord);
+                    frontier.push(QueueItem { priority, coord: next, arrival: Some(idx) });
                 }
             }
         }
 
         Err(anyhow!("no path found from {:?} to {:?}", from, to))
     }
-
-    fn is_free_in_grid(&self, coord: GridCoord) -> bool {
-        if coord.x < 0 || coord.y < 0 {
-            return false;
-        }
-        !self.grid.contains_key(&coord)
-    }
 }
 
-fn draw_box(node: &Node, graph: &Graph) -> Drawing {
-    let coord = node.grid_coord.expect("node must have coord");
+fn draw_box(node: &Node, coord: GridCoord, graph: &Graph) -> Drawing {
+    let (cols, rows) = node.footprint;
     let mut width = 0;
-    for i in 0..2 {
+    for i in 0..cols - 1 {
         width += graph.column_width.get(&(coord.x + i)).copied().unwrap_or(0);
     }
     let mut height = 0;
-    for i in 0..2 {
+    for i in 0..rows - 1 {
         height += graph.row_height.get(&(coord.y + i)).copied().unwrap_or(0);
     }
 
     let mut drawing = Drawing::new(width as usize, height as usize);
 
-    if graph.options.use_ascii {
+    if graph.ascii_lines() {
         for x in 1..width {
             drawing.set(DrawingCoord { x, y: 0 }, "-");
             drawing.set(DrawingCoord { x, y: height }, "-");
@@ -1047,20 +2613,345 @@ fn draw_box(node: &Node, graph: &Graph) -> Drawing {
         drawing.set(DrawingCoord { x: 0, y: height }, "└");
         drawing.set(DrawingCoord { x: width, y: height }, "┘");
     }
+    draw_shape_sides(&mut drawing, node.shape, graph.ascii_lines());
+    if let Some(stroke) = node.style_class.as_ref().and_then(|class| class_color(class, "stroke", graph)) {
+        color_border(&mut drawing, stroke);
+    }
 
-    let text_y = height / 2;
-    let text_x = width / 2 - (node.name.chars().count() as i32) / 2 + 1;
-    drawing.draw_text(
-        DrawingCoord {
-            x: text_x,
-            y: text_y,
-        },
-        &node.name,
-    );
+    if node.shape == NodeShape::Record {
+        draw_record(&mut drawing, node, graph);
+        return drawing;
+    }
+
+    // The row edges attach to, which is below the middle when a taller
+    // record in the same row makes the height even.
+    let row_height = |y: i32| graph.row_height.get(&y).copied().unwrap_or(0);
+    let text_y = row_height(coord.y) + row_height(coord.y + 1) / 2;
+    let text_len = text_width(&node.label) as i32;
+    let text_x = match node.shape {
+        // Clear of the notch when there is room.
+        NodeShape::Flag => centered_start(width, text_len).max(2).min(width - text_len).max(1),
+        _ => centered_start(width, text_len),
+    };
+    let text_start = DrawingCoord {
+        x: text_x,
+        y: text_y,
+    };
+    drawing.draw_text(text_start, &node.label);
+    if let Some(class) = &node.style_class {
+        drawing.style_text(text_start, &node.label, text_style(class, graph));
+    }
 
     drawing
 }
 
+/// The first column of `len` cells centered between the borders of a box
+/// whose right border is at `width`. An odd cell left over goes on the
+/// right; text too wide to fit starts right after the left border.
+fn centered_start(width: i32, len: i32) -> i32 {
+    let inside = width - 1;
+    1 + (inside - len).max(0) / 2
+}
+
+/// A record's title centered on the first text row, a line across the box
+/// under it, then its rows from the left.
+fn draw_record(drawing: &mut Drawing, node: &Node, graph: &Graph) {
+    let (width, _) = drawing.size();
+    let width = width as i32;
+    let padding = graph.options.border_padding;
+    let style = node.style_class.as_ref().map(|class| text_style(class, graph)).unwrap_or_default();
+    let lines = record_lines(&node.label);
+    let title_len = text_width(&lines[0]) as i32;
+    let title = DrawingCoord {
+        x: centered_start(width, title_len),
+        y: 1 + padding,
+    };
+    drawing.draw_text(title, &lines[0]);
+    drawing.style_text(title, &lines[0], style);
+
+    let separator = title.y + 1;
+    let (horizontal, ends) = if graph.ascii_lines() { ("-", ["+", "+"]) } else { ("─", ["├", "┤"]) };
+    for x in 1..width {
+        drawing.set(DrawingCoord { x, y: separator }, horizontal);
+    }
+    drawing.set(DrawingCoord { x: 0, y: separator }, ends[0]);
+    drawing.set(DrawingCoord { x: width, y: separator }, ends[1]);
+
+    for (idx, line) in lines.iter().skip(1).enumerate() {
+        let start = DrawingCoord {
+            x: 1 + padding,
+            y: separator + 1 + idx as i32,
+        };
+        drawing.draw_text(start, line);
+        drawing.style_text(start, line, style);
+    }
+}
+
+/// Grid cells a node covers. A record whose rows would reach the boxes one
+/// or more levels further down covers those levels too, so routing treats
+/// it as the taller obstacle it is. Heights are whole levels, which keeps
+/// the free row between nodes four cells apart.
+fn footprint(node: &Node, border_padding: i32, padding_y: i32) -> (i32, i32) {
+    if node.shape != NodeShape::Record {
+        return (3, 3);
+    }
+    let band = 1 + 2 * border_padding;
+    let inner = record_lines(&node.label).len() as i32 + 1 + 2 * border_padding;
+    let levels = (inner - band).max(0) / (band + 2 + padding_y).max(1);
+    (3, 3 + 4 * levels)
+}
+
+fn footprint_cells(coord: GridCoord, (cols, rows): (i32, i32)) -> impl Iterator<Item = GridCoord> {
+    (0..cols).flat_map(move |dx| (0..rows).map(move |dy| GridCoord { x: coord.x + dx, y: coord.y + dy }))
+}
+
+/// The text lines of a record label: the title, then one line per row with
+/// the values of `key: value` rows lined up after the longest key.
+fn record_lines(label: &str) -> Vec<String> {
+    let mut parts = label.split('|').map(str::trim);
+    let mut lines = vec![parts.next().unwrap_or("").to_string()];
+    let rows: Vec<(&str, Option<&str>)> = parts
+        .map(|row| match row.split_once(':') {
+            Some((key, value)) => (key.trim(), Some(value.trim())),
+            None => (row, None),
+        })
+        .collect();
+    let key_width = rows
+        .iter()
+        .filter(|(_, value)| value.is_some())
+        .map(|(key, _)| text_width(key))
+        .max()
+        .unwrap_or(0);
+    for (key, value) in rows {
+        lines.push(match value {
+            Some(value) => format!("{}:{} {}", key, " ".repeat(key_width - text_width(key)), value),
+            None => key.to_string(),
+        });
+    }
+    lines
+}
+
+/// The SGR attributes a `classDef` asks for: bold for `font-weight:bold`
+/// (or a weight of 600 and up), italic for `font-style:italic` or `oblique`
+/// and the foreground from `color`.
+fn text_style(class: &StyleClass, graph: &Graph) -> TextStyle {
+    let weight = class.styles.get("font-weight").map(|value| value.trim().to_ascii_lowercase());
+    let font_style = class.styles.get("font-style").map(|value| value.trim().to_ascii_lowercase());
+    TextStyle {
+        bold: weight.is_some_and(|weight| {
+            matches!(weight.as_str(), "bold" | "bolder") || weight.parse::<u32>().is_ok_and(|weight| weight >= 600)
+        }),
+        italic: font_style.is_some_and(|style| matches!(style.as_str(), "italic" | "oblique")),
+        inverse: false,
+        dim: false,
+        foreground: class_color(class, "color", graph),
+    }
+}
+
+/// The `#rgb` or `#rrggbb` color a `classDef` gives `key`, matched to the
+/// color theme unless the options ask for truecolor.
+fn class_color(class: &StyleClass, key: &str, graph: &Graph) -> Option<Color> {
+    let hex = class.styles.get(key)?.trim().strip_prefix('#')?;
+    let digits: Vec<u8> = hex.chars().map(|c| c.to_digit(16).map(|digit| digit as u8)).collect::<Option<_>>()?;
+    let (r, g, b) = match digits[..] {
+        [r, g, b] => (r * 17, g * 17, b * 17),
+        [r1, r2, g1, g2, b1, b2] => (r1 * 16 + r2, g1 * 16 + g2, b1 * 16 + b2),
+        _ => return None,
+    };
+    Some(if graph.options.truecolor {
+        Color::Rgb(r, g, b)
+    } else {
+        graph.options.color_theme.nearest((r, g, b))
+    })
+}
+
+/// Gives the border of a box the foreground `color`.
+fn color_border(drawing: &mut Drawing, color: Color) {
+    let (width, height) = drawing.size();
+    let (width, height) = (width as i32, height as i32);
+    for x in 0..=width {
+        for y in 0..=height {
+            let coord = DrawingCoord { x, y };
+            if (x == 0 || y == 0 || x == width || y == height) && drawing.get(coord) != " " {
+                let cell = drawing.get(coord).to_string();
+                let style = TextStyle { foreground: Some(color), ..drawing.style(coord) };
+                drawing.style_text(coord, &cell, style);
+            }
+        }
+    }
+}
+
+/// Dots the border of a box for `--reachable-from`, or with `--color`
+/// shows the whole box faint instead.
+fn dim_border(drawing: &mut Drawing, graph: &Graph) {
+    let (width, height) = drawing.size();
+    let (width, height) = (width as i32, height as i32);
+    let dot = if graph.options.use_ascii { "." } else { "·" };
+    for x in 0..=width {
+        for y in 0..=height {
+            let coord = DrawingCoord { x, y };
+            if graph.options.color {
+                let cell = drawing.get(coord).to_string();
+                let style = TextStyle { dim: true, ..drawing.style(coord) };
+                drawing.style_text(coord, &cell, style);
+            } else if (x == 0 || y == 0 || x == width || y == height) && drawing.get(coord) != " " {
+                drawing.set(coord, dot);
+            }
+        }
+    }
+}
+
+/// Thickens the border of a box for `--highlight`, and with `--color`
+/// shows the whole box in inverse video.
+fn emphasize_border(drawing: &mut Drawing, graph: &Graph) {
+    let (width, height) = drawing.size();
+    let (width, height) = (width as i32, height as i32);
+    for x in 0..=width {
+        for y in 0..=height {
+            let coord = DrawingCoord { x, y };
+            let heavy = match drawing.get(coord) {
+                "─" => Some("━"),
+                "│" => Some("┃"),
+                "├" => Some("┣"),
+                "┤" => Some("┫"),
+                "┌" => Some("┏"),
+                "┐" => Some("┓"),
+                "└" => Some("┗"),
+                "┘" => Some("┛"),
+                "-" => Some("="),
+                "+" | "|" => Some("#"),
+                _ => None,
+            };
+            if let Some(heavy) = heavy.filter(|_| x == 0 || y == 0 || x == width || y == height) {
+                drawing.set(coord, heavy);
+            }
+            if graph.options.color {
+                let cell = drawing.get(coord).to_string();
+                let style = TextStyle { inverse: true, ..drawing.style(coord) };
+                drawing.style_text(coord, &cell, style);
+            }
+        }
+    }
+}
+
+fn has_shadow(class: &StyleClass) -> bool {
+    let value = |key: &str| class.styles.get(key).map(|value| value.trim().to_ascii_lowercase());
+    value("shadow").is_some_and(|shadow| shadow == "true")
+        || value("elevation").is_some_and(|elevation| elevation.parse::<u32>().is_ok_and(|level| level > 0))
+}
+
+/// Redraws the left and right sides of a rectangle drawn by [`draw_box`] for
+/// shapes that are not rectangles: a `>` notch for flags, and `/` or `\`
+/// slopes with the short edge pulled in for trapezoids.
+fn draw_shape_sides(drawing: &mut Drawing, shape: NodeShape, ascii: bool) {
+    let (width, height) = drawing.size();
+    let (width, height) = (width as i32, height as i32);
+    let horizontal = if ascii { "-" } else { "─" };
+    let (left, right, short_edge) = match shape {
+        NodeShape::Rectangle | NodeShape::Record | NodeShape::Spacer => return,
+        NodeShape::Flag => {
+            let middle = height / 2;
+            for y in 1..height {
+                let notch = match y.cmp(&middle) {
+                    Ordering::Less => "\\",
+                    Ordering::Equal => ">",
+                    Ordering::Greater => "/",
+                };
+                drawing.set(DrawingCoord { x: 0, y }, notch);
+            }
+            drawing.set(DrawingCoord { x: 0, y: 0 }, horizontal);
+            drawing.set(DrawingCoord { x: 0, y: height }, horizontal);
+            return;
+        }
+        NodeShape::Trapezoid => ("/", "\\", 0),
+        NodeShape::ReverseTrapezoid => ("\\", "/", height),
+    };
+    for y in 1..height {
+        drawing.set(DrawingCoord { x: 0, y }, left);
+        drawing.set(DrawingCoord { x: width, y }, right);
+    }
+    let long_edge = height - short_edge;
+    for x in [0, width] {
+        drawing.set(DrawingCoord { x, y: short_edge }, " ");
+        drawing.set(DrawingCoord { x, y: long_edge }, horizontal);
+    }
+}
+
+fn direction_name(dir: Direction) -> &'static str {
+    match dir {
+        Direction::Up => "up",
+        Direction::Down => "down",
+        Direction::Left => "left",
+        Direction::Right => "right",
+        Direction::UpperRight => "upper-right",
+        Direction::UpperLeft => "upper-left",
+        Direction::LowerRight => "lower-right",
+        Direction::LowerLeft => "lower-left",
+        Direction::Middle => "middle",
+    }
+}
+
+/// Abbreviates names for `--shorten-labels`: multi-word names become their
+/// initials, long single words their first three characters. Collisions get a
+/// numeric suffix, and names that would not get shorter are kept as is.
+fn shorten_names(names: &[String]) -> Vec<String> {
+    let mut taken: HashSet<String> = names.iter().cloned().collect();
+    names
+        .iter()
+        .map(|name| {
+            let words: Vec<&str> = name
+                .split(|c: char| c.is_whitespace() || c == '_' || c == '-')
+                .filter(|w| !w.is_empty())
+                .collect();
+            let base: String = if words.len() > 1 {
+                words
+                    .iter()
+                    .filter_map(|w| w.graphemes(true).next())
+                    .map(str::to_uppercase)
+                    .collect()
+            } else {
+                name.graphemes(true).take(3).collect()
+            };
+            let mut short = base.clone();
+            let mut suffix = 1;
+            while taken.contains(&short) && short != *name {
+                suffix += 1;
+                short = format!("{}{}", base, suffix);
+            }
+            if short.graphemes(true).count() >= name.graphemes(true).count() {
+                return name.clone();
+            }
+            taken.insert(short.clone());
+            short
+        })
+        .collect()
+}
+
+/// Grid cells a path of turning points runs through.
+fn path_cells(path: &[GridCoord]) -> i32 {
+    path.windows(2)
+        .map(|step| (step[1].x - step[0].x).abs() + (step[1].y - step[0].y).abs())
+        .sum()
+}
+
+/// Cuts out the detour wherever a path comes back to a cell it already
+/// passed through.
+fn without_loops(path: Vec<GridCoord>) -> Vec<GridCoord> {
+    let mut result: Vec<GridCoord> = Vec::with_capacity(path.len());
+    let mut seen: HashMap<GridCoord, usize> = HashMap::new();
+    for coord in path {
+        if let Some(&idx) = seen.get(&coord) {
+            for dropped in result.drain(idx + 1..) {
+                seen.remove(&dropped);
+            }
+            continue;
+        }
+        seen.insert(coord, result.len());
+        result.push(coord);
+    }
+    result
+}
+
 fn merge_path(path: Vec<GridCoord>) -> Vec<GridCoord> {
     if path.len() <= 2 {
         return path;
@@ -1098,10 +2989,133 @@ fn heuristic(a: GridCoord, b: GridCoord) -> i32 {
     }
 }
 
+/// Which grid cells an edge may cross at one clearance: for each cell, the
+/// nodes close enough that only their own edges may pass it.
+#[derive(Clone, Debug)]
+struct ClearanceMap {
+    clearance: i32,
+    /// The largest column and row any node covers.
+    extent: GridCoord,
+    /// Row-major, one past `extent` plus the clearance on each axis; cells
+    /// further out are near no node.
+    cells: Vec<Nearby>,
+    width: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Nearby {
+    Clear,
+    One(usize),
+    Two(usize, usize),
+    /// Near three nodes or more, so near one that is not an endpoint.
+    Crowded,
+    /// Covered by a node.
+    Occupied,
+}
+
+impl ClearanceMap {
+    fn new(grid: &HashMap<GridCoord, usize>, clearance: i32) -> ClearanceMap {
+        let extent = grid.keys().fold(GridCoord { x: 0, y: 0 }, |extent, coord| GridCoord {
+            x: extent.x.max(coord.x),
+            y: extent.y.max(coord.y),
+        });
+        let (width, height) = (extent.x + clearance + 1, extent.y + clearance + 1);
+        let mut cells = vec![Nearby::Clear; (width * height) as usize];
+        for (coord, node) in grid {
+            for dx in -clearance..=clearance {
+                for dy in -clearance..=clearance {
+                    let (x, y) = (coord.x + dx, coord.y + dy);
+                    if x < 0 || y < 0 {
+                        continue;
+                    }
+                    let cell = &mut cells[(y * width + x) as usize];
+                    *cell = match *cell {
+                        Nearby::Clear => Nearby::One(*node),
+                        Nearby::One(other) if other != *node => Nearby::Two(other, *node),
+                        Nearby::Two(a, b) if a != *node && b != *node => Nearby::Crowded,
+                        unchanged => unchanged,
+                    };
+                }
+            }
+        }
+        for coord in grid.keys().filter(|coord| coord.x >= 0 && coord.y >= 0) {
+            cells[(coord.y * width + coord.x) as usize] = Nearby::Occupied;
+        }
+        ClearanceMap { clearance, extent, cells, width }
+    }
+
+    /// Whether an edge between `endpoints` may cross `coord`: it is inside
+    /// the grid, no node covers it and only those two are within the
+    /// clearance.
+    fn is_free(&self, coord: GridCoord, endpoints: (usize, usize)) -> bool {
+        if coord.x < 0 || coord.y < 0 {
+            return false;
+        }
+        if coord.x >= self.width || (coord.y * self.width + coord.x) as usize >= self.cells.len() {
+            return true;
+        }
+        let endpoint = |node: usize| node == endpoints.0 || node == endpoints.1;
+        match self.cells[(coord.y * self.width + coord.x) as usize] {
+            Nearby::Clear => true,
+            Nearby::One(node) => endpoint(node),
+            Nearby::Two(a, b) => endpoint(a) && endpoint(b),
+            Nearby::Crowded | Nearby::Occupied => false,
+        }
+    }
+
+    /// Furthest cell worth exploring: enough slack past every placed node to
+    /// walk around it, so searches that cannot succeed still terminate.
+    fn search_limit(&self, from: GridCoord, to: GridCoord) -> GridCoord {
+        GridCoord {
+            x: from.x.max(to.x).max(self.extent.x) + self.clearance + 2,
+            y: from.y.max(to.y).max(self.extent.y) + self.clearance + 2,
+        }
+    }
+}
+
+/// The costs and back-links of a route search, kept from one search to the
+/// next so each reuses the memory of the last. An entry belongs to the
+/// current search only when its stamp does.
+#[derive(Clone, Debug, Default)]
+struct SearchScratch {
+    stamp: u32,
+    stamps: Vec<u32>,
+    costs: Vec<i32>,
+    came_from: Vec<usize>,
+}
+
+impl SearchScratch {
+    /// Forgets the last search and makes room for `states` states.
+    fn start(&mut self, states: usize) {
+        if self.stamps.len() < states {
+            self.stamps.resize(states, 0);
+            self.costs.resize(states, 0);
+            self.came_from.resize(states, 0);
+        }
+        self.stamp = self.stamp.wrapping_add(1);
+        if self.stamp == 0 {
+            self.stamps.fill(0);
+            self.stamp = 1;
+        }
+    }
+
+    fn cost(&self, state: usize) -> Option<i32> {
+        (self.stamps[state] == self.stamp).then(|| self.costs[state])
+    }
+
+    fn relax(&mut self, state: usize, cost: i32, from: usize) {
+        self.stamps[state] = self.stamp;
+        self.costs[state] = cost;
+        self.came_from[state] = from;
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq)]
 struct QueueItem {
     priority: i32,
     coord: GridCoord,
+    /// Index of the step direction the cell was entered by.
+    arrival: Option<usize>,
 }
 
 impl Ord for QueueItem {
@@ -0,0 +1,26 @@
+use anyhow::Result;
+
+use crate::render::geom::GridCoord;
+
+/// Callbacks run by [`Graph::layout_with`](super::Graph::layout_with) so tools
+/// can watch, adjust or veto the layout as it is built. Returning an error
+/// aborts the layout with that error.
+pub trait LayoutHooks {
+    /// Called once per node after all nodes are placed, with the top-left
+    /// cell of its 3×3 footprint. Changing `coord` moves the node; it must not
+    /// overlap another node.
+    fn on_node_placed(&mut self, _name: &str, _coord: &mut GridCoord) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after each edge is routed, with the turning points of its path
+    /// from the border of `from` to the border of `to`. The path may be
+    /// replaced but not emptied.
+    fn on_edge_routed(&mut self, _from: &str, _to: &str, _path: &mut Vec<GridCoord>) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) struct NoHooks;
+
+impl LayoutHooks for NoHooks {}
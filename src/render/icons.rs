@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+static ICON_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\bfa[bslr]?:(fa-[A-Za-z0-9-]+)").unwrap());
+
+const BUILTIN_ICONS: &[(&str, &str, &str)] = &[
+    ("fa-server", "🖥", "[S]"),
+    ("fa-database", "🛢", "[DB]"),
+    ("fa-user", "👤", "[U]"),
+    ("fa-users", "👥", "[UU]"),
+    ("fa-cloud", "☁", "[C]"),
+    ("fa-lock", "🔒", "[L]"),
+    ("fa-key", "🔑", "[K]"),
+    ("fa-cog", "⚙", "[*]"),
+    ("fa-globe", "🌐", "[W]"),
+    ("fa-envelope", "✉", "[M]"),
+    ("fa-file", "📄", "[F]"),
+    ("fa-folder", "📁", "[D]"),
+    ("fa-check", "✔", "[v]"),
+    ("fa-times", "✖", "[x]"),
+    ("fa-bolt", "⚡", "[!]"),
+    ("fa-clock", "⏰", "[T]"),
+    ("fa-bell", "🔔", "[B]"),
+    ("fa-mobile", "📱", "[P]"),
+    ("fa-laptop", "💻", "[PC]"),
+    ("fa-search", "🔍", "[?]"),
+];
+
+/// Font Awesome names (`fa-server`) and the text drawn for them in Unicode
+/// and ASCII mode.
+#[derive(Clone, Debug)]
+pub struct IconTable {
+    icons: HashMap<String, (String, String)>,
+}
+
+impl Default for IconTable {
+    fn default() -> IconTable {
+        IconTable {
+            icons: BUILTIN_ICONS
+                .iter()
+                .map(|(name, unicode, ascii)| {
+                    (name.to_string(), (unicode.to_string(), ascii.to_string()))
+                })
+                .collect(),
+        }
+    }
+}
+
+impl IconTable {
+    /// Adds or replaces icons from lines of `name unicode ascii`. Blank lines
+    /// and lines starting with `#` are skipped.
+    pub fn load(&mut self, text: &str) -> Result<()> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [name, unicode, ascii] = fields[..] else {
+                return Err(anyhow!(
+                    "icon table line {}: expected `name unicode ascii`, got `{}`",
+                    number + 1,
+                    line
+                ));
+            };
+            let name = name.strip_prefix("fa:").unwrap_or(name);
+            self.icons
+                .insert(name.to_string(), (unicode.to_string(), ascii.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Replaces `fa:fa-name` references in `label`. Unknown icons are left
+    /// as written.
+    pub fn expand(&self, label: &str, use_ascii: bool) -> String {
+        ICON_REGEX
+            .replace_all(label, |caps: &regex::Captures| {
+                match self.icons.get(&caps[1]) {
+                    Some((_, ascii)) if use_ascii => ascii.clone(),
+                    Some((unicode, _)) => unicode.clone(),
+                    None => caps[0].to_string(),
+                }
+            })
+            .into_owned()
+    }
+}
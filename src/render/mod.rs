@@ -1,6 +1,23 @@
+#[cfg(feature = "flowchart")]
+mod braille;
 mod drawing;
 mod geom;
+#[cfg(feature = "flowchart")]
 mod graph;
+#[cfg(feature = "flowchart")]
+mod hooks;
+#[cfg(feature = "flowchart")]
+mod icons;
+#[cfg(feature = "flowchart")]
+mod options;
 
-pub use graph::render_properties;
-pub use graph::RenderOptions;
+pub use drawing::{Color, Drawing, Stroke, TextStyle};
+pub use geom::{determine_direction, Direction, DrawingCoord, GenericCoord, GridCoord};
+#[cfg(feature = "flowchart")]
+pub use graph::{render_properties, render_to, Graph, LayoutStats, SourceItem, SourcePosition};
+#[cfg(feature = "flowchart")]
+pub use hooks::LayoutHooks;
+#[cfg(feature = "flowchart")]
+pub use icons::IconTable;
+#[cfg(feature = "flowchart")]
+pub use options::{AsciiStyle, AutoStyle, ColorTheme, RenderOptions, RenderOptionsBuilder, Sides};
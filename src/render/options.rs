@@ -0,0 +1,523 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::parser::GraphDirection;
+use crate::render::drawing::Color;
+use crate::render::icons::IconTable;
+
+/// How `use_ascii` drawings look.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AsciiStyle {
+    /// `+` for every corner and junction.
+    #[default]
+    Plain,
+    /// `.` and `'` for top and bottom corners, `+` only where lines meet.
+    Fancy,
+}
+
+/// The terminal colors `classDef` colors are matched to with `color`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorTheme {
+    /// The bright colors, which stay readable on a dark background.
+    #[default]
+    Dark,
+    /// The normal colors without yellow and white, for a light background.
+    Light,
+    /// The accent and gray colors of the Solarized palette.
+    Solarized,
+}
+
+impl ColorTheme {
+    /// The terminal colors this theme picks from, with how the theme's
+    /// terminals usually show them.
+    fn palette(self) -> &'static [(u8, (u8, u8, u8))] {
+        match self {
+            ColorTheme::Dark => &[
+                (8, (127, 127, 127)),
+                (9, (255, 85, 85)),
+                (10, (85, 255, 85)),
+                (11, (255, 255, 85)),
+                (12, (92, 92, 255)),
+                (13, (255, 85, 255)),
+                (14, (85, 255, 255)),
+                (15, (255, 255, 255)),
+            ],
+            ColorTheme::Light => &[
+                (0, (0, 0, 0)),
+                (1, (205, 0, 0)),
+                (2, (0, 205, 0)),
+                (4, (0, 0, 238)),
+                (5, (205, 0, 205)),
+                (6, (0, 205, 205)),
+                (8, (127, 127, 127)),
+            ],
+            ColorTheme::Solarized => &[
+                (1, (220, 50, 47)),
+                (2, (133, 153, 0)),
+                (3, (181, 137, 0)),
+                (4, (38, 139, 210)),
+                (5, (211, 54, 130)),
+                (6, (42, 161, 152)),
+                (9, (203, 75, 22)),
+                (10, (88, 110, 117)),
+                (13, (108, 113, 196)),
+                (14, (147, 161, 161)),
+            ],
+        }
+    }
+
+    /// The color of this theme that looks most like `rgb`, by a distance
+    /// weighting red and blue by how red the pair is.
+    pub fn nearest(self, rgb: (u8, u8, u8)) -> Color {
+        let distance = |(r, g, b): (u8, u8, u8)| {
+            let red_mean = (i32::from(r) + i32::from(rgb.0)) / 2;
+            let (dr, dg, db) =
+                (i32::from(r) - i32::from(rgb.0), i32::from(g) - i32::from(rgb.1), i32::from(b) - i32::from(rgb.2));
+            (((512 + red_mean) * dr * dr) >> 8) + 4 * dg * dg + (((767 - red_mean) * db * db) >> 8)
+        };
+        let (idx, _) = self.palette().iter().min_by_key(|(_, shown)| distance(*shown)).expect("palettes are not empty");
+        Color::Ansi(*idx)
+    }
+}
+
+/// Which nodes `auto_style` emphasizes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoStyle {
+    /// The `auto_style_top` nodes with the most edges.
+    Degree,
+}
+
+/// Blank cells on each side of the finished diagram.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sides {
+    pub top: usize,
+    pub right: usize,
+    pub bottom: usize,
+    pub left: usize,
+}
+
+impl Sides {
+    /// CSS-style shorthand: one value for every side, two for vertical and
+    /// horizontal, three for top, horizontal and bottom, or four clockwise
+    /// from the top.
+    pub fn from_shorthand(values: &[usize]) -> Result<Sides> {
+        let [top, right, bottom, left] = match *values {
+            [all] => [all; 4],
+            [vertical, horizontal] => [vertical, horizontal, vertical, horizontal],
+            [top, horizontal, bottom] => [top, horizontal, bottom, horizontal],
+            [top, right, bottom, left] => [top, right, bottom, left],
+            _ => return Err(anyhow!("padding takes 1 to 4 values (got {})", values.len())),
+        };
+        Ok(Sides { top, right, bottom, left })
+    }
+}
+
+/// Everything that controls how a parsed graph is drawn. The CLI, config
+/// files (`RenderOptions` deserializes from TOML or JSON) and library users
+/// all go through this type; [`RenderOptions::builder`] validates as it
+/// builds.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderOptions {
+    /// Cells between a node's text and its border.
+    pub border_padding: i32,
+    /// Overrides the graph's `paddingX` (horizontal gap between nodes).
+    pub padding_x: Option<i32>,
+    /// Overrides the graph's `paddingY` (vertical gap between nodes).
+    pub padding_y: Option<i32>,
+    /// Overrides the direction from the `graph` header.
+    pub direction: Option<GraphDirection>,
+    /// When `direction` is unset, picks whichever of LR and TD is estimated
+    /// to fit these `[columns, rows]` better.
+    pub fit_to: Option<(usize, usize)>,
+    pub use_ascii: bool,
+    pub ascii_style: AsciiStyle,
+    pub show_coords: bool,
+    /// Number the layout levels along the main axis.
+    pub show_ranks: bool,
+    pub number_edges: bool,
+    pub braille: bool,
+    /// Order nodes within a level so the ones with most edges sit in the
+    /// middle.
+    pub center_hubs: bool,
+    /// Ignore edge direction and lay each connected group out as a
+    /// breadth-first tree from its best-connected node.
+    pub undirected: bool,
+    /// Grid cells kept free around nodes an edge does not connect to.
+    pub edge_clearance: i32,
+    /// Extra cost of a corner when routing edges, so that of two routes the
+    /// one with fewer corners wins unless it is this many cells longer.
+    pub turn_penalty: i32,
+    pub shorten_labels: bool,
+    /// List each `classDef` with its styles and the nodes using it below the
+    /// diagram.
+    pub class_legend: bool,
+    /// Print a table of how each edge's route was chosen below the diagram.
+    pub explain: bool,
+    /// Blank the line cells on either side of an edge label.
+    pub clear_label_background: bool,
+    /// In top-down graphs, stack a label that would widen the columns beside
+    /// its line one character per row along the line instead.
+    pub vertical_labels: bool,
+    /// Print, in a gutter left of the drawing, the source line each node was
+    /// first written on, level with the top of its box.
+    pub source_map: bool,
+    /// Outer width boxes are widened to, unless a class sets `min-width`.
+    pub min_node_width: i32,
+    /// Put right-to-left label text into display order. Turn off for
+    /// terminals that apply the bidi algorithm themselves.
+    pub reorder_bidi: bool,
+    /// Draw node boxes after the edges so no line shows inside a box.
+    pub edges_under_nodes: bool,
+    /// Draw a border around the whole diagram, with the title in its top
+    /// edge.
+    pub frame: bool,
+    /// Title for the frame; defaults to the front matter's `title:`.
+    pub title: Option<String>,
+    /// Halve the vertical gaps between nodes so they look as wide as the
+    /// horizontal ones in terminal cells, which are about twice as tall as
+    /// they are wide.
+    pub aspect_compensate: bool,
+    /// Space around the diagram (and frame), e.g. to leave room for a
+    /// caption.
+    pub outer_padding: Sides,
+    /// Write node text styled with `font-weight:bold` or `font-style:italic`
+    /// with ANSI SGR attributes, and `classDef` `color` and `stroke` hex
+    /// colors on node text and borders.
+    pub color: bool,
+    /// The terminal colors `color` matches hex colors to.
+    pub color_theme: ColorTheme,
+    /// Write hex colors as exact 24-bit colors instead of matching them to
+    /// `color_theme`.
+    pub truecolor: bool,
+    /// Draws the boxes of nodes whose name or label matches this regular
+    /// expression with heavy borders (`#` in ASCII), in inverse video with
+    /// `color`.
+    pub highlight: Option<String>,
+    /// Emphasizes nodes picked by their place in the graph the way
+    /// `highlight` does, so hubs stand out without a `classDef`.
+    pub auto_style: Option<AutoStyle>,
+    /// How many nodes `auto_style` picks; ties go to the one declared first.
+    pub auto_style_top: usize,
+    /// Draws the nodes no path of edges leads to from the node of this name
+    /// with dotted borders, or faint with `color`.
+    pub reachable_from: Option<String>,
+    /// Most nodes a graph may have before layout refuses it.
+    pub max_nodes: usize,
+    /// Most cells the canvas may take, checked before it is allocated.
+    pub max_canvas_cells: usize,
+    /// Seed for randomized tie-breaking. The layout currently breaks every
+    /// tie by input order, so this changes nothing yet; either way, the same
+    /// input, options and seed always give byte-identical output.
+    pub seed: u64,
+    /// Replacements for `fa:fa-name` icon references in node labels.
+    #[serde(skip)]
+    pub icons: IconTable,
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions {
+            border_padding: 1,
+            padding_x: None,
+            padding_y: None,
+            direction: None,
+            fit_to: None,
+            use_ascii: false,
+            ascii_style: AsciiStyle::Plain,
+            show_coords: false,
+            show_ranks: false,
+            number_edges: false,
+            braille: false,
+            center_hubs: false,
+            undirected: false,
+            edge_clearance: 1,
+            turn_penalty: 1,
+            shorten_labels: false,
+            class_legend: false,
+            explain: false,
+            clear_label_background: true,
+            vertical_labels: false,
+            source_map: false,
+            min_node_width: 0,
+            reorder_bidi: true,
+            edges_under_nodes: true,
+            frame: false,
+            title: None,
+            aspect_compensate: false,
+            outer_padding: Sides::default(),
+            color: false,
+            color_theme: ColorTheme::Dark,
+            truecolor: false,
+            highlight: None,
+            auto_style: None,
+            auto_style_top: 3,
+            reachable_from: None,
+            max_nodes: 10_000,
+            max_canvas_cells: 4_000_000,
+            seed: 0,
+            icons: IconTable::default(),
+        }
+    }
+}
+
+impl RenderOptions {
+    pub fn builder() -> RenderOptionsBuilder {
+        RenderOptionsBuilder::default()
+    }
+
+    /// Reports every out-of-range or conflicting setting at once.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        let non_negative = [
+            ("border_padding", Some(self.border_padding)),
+            ("padding_x", self.padding_x),
+            ("padding_y", self.padding_y),
+            ("edge_clearance", Some(self.edge_clearance)),
+            ("turn_penalty", Some(self.turn_penalty)),
+            ("min_node_width", Some(self.min_node_width)),
+        ];
+        for (name, value) in non_negative {
+            if let Some(value) = value.filter(|value| *value < 0) {
+                problems.push(format!("{} must not be negative (got {})", name, value));
+            }
+        }
+        for (name, value) in [("max_nodes", self.max_nodes), ("max_canvas_cells", self.max_canvas_cells)] {
+            if value == 0 {
+                problems.push(format!("{} must be at least 1", name));
+            }
+        }
+        if matches!(self.fit_to, Some((0, _)) | Some((_, 0))) {
+            problems.push("fit_to needs at least one column and row".to_string());
+        }
+        if self.braille && self.use_ascii {
+            problems.push("braille and use_ascii cannot both be set".to_string());
+        }
+        if let Some(Err(err)) = self.highlight.as_deref().map(Regex::new) {
+            problems.push(format!("highlight is not a valid regular expression: {}", err));
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("invalid render options: {}", problems.join("; ")))
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RenderOptionsBuilder {
+    options: RenderOptions,
+}
+
+impl RenderOptionsBuilder {
+    pub fn border_padding(mut self, border_padding: i32) -> Self {
+        self.options.border_padding = border_padding;
+        self
+    }
+
+    pub fn padding_x(mut self, padding_x: i32) -> Self {
+        self.options.padding_x = Some(padding_x);
+        self
+    }
+
+    pub fn padding_y(mut self, padding_y: i32) -> Self {
+        self.options.padding_y = Some(padding_y);
+        self
+    }
+
+    pub fn direction(mut self, direction: GraphDirection) -> Self {
+        self.options.direction = Some(direction);
+        self
+    }
+
+    pub fn fit_to(mut self, columns: usize, rows: usize) -> Self {
+        self.options.fit_to = Some((columns, rows));
+        self
+    }
+
+    pub fn use_ascii(mut self, use_ascii: bool) -> Self {
+        self.options.use_ascii = use_ascii;
+        self
+    }
+
+    pub fn ascii_style(mut self, ascii_style: AsciiStyle) -> Self {
+        self.options.ascii_style = ascii_style;
+        self
+    }
+
+    pub fn show_coords(mut self, show_coords: bool) -> Self {
+        self.options.show_coords = show_coords;
+        self
+    }
+
+    pub fn show_ranks(mut self, show_ranks: bool) -> Self {
+        self.options.show_ranks = show_ranks;
+        self
+    }
+
+    pub fn number_edges(mut self, number_edges: bool) -> Self {
+        self.options.number_edges = number_edges;
+        self
+    }
+
+    pub fn braille(mut self, braille: bool) -> Self {
+        self.options.braille = braille;
+        self
+    }
+
+    pub fn center_hubs(mut self, center_hubs: bool) -> Self {
+        self.options.center_hubs = center_hubs;
+        self
+    }
+
+    pub fn undirected(mut self, undirected: bool) -> Self {
+        self.options.undirected = undirected;
+        self
+    }
+
+    pub fn edge_clearance(mut self, edge_clearance: i32) -> Self {
+        self.options.edge_clearance = edge_clearance;
+        self
+    }
+
+    pub fn turn_penalty(mut self, turn_penalty: i32) -> Self {
+        self.options.turn_penalty = turn_penalty;
+        self
+    }
+
+    pub fn shorten_labels(mut self, shorten_labels: bool) -> Self {
+        self.options.shorten_labels = shorten_labels;
+        self
+    }
+
+    pub fn class_legend(mut self, class_legend: bool) -> Self {
+        self.options.class_legend = class_legend;
+        self
+    }
+
+    pub fn explain(mut self, explain: bool) -> Self {
+        self.options.explain = explain;
+        self
+    }
+
+    pub fn clear_label_background(mut self, clear_label_background: bool) -> Self {
+        self.options.clear_label_background = clear_label_background;
+        self
+    }
+
+    pub fn vertical_labels(mut self, vertical_labels: bool) -> Self {
+        self.options.vertical_labels = vertical_labels;
+        self
+    }
+
+    pub fn source_map(mut self, source_map: bool) -> Self {
+        self.options.source_map = source_map;
+        self
+    }
+
+    pub fn min_node_width(mut self, min_node_width: i32) -> Self {
+        self.options.min_node_width = min_node_width;
+        self
+    }
+
+    pub fn reorder_bidi(mut self, reorder_bidi: bool) -> Self {
+        self.options.reorder_bidi = reorder_bidi;
+        self
+    }
+
+    pub fn edges_under_nodes(mut self, edges_under_nodes: bool) -> Self {
+        self.options.edges_under_nodes = edges_under_nodes;
+        self
+    }
+
+    pub fn frame(mut self, frame: bool) -> Self {
+        self.options.frame = frame;
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.options.title = Some(title.into());
+        self
+    }
+
+    pub fn aspect_compensate(mut self, aspect_compensate: bool) -> Self {
+        self.options.aspect_compensate = aspect_compensate;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.options.seed = seed;
+        self
+    }
+
+    pub fn outer_padding(mut self, outer_padding: Sides) -> Self {
+        self.options.outer_padding = outer_padding;
+        self
+    }
+
+    pub fn color(mut self, color: bool) -> Self {
+        self.options.color = color;
+        self
+    }
+
+    pub fn color_theme(mut self, color_theme: ColorTheme) -> Self {
+        self.options.color_theme = color_theme;
+        self
+    }
+
+    pub fn truecolor(mut self, truecolor: bool) -> Self {
+        self.options.truecolor = truecolor;
+        self
+    }
+
+    pub fn highlight(mut self, pattern: impl Into<String>) -> Self {
+        self.options.highlight = Some(pattern.into());
+        self
+    }
+
+    pub fn auto_style(mut self, auto_style: AutoStyle) -> Self {
+        self.options.auto_style = Some(auto_style);
+        self
+    }
+
+    pub fn auto_style_top(mut self, auto_style_top: usize) -> Self {
+        self.options.auto_style_top = auto_style_top;
+        self
+    }
+
+    pub fn reachable_from(mut self, name: impl Into<String>) -> Self {
+        self.options.reachable_from = Some(name.into());
+        self
+    }
+
+    pub fn max_nodes(mut self, max_nodes: usize) -> Self {
+        self.options.max_nodes = max_nodes;
+        self
+    }
+
+    pub fn max_canvas_cells(mut self, max_canvas_cells: usize) -> Self {
+        self.options.max_canvas_cells = max_canvas_cells;
+        self
+    }
+
+    pub fn icons(mut self, icons: IconTable) -> Self {
+        self.options.icons = icons;
+        self
+    }
+
+    pub fn build(self) -> Result<RenderOptions> {
+        self.options.validate()?;
+        Ok(self.options)
+    }
+}
+
+impl From<RenderOptions> for RenderOptionsBuilder {
+    fn from(options: RenderOptions) -> RenderOptionsBuilder {
+        RenderOptionsBuilder { options }
+    }
+}
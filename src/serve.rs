@@ -0,0 +1,156 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use anyhow::{anyhow, Context, Result};
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use flate2::read::ZlibDecoder;
+use mermaid_ascii::source::decode_source;
+
+const MAX_BODY_BYTES: usize = 1 << 20;
+
+/// Kroki payloads are URL-safe base64, with or without padding.
+const KROKI_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Serves renderings over HTTP until the process is stopped.
+///
+/// `POST /` (or `/mermaid`, `/mermaid/txt`, `/mermaid/utxt`) takes Mermaid
+/// text as the body. `GET /mermaid/{txt,utxt}/<payload>` takes the Kroki
+/// encoding: zlib-deflated source in URL-safe base64. `txt` renders ASCII
+/// and `utxt` Unicode; the other routes use the command-line options.
+/// `render` gets the source and the requested ASCII override.
+pub fn run<F>(addr: &str, render: F) -> Result<()>
+where
+    F: Fn(&str, Option<bool>) -> Result<String> + Sync,
+{
+    let listener = TcpListener::bind(addr).with_context(|| format!("binding {}", addr))?;
+    eprintln!("listening on http://{}", listener.local_addr()?);
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let render = &render;
+                    scope.spawn(move || {
+                        if let Err(err) = handle(stream, render) {
+                            log::warn!("request failed: {:#}", err);
+                        }
+                    });
+                }
+                Err(err) => log::warn!("accept failed: {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+struct Response {
+    status: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn new(status: &'static str, body: impl Into<String>) -> Response {
+        Response {
+            status,
+            body: body.into(),
+        }
+    }
+}
+
+fn handle<F>(stream: TcpStream, render: &F) -> Result<()>
+where
+    F: Fn(&str, Option<bool>) -> Result<String>,
+{
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader) {
+        Ok((method, path, body)) => respond(&method, &path, &body, render),
+        Err(err) => Response::new("400 Bad Request", format!("{:#}\n", err)),
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<(String, String, Vec<u8>)> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().ok_or_else(|| anyhow!("empty request"))?.to_string();
+    let path = parts.next().ok_or_else(|| anyhow!("missing request path"))?.to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().context("bad Content-Length")?;
+            }
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(anyhow!("body larger than {} bytes", MAX_BODY_BYTES));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok((method, path, body))
+}
+
+fn respond<F>(method: &str, path: &str, body: &[u8], render: &F) -> Response
+where
+    F: Fn(&str, Option<bool>) -> Result<String>,
+{
+    let path = path.split('?').next().unwrap_or("");
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let source = match (method, segments.as_slice()) {
+        ("POST", [""] | ["mermaid"]) => decode_source(body).map(|(text, _)| (text, None)),
+        ("POST", ["mermaid", format]) => match ascii_for(format) {
+            Some(ascii) => decode_source(body).map(|(text, _)| (text, Some(ascii))),
+            None => return Response::new("404 Not Found", "unknown output format\n"),
+        },
+        ("GET", ["mermaid", format, payload]) => match ascii_for(format) {
+            Some(ascii) => decode_kroki(payload).map(|text| (text, Some(ascii))),
+            None => return Response::new("404 Not Found", "unknown output format\n"),
+        },
+        ("GET" | "POST", _) => return Response::new("404 Not Found", "not found\n"),
+        _ => return Response::new("405 Method Not Allowed", "use GET or POST\n"),
+    };
+    match source.and_then(|(text, ascii)| render(&text, ascii)) {
+        Ok(drawing) => Response::new("200 OK", format!("{}\n", drawing)),
+        Err(err) => Response::new("400 Bad Request", format!("{:#}\n", err)),
+    }
+}
+
+fn ascii_for(format: &str) -> Option<bool> {
+    match format {
+        "txt" => Some(true),
+        "utxt" => Some(false),
+        _ => None,
+    }
+}
+
+fn decode_kroki(payload: &str) -> Result<String> {
+    let compressed = KROKI_BASE64
+        .decode(payload)
+        .context("payload is not URL-safe base64")?;
+    let mut text = String::new();
+    ZlibDecoder::new(compressed.as_slice())
+        .take(MAX_BODY_BYTES as u64)
+        .read_to_string(&mut text)
+        .context("payload is not zlib-deflated UTF-8")?;
+    Ok(text)
+}
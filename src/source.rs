@@ -0,0 +1,146 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::error::{tagged, ErrorCategory};
+
+static INCLUDE_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"^\s*%%\s*include\s+(?:"([^"]+)"|(\S.*?))\s*$"#).unwrap());
+static HEADER_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\s*(graph|flowchart)\b").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceEncoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl fmt::Display for SourceEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SourceEncoding::Utf8 => "UTF-8",
+            SourceEncoding::Utf8Bom => "UTF-8 with BOM",
+            SourceEncoding::Utf16Le => "UTF-16LE",
+            SourceEncoding::Utf16Be => "UTF-16BE",
+        })
+    }
+}
+
+/// Decodes raw input bytes, stripping a byte order mark. UTF-16 is picked up
+/// from its BOM, or from a NUL in the first code unit when there is none.
+pub fn decode_source(bytes: &[u8]) -> Result<(String, SourceEncoding)> {
+    decode(bytes).map_err(|err| tagged(ErrorCategory::Parse, err))
+}
+
+fn decode(bytes: &[u8]) -> Result<(String, SourceEncoding)> {
+    let encoding = match bytes {
+        [0xEF, 0xBB, 0xBF, ..] => SourceEncoding::Utf8Bom,
+        [0xFF, 0xFE, ..] => SourceEncoding::Utf16Le,
+        [0xFE, 0xFF, ..] => SourceEncoding::Utf16Be,
+        [first, 0, ..] if *first != 0 => SourceEncoding::Utf16Le,
+        [0, second, ..] if *second != 0 => SourceEncoding::Utf16Be,
+        _ => SourceEncoding::Utf8,
+    };
+    let text = match encoding {
+        SourceEncoding::Utf8 | SourceEncoding::Utf8Bom => {
+            let body = if encoding == SourceEncoding::Utf8Bom {
+                &bytes[3..]
+            } else {
+                bytes
+            };
+            String::from_utf8(body.to_vec()).map_err(|err| {
+                let offset = err.utf8_error().valid_up_to();
+                anyhow!(
+                    "input is not valid UTF-8 (byte 0x{:02x} at offset {}); save it as UTF-8 or UTF-16",
+                    body[offset],
+                    offset
+                )
+            })?
+        }
+        SourceEncoding::Utf16Le | SourceEncoding::Utf16Be => {
+            if !bytes.len().is_multiple_of(2) {
+                return Err(anyhow!("{} input has an odd number of bytes", encoding));
+            }
+            let units = bytes.chunks_exact(2).map(|pair| {
+                if encoding == SourceEncoding::Utf16Le {
+                    u16::from_le_bytes([pair[0], pair[1]])
+                } else {
+                    u16::from_be_bytes([pair[0], pair[1]])
+                }
+            });
+            let text = char::decode_utf16(units)
+                .collect::<Result<String, _>>()
+                .map_err(|err| anyhow!("{} input has an unpaired surrogate 0x{:04x}", encoding, err.unpaired_surrogate()))?;
+            text.strip_prefix('\u{feff}').map(str::to_string).unwrap_or(text)
+        }
+    };
+    Ok((text, encoding))
+}
+
+/// Replaces `%%include <path>` lines with the statements of that file.
+/// Paths are relative to the including file; `file` is where `input` came
+/// from, or `None` for stdin, which resolves against the working directory.
+/// The `graph`/`flowchart` header of an included file is dropped, and a file
+/// that ends up including itself is an error.
+pub fn expand_includes(input: &str, file: Option<&Path>) -> Result<String> {
+    let mut stack = Vec::new();
+    let mut dir = PathBuf::from(".");
+    if let Some(file) = file {
+        let canonical = file.canonicalize().with_context(|| format!("reading {}", file.display()))?;
+        dir = canonical.parent().map(Path::to_path_buf).unwrap_or(dir);
+        stack.push(canonical);
+    }
+    let mut output = String::new();
+    expand_into(input, &dir, &mut stack, false, &mut output)?;
+    Ok(output)
+}
+
+fn expand_into(
+    input: &str,
+    dir: &Path,
+    stack: &mut Vec<PathBuf>,
+    nested: bool,
+    output: &mut String,
+) -> Result<()> {
+    let mut header_pending = nested;
+    for (number, line) in input.lines().enumerate() {
+        let Some(caps) = INCLUDE_REGEX.captures(line) else {
+            let trimmed = line.trim();
+            if header_pending && !trimmed.is_empty() && !trimmed.starts_with("%%") {
+                header_pending = false;
+                if HEADER_REGEX.is_match(line) {
+                    continue;
+                }
+            }
+            output.push_str(line);
+            output.push('\n');
+            continue;
+        };
+        let name = caps.get(1).or(caps.get(2)).map_or("", |m| m.as_str());
+        let path = dir.join(name);
+        let canonical = path
+            .canonicalize()
+            .with_context(|| format!("line {}: cannot include {}", number + 1, path.display()))?;
+        if let Some(start) = stack.iter().position(|open| *open == canonical) {
+            let cycle: Vec<String> = stack[start..]
+                .iter()
+                .chain([&canonical])
+                .map(|path| path.display().to_string())
+                .collect();
+            return Err(tagged(ErrorCategory::Parse, anyhow!("include cycle: {}", cycle.join(" -> "))));
+        }
+        let bytes = fs::read(&canonical).with_context(|| format!("reading {}", path.display()))?;
+        let (text, _) = decode_source(&bytes).with_context(|| format!("reading {}", path.display()))?;
+        let nested_dir = canonical.parent().unwrap_or(dir).to_path_buf();
+        stack.push(canonical);
+        expand_into(&text, &nested_dir, stack, true, output)?;
+        stack.pop();
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+
+/// Log target that `--trace-layout` switches on.
+pub const TARGET: &str = "mermaid_ascii::trace";
+
+/// Times one pipeline phase and logs it with its counters when finished, as a
+/// single `phase=<name> elapsed_us=<n> key=value ...` line. With the
+/// `profiling` feature the phase is also a `tracing` span of the same name.
+#[cfg_attr(not(feature = "flowchart"), allow(dead_code))]
+pub(crate) struct Phase {
+    name: &'static str,
+    start: Instant,
+    counts: Vec<(&'static str, usize)>,
+    #[cfg(feature = "profiling")]
+    _span: tracing::span::EnteredSpan,
+}
+
+#[cfg_attr(not(feature = "flowchart"), allow(dead_code))]
+impl Phase {
+    pub(crate) fn start(name: &'static str) -> Phase {
+        Phase {
+            name,
+            start: Instant::now(),
+            counts: Vec::new(),
+            #[cfg(feature = "profiling")]
+            _span: phase_span(name).entered(),
+        }
+    }
+
+    pub(crate) fn count(&mut self, key: &'static str, value: usize) {
+        self.counts.push((key, value));
+    }
+
+    /// Logs the phase and returns how long it took.
+    pub(crate) fn finish(self) -> Duration {
+        let elapsed = self.start.elapsed();
+        if !log::log_enabled!(target: TARGET, log::Level::Debug) {
+            return elapsed;
+        }
+        let counts: Vec<String> = self
+            .counts
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        log::debug!(
+            target: TARGET,
+            "phase={} elapsed_us={} {}",
+            self.name,
+            elapsed.as_micros(),
+            counts.join(" ")
+        );
+        elapsed
+    }
+}
+
+/// Flamegraphs group stacks by span name, and span names have to be known
+/// at compile time, so each phase gets its own.
+#[cfg(feature = "profiling")]
+fn phase_span(name: &'static str) -> tracing::Span {
+    match name {
+        "parse" => tracing::info_span!("parse"),
+        "mapping" => tracing::info_span!("mapping"),
+        "routing" => tracing::info_span!("routing"),
+        "drawing" => tracing::info_span!("drawing"),
+        _ => tracing::info_span!("phase", name),
+    }
+}
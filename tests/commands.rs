@@ -0,0 +1,70 @@
+use assert_cmd::Command;
+use mermaid_ascii::format::format_mermaid;
+
+fn run(args: &[&str], input: &str) -> (Option<i32>, String) {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.args(args).write_stdin(input.to_string());
+    let output = cmd.assert().get_output().clone();
+    (output.status.code(), String::from_utf8(output.stdout).expect("valid utf-8"))
+}
+
+#[test]
+fn render_is_the_default_and_global_flags_go_either_side() {
+    let input = "graph LR\nA --> B\n";
+    let flat = run(&["--ascii"], input);
+    assert_eq!(flat.0, Some(0));
+    assert_eq!(run(&["render", "--ascii"], input), flat);
+    assert_eq!(run(&["--ascii", "render"], input), flat);
+}
+
+#[test]
+fn check_and_stats_print_no_diagram() {
+    assert_eq!(run(&["check"], "graph LR\nA --> B\n"), (Some(0), String::new()));
+    assert_eq!(run(&["check"], "graph LR\n").0, Some(3));
+
+    let (code, stdout) = run(&["stats"], "graph LR\nA --> B\n");
+    assert_eq!(code, Some(0));
+    assert!(stdout.starts_with("summary: 2 nodes, 1 edges, grid"), "{}", stdout);
+    assert_eq!(stdout.lines().count(), 1);
+}
+
+#[test]
+fn fmt_indents_subgraphs_and_spaces_links() {
+    let input = "graph   LR\nA-->B\n  subgraph S\nB-.->|x|C   &   D\n\n\nC<--D[a-->b]\n end\n";
+    let expected = "graph LR\n    A --> B\n    subgraph S\n        B -.->|x| C & D\n\n        C <-- D[a-->b]\n    end\n";
+    assert_eq!(run(&["fmt"], input), (Some(0), expected.to_string()));
+    assert_eq!(format_mermaid(expected), expected);
+
+    let inline = "graph LR\nA--yes-->B-.maybe.->C\n";
+    assert_eq!(format_mermaid(inline), "graph LR\n    A -- yes --> B -. maybe .-> C\n");
+
+    let front_matter = "---\ntitle: Jobs\n---\ngraph TD\nA --> B\n";
+    assert_eq!(format_mermaid(front_matter), "---\ntitle: Jobs\n---\ngraph TD\n    A --> B\n");
+}
+
+#[test]
+fn completions_are_generated_for_each_shell() {
+    for shell in ["bash", "zsh", "fish"] {
+        let (code, script) = run(&["completions", shell], "");
+        assert_eq!(code, Some(0));
+        assert!(script.contains("mermaid-ascii") && script.contains("stats"), "{}", shell);
+    }
+}
+
+#[test]
+fn input_format_is_detected_from_the_first_line() {
+    let boxes = |input: &str, args: &[&str]| {
+        let (code, output) = run(&[&["--ascii"], args].concat(), input);
+        (code, output.matches("+---").count() / 2)
+    };
+    assert_eq!(boxes("%% deps\ngraph LR\nA --> B\n", &[]), (Some(0), 2));
+    assert_eq!(boxes("\n{\"nodes\": [{\"id\": \"a\"}], \"edges\": [{\"from\": \"a\", \"to\": \"b\"}]}\n", &[]), (Some(0), 2));
+    assert_eq!(boxes("from,to\na,b\nb,c\n", &[]), (Some(0), 3));
+    assert_eq!(boxes("a;b\n", &["--csv-delimiter", ";"]), (Some(0), 2));
+    assert_eq!(boxes("from,to\na,b\n", &["--input-format", "mermaid"]).0, Some(2));
+
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.write_stdin("// exported\ndigraph G {\n  a -> b;\n}\n");
+    let stderr = String::from_utf8(cmd.assert().code(2).get_output().stderr.clone()).expect("valid utf-8");
+    assert!(stderr.contains("Graphviz DOT"), "{}", stderr);
+}
@@ -0,0 +1,72 @@
+#![cfg(unix)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::process::{Command, Stdio};
+
+use serde_json::Value;
+
+#[test]
+fn daemon_answers_each_json_line_on_the_socket() {
+    let socket = std::env::temp_dir().join(format!("mermaid-ascii-{}.sock", std::process::id()));
+    let mut child = Command::new(assert_cmd::cargo::cargo_bin("mermaid-ascii"))
+        .args(["daemon", "--socket"])
+        .arg(&socket)
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("daemon starts");
+    let mut line = String::new();
+    BufReader::new(child.stderr.take().expect("stderr piped"))
+        .read_line(&mut line)
+        .expect("daemon announces its socket");
+    assert!(line.starts_with("listening on "), "{}", line);
+
+    let mut stream = UnixStream::connect(&socket).expect("connects");
+    let mut responses = BufReader::new(stream.try_clone().expect("clones")).lines();
+    let mut request = |line: &str| -> Value {
+        writeln!(stream, "{}", line).expect("request sent");
+        serde_json::from_str(&responses.next().expect("a response").expect("reads")).expect("JSON response")
+    };
+
+    let unicode = request(r#"{"source": "graph LR\nA --> B\n"}"#);
+    assert_eq!(unicode["ok"], true);
+    assert!(unicode["output"].as_str().is_some_and(|output| output.contains('►')), "{}", unicode);
+    let ascii = request(r#"{"source": "graph LR\nA --> B\n", "ascii": true}"#);
+    assert!(ascii["output"].as_str().is_some_and(|output| output.contains('>')), "{}", ascii);
+
+    let failed = request(r#"{"source": "not a graph"}"#);
+    assert_eq!((&failed["ok"], &failed["exit_code"]), (&Value::Bool(false), &Value::from(2)), "{}", failed);
+    let malformed = request("{");
+    assert!(malformed["error"].as_str().is_some_and(|error| error.contains("not JSON")), "{}", malformed);
+
+    let _ = child.kill();
+    let _ = child.wait();
+    let _ = std::fs::remove_file(&socket);
+}
+
+#[test]
+fn daemon_flag_starts_the_same_daemon() {
+    let socket = std::env::temp_dir().join(format!("mermaid-ascii-flag-{}.sock", std::process::id()));
+    let mut child = Command::new(assert_cmd::cargo::cargo_bin("mermaid-ascii"))
+        .args(["--daemon", "--socket"])
+        .arg(&socket)
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("daemon starts");
+    let mut line = String::new();
+    BufReader::new(child.stderr.take().expect("stderr piped"))
+        .read_line(&mut line)
+        .expect("daemon announces its socket");
+    assert!(line.starts_with("listening on "), "{}", line);
+
+    let mut stream = UnixStream::connect(&socket).expect("connects");
+    writeln!(stream, r#"{{"source": "graph LR\nA --> B\n"}}"#).expect("request sent");
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response).expect("reads");
+    let response: Value = serde_json::from_str(&response).expect("JSON response");
+    assert_eq!(response["ok"], true, "{}", response);
+
+    let _ = child.kill();
+    let _ = child.wait();
+    let _ = std::fs::remove_file(&socket);
+}
@@ -0,0 +1,88 @@
+use mermaid_ascii::parser::mermaid_file_to_map;
+use mermaid_ascii::render::{render_properties, RenderOptions};
+use proptest::prelude::*;
+
+fn options(use_ascii: bool, border_padding: i32) -> RenderOptions {
+    RenderOptions {
+        border_padding,
+        use_ascii,
+        ..RenderOptions::default()
+    }
+}
+
+fn render(
+    input: &str,
+    use_ascii: bool,
+    border_padding: i32,
+    padding: i32,
+) -> anyhow::Result<String> {
+    let mut properties = mermaid_file_to_map(input, "cli")?;
+    properties.padding_x = padding;
+    properties.padding_y = padding;
+    render_properties(&properties, &options(use_ascii, border_padding))
+}
+
+#[test]
+fn single_node_renders_a_closed_box() {
+    let output = render("graph LR\nA\n", false, 1, 5).expect("renders");
+    assert_eq!(output.lines().count(), 5, "output:\n{}", output);
+    assert!(
+        output.contains('┌') && output.contains('┘'),
+        "output:\n{}",
+        output
+    );
+}
+
+#[test]
+fn zero_border_padding_keeps_labels_inside_boxes() {
+    let output = render("graph LR\nA --> B\n", false, 0, 5).expect("renders");
+    assert!(
+        output.contains("│A") && output.contains("│B│"),
+        "output:\n{}",
+        output
+    );
+}
+
+fn name() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[A-D]",
+        "[a-z ]{0,4}",
+        Just(String::new()),
+        Just("\"\"".to_string()),
+        Just("   ".to_string()),
+        Just("日本".to_string()),
+    ]
+}
+
+fn statement() -> impl Strategy<Value = String> {
+    (
+        name(),
+        prop_oneof![Just("-->"), Just("---"), Just("-.->")],
+        "[a-z ]{0,3}",
+        name(),
+    )
+        .prop_map(|(from, link, label, to)| {
+            if label.is_empty() {
+                format!("{} {} {}", from, link, to)
+            } else {
+                format!("{} {}|{}| {}", from, link, label, to)
+            }
+        })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn random_small_graphs_never_panic(
+        td in any::<bool>(),
+        use_ascii in any::<bool>(),
+        border_padding in -2..3i32,
+        padding in -3..6i32,
+        statements in prop::collection::vec(statement(), 0..6),
+    ) {
+        let header = if td { "graph TD" } else { "graph LR" };
+        let input = format!("{}\n{}\n", header, statements.join("\n"));
+        let _ = render(&input, use_ascii, border_padding, padding);
+    }
+}
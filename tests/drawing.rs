@@ -0,0 +1,231 @@
+use mermaid_ascii::render::{Drawing, DrawingCoord, Stroke};
+
+const JUNCTIONS: [&str; 15] = [
+    "─", "│", "┌", "┐", "└", "┘", "├", "┤", "┬", "┴", "┼", "╴", "╵", "╶", "╷",
+];
+
+/// Arms of a light box-drawing glyph as (up, down, left, right).
+fn arms(glyph: &str) -> (bool, bool, bool, bool) {
+    match glyph {
+        "─" => (false, false, true, true),
+        "│" => (true, true, false, false),
+        "┌" => (false, true, false, true),
+        "┐" => (false, true, true, false),
+        "└" => (true, false, false, true),
+        "┘" => (true, false, true, false),
+        "├" => (true, true, false, true),
+        "┤" => (true, true, true, false),
+        "┬" => (false, true, true, true),
+        "┴" => (true, false, true, true),
+        "┼" => (true, true, true, true),
+        "╴" => (false, false, true, false),
+        "╵" => (true, false, false, false),
+        "╶" => (false, false, false, true),
+        "╷" => (false, true, false, false),
+        other => panic!("not a junction glyph: {}", other),
+    }
+}
+
+fn at(x: i32, y: i32) -> DrawingCoord {
+    DrawingCoord { x, y }
+}
+
+#[test]
+fn overlaying_junctions_unions_their_arms() {
+    for current in JUNCTIONS {
+        for new in JUNCTIONS {
+            let mut base = Drawing::new(0, 0);
+            base.set(at(0, 0), current);
+            let mut top = Drawing::new(0, 0);
+            top.set(at(0, 0), new);
+            base.overlay(&top, at(0, 0), false);
+            let (a, b) = (arms(current), arms(new));
+            let expected = (a.0 || b.0, a.1 || b.1, a.2 || b.2, a.3 || b.3);
+            assert_eq!(
+                arms(base.get(at(0, 0))),
+                expected,
+                "{} + {} gave {}",
+                current,
+                new,
+                base.get(at(0, 0))
+            );
+        }
+    }
+}
+
+#[test]
+fn sizes_are_inclusive_maximum_indexes() {
+    let drawing = Drawing::new(3, 1);
+    assert_eq!(drawing.size(), (3, 1));
+    assert_eq!(
+        drawing.to_string().lines().collect::<Vec<_>>(),
+        ["    ", "    "]
+    );
+
+    let mut grown = Drawing::empty();
+    assert_eq!(grown.size(), (0, 0));
+    grown.set(at(4, 2), "x");
+    assert_eq!(grown.size(), (4, 2));
+    assert_eq!(grown.get(at(4, 2)), "x");
+}
+
+/// Cells a line from `from` to `to` should cover once both ends are moved
+/// along the line by their offsets: the inclusive run from start to end, or
+/// nothing when the offsets cross.
+fn expected_line(
+    from: (i32, i32),
+    to: (i32, i32),
+    offset_from: i32,
+    offset_to: i32,
+) -> Vec<(i32, i32)> {
+    let (dx, dy) = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+    let (dx, dy) = if (dx, dy) == (0, 0) {
+        (0, -1)
+    } else {
+        (dx, dy)
+    };
+    let start = (from.0 + dx * offset_from, from.1 + dy * offset_from);
+    let end = (to.0 + dx * offset_to, to.1 + dy * offset_to);
+    let length =
+        ((end.0 - start.0) * dx + (end.1 - start.1) * dy) / if dx != 0 && dy != 0 { 2 } else { 1 };
+    if length < 0 {
+        return Vec::new();
+    }
+    (0..=length)
+        .map(|step| (start.0 + dx * step, start.1 + dy * step))
+        .collect()
+}
+
+#[test]
+fn draw_line_covers_the_offset_run_in_every_direction() {
+    let center = (5, 5);
+    let targets = [
+        (5, 1),
+        (5, 9),
+        (1, 5),
+        (9, 5),
+        (9, 1),
+        (1, 1),
+        (9, 9),
+        (1, 9),
+        (5, 5),
+        (5, 4),
+        (6, 5),
+    ];
+    for to in targets {
+        for offset_from in [-1, 0, 1] {
+            for offset_to in [-1, 0, 1] {
+                let mut drawing = Drawing::new(11, 11);
+                let drawn = drawing.draw_line(
+                    at(center.0, center.1),
+                    at(to.0, to.1),
+                    offset_from,
+                    offset_to,
+                    Stroke::Normal,
+                    false,
+                );
+                let drawn: Vec<(i32, i32)> = drawn.iter().map(|c| (c.x, c.y)).collect();
+                let expected = expected_line(center, to, offset_from, offset_to);
+                assert_eq!(
+                    drawn, expected,
+                    "to {:?} offsets {} {}",
+                    to, offset_from, offset_to
+                );
+                let painted = (0..=11)
+                    .flat_map(|x| (0..=11).map(move |y| (x, y)))
+                    .filter(|(x, y)| drawing.get(at(*x, *y)) != " ")
+                    .count();
+                assert_eq!(
+                    painted,
+                    expected.len(),
+                    "to {:?} offsets {} {}:\n{}",
+                    to,
+                    offset_from,
+                    offset_to,
+                    drawing
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn draw_line_picks_glyphs_by_direction_and_stroke() {
+    let glyph = |to: (i32, i32), stroke: Stroke, ascii: bool| {
+        let mut drawing = Drawing::new(4, 4);
+        drawing.draw_line(at(2, 2), at(to.0, to.1), 0, 0, stroke, ascii);
+        drawing.get(at(2, 2)).to_string()
+    };
+    assert_eq!(glyph((4, 2), Stroke::Normal, false), "─");
+    assert_eq!(glyph((0, 2), Stroke::Heavy, false), "━");
+    assert_eq!(glyph((2, 0), Stroke::Double, false), "║");
+    assert_eq!(glyph((2, 4), Stroke::Thin, false), "╎");
+    assert_eq!(glyph((4, 4), Stroke::Normal, false), "╲");
+    assert_eq!(glyph((0, 4), Stroke::Normal, false), "╱");
+    assert_eq!(glyph((4, 2), Stroke::Heavy, true), "=");
+    assert_eq!(glyph((2, 4), Stroke::Normal, true), "|");
+    assert_eq!(glyph((0, 0), Stroke::Normal, true), "\\");
+}
+
+#[test]
+fn skewed_diagonals_terminate_at_their_end() {
+    let mut drawing = Drawing::new(6, 6);
+    let drawn = drawing.draw_line(at(0, 0), at(5, 2), 0, 0, Stroke::Normal, false);
+    let cells: Vec<(i32, i32)> = drawn.iter().map(|c| (c.x, c.y)).collect();
+    assert_eq!(cells, [(0, 0), (1, 1), (2, 2), (3, 2), (4, 2), (5, 2)]);
+}
+
+#[test]
+fn dashed_lines_skip_cells_but_report_all_of_them() {
+    let mut drawing = Drawing::new(6, 0);
+    let drawn = drawing.draw_dashed_line(at(0, 0), at(6, 0), 0, 0, &[2, 1], Stroke::Normal, false);
+    assert_eq!(drawn.len(), 7);
+    assert_eq!(drawing.to_string(), "── ── ─");
+}
+
+#[test]
+fn overlay_keeps_spaces_transparent_and_merges_only_in_unicode() {
+    let mut base = Drawing::new(2, 0);
+    base.draw_line(at(0, 0), at(2, 0), 0, 0, Stroke::Normal, false);
+    let mut top = Drawing::new(1, 0);
+    top.set(at(1, 0), "│");
+    let mut unicode = base.clone();
+    unicode.overlay(&top, at(0, 0), false);
+    assert_eq!(unicode.to_string(), "─┼─");
+    let mut ascii = base.clone();
+    ascii.overlay(&top, at(0, 0), true);
+    assert_eq!(ascii.to_string(), "─│─");
+
+    let mut shifted = base.clone();
+    shifted.overlay(&top, at(3, 1), false);
+    assert_eq!(shifted.size(), (4, 1));
+    assert_eq!(shifted.get(at(4, 1)), "│");
+    assert_eq!(shifted.get(at(3, 1)), " ");
+
+    let mut pasted = base;
+    pasted.paste(&top, at(0, 0));
+    assert_eq!(pasted.to_string(), " │─");
+}
+
+#[test]
+fn wide_text_leaves_continuation_cells() {
+    let mut drawing = Drawing::new(5, 0);
+    drawing.draw_text(at(0, 0), "a🔒b");
+    assert_eq!(drawing.get(at(1, 0)), "🔒");
+    assert_eq!(drawing.get(at(2, 0)), "");
+    assert_eq!(drawing.get(at(3, 0)), "b");
+}
+
+#[test]
+fn text_is_drawn_one_grapheme_cluster_per_cell() {
+    let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+    let flag = "\u{1f1fa}\u{1f1f8}";
+    let accent = "e\u{301}";
+    let mut drawing = Drawing::new(0, 0);
+    drawing.draw_text(at(0, 0), &format!("{}{}{}|", family, flag, accent));
+    assert_eq!(drawing.get(at(0, 0)), family);
+    assert_eq!(drawing.get(at(2, 0)), flag);
+    assert_eq!(drawing.get(at(4, 0)), accent);
+    assert_eq!(drawing.get(at(5, 0)), "|");
+    assert_eq!(drawing.to_string().trim_end(), format!("{}{}{}|", family, flag, accent));
+}
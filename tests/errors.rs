@@ -0,0 +1,23 @@
+use mermaid_ascii::error::ErrorCategory;
+use mermaid_ascii::parser::mermaid_file_to_map;
+use mermaid_ascii::render::{render_properties, RenderOptions};
+use mermaid_ascii::source::decode_source;
+
+#[test]
+fn library_errors_carry_their_category_through_context() {
+    let parse = mermaid_file_to_map("graph XY\n", "cli").expect_err("bad header");
+    assert_eq!(ErrorCategory::of(&parse), Some(ErrorCategory::Parse));
+    let wrapped = parse.context("rendering README.md");
+    assert_eq!(ErrorCategory::of(&wrapped), Some(ErrorCategory::Parse));
+    assert_eq!(wrapped.root_cause().to_string(), "first line should define the graph");
+
+    let properties = mermaid_file_to_map("graph LR\n", "cli").expect("parses");
+    let layout = render_properties(&properties, &RenderOptions::default()).expect_err("no nodes");
+    assert_eq!(ErrorCategory::of(&layout), Some(ErrorCategory::Layout));
+
+    let encoding = decode_source(b"graph LR\n\xff").expect_err("invalid UTF-8");
+    assert_eq!(ErrorCategory::of(&encoding), Some(ErrorCategory::Parse));
+
+    let io = anyhow::Error::from(std::io::Error::other("disk on fire"));
+    assert_eq!(ErrorCategory::of(&io).map(ErrorCategory::exit_code), Some(4));
+}
@@ -52,3 +52,679 @@ fn ascii_mode_respects_flag() {
         text
     );
 }
+
+#[test]
+fn number_edges_prefixes_labels_and_lists_unlabeled_edges() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--file")
+        .arg("examples/basic.mermaid")
+        .arg("--number-edges");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let text = String::from_utf8(output).expect("valid utf-8");
+    assert!(
+        text.contains("1. A → B") && text.contains("4. C → D"),
+        "numbered edges should be listed in source order:\n{}",
+        text
+    );
+}
+
+#[test]
+fn number_edges_legend_skips_invisible_links() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--number-edges").write_stdin("graph LR\nA ~~~ B\nB --> C\nC --> D\n");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let text = String::from_utf8(output).expect("valid utf-8");
+    let legend: Vec<&str> = text.lines().rev().take(2).collect();
+    assert_eq!(legend, ["2. C → D", "1. B → C"], "{}", text);
+}
+
+#[test]
+fn split_by_subgraph_renders_each_group_with_stubs() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--file")
+        .arg("examples/subgraph.mermaid")
+        .arg("--split-by-subgraph");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let text = String::from_utf8(output).expect("valid utf-8");
+    assert!(
+        text.contains("Group 1") && text.contains("Group 2"),
+        "each subgraph should get its own heading:\n{}",
+        text
+    );
+    assert!(
+        text.contains("to C") && text.contains("from B"),
+        "cross-group edges should end in stub nodes:\n{}",
+        text
+    );
+}
+
+#[test]
+fn heavy_weights_render_with_thick_lines() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.write_stdin("graph LR\nA -->{weight=5} B\nB -->{weight=9} C\n");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let text = String::from_utf8(output).expect("valid utf-8");
+    assert!(
+        text.contains('━') && text.contains('═'),
+        "weighted edges should use heavy and double strokes:\n{}",
+        text
+    );
+}
+
+#[test]
+fn braille_mode_draws_edges_with_dots() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--file")
+        .arg("examples/basic.mermaid")
+        .arg("--braille");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let text = String::from_utf8(output).expect("valid utf-8");
+    assert!(
+        text.contains('⠒') && !text.contains("─►"),
+        "edges should be drawn with braille dots:\n{}",
+        text
+    );
+}
+
+#[test]
+fn dotted_edges_render_dashed() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--ascii").write_stdin("graph LR\nA -.-> B\n");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let text = String::from_utf8(output).expect("valid utf-8");
+    assert!(
+        text.contains("- - >"),
+        "dotted edges should alternate drawn and blank cells:\n{}",
+        text
+    );
+}
+
+#[test]
+fn wide_edge_clearance_still_routes_every_edge() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--file")
+        .arg("examples/complex.mermaid")
+        .arg("--edge-clearance")
+        .arg("3");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let text = String::from_utf8(output).expect("valid utf-8");
+    assert!(
+        text.contains("Operator") && text.contains('►'),
+        "blocked margins should fall back to tighter routes:\n{}",
+        text
+    );
+}
+
+#[test]
+fn shorten_labels_abbreviates_and_prints_legend() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--shorten-labels")
+        .write_stdin("graph LR\nLoad Balancer --> Application Server\n");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let text = String::from_utf8(output).expect("valid utf-8");
+    assert!(
+        text.contains("LB = Load Balancer") && text.contains("AS = Application Server"),
+        "legend should map short names back:\n{}",
+        text
+    );
+    assert!(
+        !text.lines().next().unwrap_or("").is_empty() && !text.contains("│ Load Balancer"),
+        "boxes should carry the short names:\n{}",
+        text
+    );
+}
+
+#[test]
+fn follow_mode_renders_each_streamed_diagram() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--follow")
+        .write_stdin("graph LR\nFirst --> Second\n\ngraph LR\nThird --> Fourth\n%%end\n");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let text = String::from_utf8(output).expect("valid utf-8");
+    let first = text.find("First").expect("first diagram rendered");
+    let third = text.find("Third").expect("second diagram rendered");
+    assert!(first < third, "diagrams should render in arrival order:\n{}", text);
+}
+
+#[test]
+fn trace_layout_logs_each_phase() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--file")
+        .arg("examples/basic.mermaid")
+        .arg("--trace-layout");
+    let output = cmd.assert().success().get_output().stderr.clone();
+    let text = String::from_utf8(output).expect("valid utf-8");
+    for phase in ["parse", "mapping", "routing", "drawing"] {
+        assert!(
+            text.contains(&format!("phase={} ", phase)),
+            "missing {} phase in trace:\n{}",
+            phase,
+            text
+        );
+    }
+}
+
+#[test]
+fn edge_labels_are_padded_from_their_line() {
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.args(extra).write_stdin("graph LR\nA -->|yes| B\n");
+        let output = cmd.assert().success().get_output().stdout.clone();
+        String::from_utf8(output).expect("valid utf-8")
+    };
+    let padded = run(&[]);
+    assert!(padded.contains(" yes ►"), "label should have cleared margins:\n{}", padded);
+    let tight = run(&["--no-label-background"]);
+    assert!(tight.contains("─yes►"), "flag should keep lines touching:\n{}", tight);
+}
+
+#[test]
+fn small_independent_chains_share_rows_in_lr() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.write_stdin("graph LR\nA --> B --> C --> D --> E\nX --> Y\nP --> Q\nR --> S\n");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let text = String::from_utf8(output).expect("valid utf-8");
+    assert!(
+        text.lines().any(|line| line.contains("X") && line.contains("P")),
+        "short chains should be packed side by side:\n{}",
+        text
+    );
+}
+
+#[test]
+fn min_node_width_widens_small_boxes() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--min-node-width")
+        .arg("10")
+        .write_stdin("graph LR\nclassDef wide min-width:15\nA --> B:::wide\n");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let text = String::from_utf8(output).expect("valid utf-8");
+    let top = text.lines().next().unwrap_or("");
+    let widths: Vec<usize> = top
+        .split_whitespace()
+        .map(|border| border.chars().count())
+        .collect();
+    assert_eq!(widths, vec![10, 15], "box widths:\n{}", text);
+}
+
+#[test]
+fn emit_metadata_appends_a_reproducible_comment() {
+    let run = |input: &str| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.arg("--emit-metadata").arg("--ascii").write_stdin(input.to_string());
+        let output = cmd.assert().success().get_output().stdout.clone();
+        String::from_utf8(output).expect("valid utf-8")
+    };
+    let text = run("graph LR\nA --> B\n");
+    let footer = text.lines().last().unwrap_or("");
+    assert!(
+        footer.starts_with("<!-- mermaid-ascii ") && footer.contains("--ascii") && footer.contains("fnv1a64:"),
+        "footer:\n{}",
+        text
+    );
+    assert_eq!(footer, run("graph LR\nA --> B\n").lines().last().unwrap_or(""));
+    assert_ne!(footer, run("graph LR\nA --> C\n").lines().last().unwrap_or(""));
+}
+
+#[test]
+fn emit_metadata_records_a_non_default_strictness() {
+    let run = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.args(args).arg("--emit-metadata").write_stdin("graph LR\nA <--> B\n");
+        let output = cmd.assert().success().get_output().clone();
+        (String::from_utf8(output.stdout).expect("valid utf-8"), output.stderr)
+    };
+    let (compat, _) = run(&[]);
+    assert!(!compat.contains("--strictness"), "{}", compat);
+    let (lenient, _) = run(&["--strictness", "lenient"]);
+    let footer = lenient.lines().last().unwrap_or("");
+    let options = footer.split(" | options: ").nth(1).and_then(|rest| rest.split(" | ").next()).unwrap_or("");
+    assert!(options.contains("--strictness lenient"), "{}", footer);
+    let (replayed, stderr) = run(&options.split(' ').collect::<Vec<_>>());
+    assert_eq!(replayed, lenient);
+    assert!(stderr.is_empty(), "{}", String::from_utf8_lossy(&stderr));
+}
+
+#[test]
+fn emit_metadata_records_the_icon_table() {
+    let table = std::env::temp_dir().join(format!("mermaid-ascii-icons-{}.txt", std::process::id()));
+    std::fs::write(&table, "fa-rocket 🚀 ^\n").expect("writes icon table");
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--icons").arg(&table).arg("--emit-metadata").write_stdin("graph LR\nA[fa:fa-rocket Go] --> B\n");
+    let output = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8");
+    std::fs::remove_file(&table).ok();
+    let footer = output.lines().last().unwrap_or("");
+    assert!(footer.contains(&format!("--icons {:?}", table)), "{}", output);
+    assert!(output.contains("🚀 Go"), "{}", output);
+}
+
+#[test]
+fn font_awesome_icons_prefix_labels_in_both_modes() {
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.args(extra)
+            .write_stdin("graph LR\nA[fa:fa-lock Vault] --> B[fa:fa-server Web]\n");
+        let output = cmd.assert().success().get_output().stdout.clone();
+        String::from_utf8(output).expect("valid utf-8")
+    };
+    let unicode = run(&[]);
+    assert!(unicode.contains("🔒 Vault") && unicode.contains("🖥 Web"), "{}", unicode);
+    let widths: Vec<usize> = unicode
+        .lines()
+        .map(|line| line.chars().map(|c| if c == '🔒' { 2 } else { 1 }).sum())
+        .collect();
+    assert!(widths.windows(2).all(|w| w[0] == w[1]), "rows should line up:\n{}", unicode);
+    let ascii = run(&["--ascii"]);
+    assert!(ascii.contains("[L] Vault") && ascii.contains("[S] Web"), "{}", ascii);
+}
+
+#[test]
+fn config_file_sets_options_and_flags_override_it() {
+    let config = std::env::temp_dir().join(format!("mermaid-ascii-config-{}.toml", std::process::id()));
+    std::fs::write(&config, "use_ascii = true\nborder_padding = 3\n").expect("write config");
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.arg("--config").arg(&config).args(extra).write_stdin("graph LR\nA --> B\n");
+        cmd.assert()
+    };
+    let configured = String::from_utf8(run(&[]).success().get_output().stdout.clone()).expect("valid utf-8");
+    assert!(configured.starts_with('+') && configured.lines().count() == 9, "{}", configured);
+    let overridden = String::from_utf8(run(&["-p", "1"]).success().get_output().stdout.clone()).expect("valid utf-8");
+    assert_eq!(overridden.lines().count(), 5, "{}", overridden);
+    let rejected = run(&["--braille"]).failure().get_output().stderr.clone();
+    assert!(String::from_utf8_lossy(&rejected).contains("braille and use_ascii"));
+    std::fs::remove_file(&config).ok();
+}
+
+#[test]
+fn right_to_left_labels_are_drawn_in_display_order() {
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.args(extra).write_stdin("graph LR\nA[שלום עולם] -->|כן| B[Hi]\n");
+        let output = cmd.assert().success().get_output().stdout.clone();
+        String::from_utf8(output).expect("valid utf-8")
+    };
+    let reordered = run(&[]);
+    assert!(reordered.contains("םלוע םולש") && reordered.contains("ןכ"), "{}", reordered);
+    let widths: Vec<usize> = reordered.lines().map(|line| line.chars().count()).collect();
+    assert!(widths.windows(2).all(|w| w[0] == w[1]), "rows should line up:\n{}", reordered);
+    assert!(run(&["--no-bidi"]).contains("שלום עולם"));
+}
+
+#[test]
+fn vertical_edge_labels_sit_beside_the_line_in_td() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.write_stdin("graph TD\nA -->|yes| B\n");
+    let output = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8");
+    assert!(output.contains("│ yes"), "{}", output);
+    let box_width = output.lines().next().unwrap_or("").trim_end().chars().count();
+    assert_eq!(box_width, 5, "label should not widen the boxes:\n{}", output);
+}
+
+#[test]
+fn edges_under_nodes_can_be_turned_off_without_changing_clean_layouts() {
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.arg("--file").arg("examples/complex.mermaid").args(extra);
+        String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8")
+    };
+    assert_eq!(run(&[]), run(&["--edges-under-nodes=false"]));
+    assert_eq!(run(&[]), run(&["--edges-under-nodes", "true"]));
+}
+
+#[test]
+fn show_ranks_numbers_levels_along_the_main_axis() {
+    let run = |input: &str| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.arg("--show-ranks").write_stdin(input.to_string());
+        String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8")
+    };
+    let lr = run("graph LR\nA --> B --> C\n");
+    let ruler = lr.lines().next().unwrap_or("");
+    assert_eq!(ruler.trim_matches('·').split('·').filter(|part| !part.is_empty()).collect::<Vec<_>>(), ["0", "1", "2"]);
+    let first_box = lr.lines().nth(2).unwrap_or("");
+    assert_eq!(ruler.chars().position(|c| c == '0'), first_box.chars().position(|c| c == '─').map(|x| x + 1));
+
+    let td = run("graph TD\nA --> B\n");
+    let ranks: Vec<char> = td.lines().filter_map(|line| line.chars().next()).filter(|c| c.is_ascii_digit()).collect();
+    assert_eq!(ranks, ['0', '1']);
+}
+
+#[test]
+fn fancy_ascii_style_rounds_corners_and_keeps_plus_for_junctions() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.args(["--ascii", "--ascii-style", "fancy", "--file", "examples/labels.mermaid"]);
+    let output = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8");
+    assert!(output.is_ascii(), "{}", output);
+    assert!(output.starts_with(".---.") && output.contains("'-+-'"), "{}", output);
+    assert!(output.contains("<-------'"), "{}", output);
+}
+
+#[test]
+fn center_hubs_moves_the_busiest_node_to_the_middle_of_its_level() {
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.args(extra)
+            .write_stdin("graph LR\nA --> B\nA --> C\nA --> H\nH --> X\nH --> Y\nH --> Z\n");
+        String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8")
+    };
+    let row_of = |output: &str, name: &str| {
+        output
+            .lines()
+            .position(|line| line.contains(&format!(" {} │", name)) || line.contains(&format!(" {} ├", name)))
+            .unwrap_or_else(|| panic!("{} missing:\n{}", name, output))
+    };
+    let plain = run(&[]);
+    assert!(row_of(&plain, "H") > row_of(&plain, "C"), "{}", plain);
+    let centered = run(&["--layout", "center-hubs"]);
+    let (b, c, h) = (row_of(&centered, "B"), row_of(&centered, "C"), row_of(&centered, "H"));
+    assert!(b.min(c) < h && h < b.max(c), "{}", centered);
+}
+
+#[test]
+fn auto_direction_picks_the_orientation_that_fits_the_terminal() {
+    let run = |columns: &str, lines: &str| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.args(["--direction", "auto"])
+            .env("COLUMNS", columns)
+            .env("LINES", lines)
+            .write_stdin("graph LR\nA --> B --> C --> D --> E --> F\n");
+        String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8")
+    };
+    let narrow = run("30", "100");
+    assert!(narrow.lines().all(|line| line.chars().count() <= 30), "{}", narrow);
+    let wide = run("200", "20");
+    assert!(wide.lines().count() <= 20, "{}", wide);
+}
+
+#[test]
+fn frame_surrounds_the_diagram_with_its_title() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.args(["--frame", "--ascii"])
+        .write_stdin("---\ntitle: Order flow\n---\ngraph LR\nA --> B\n");
+    let output = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8");
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(lines[0].starts_with("+- Order flow -") && lines[0].ends_with('+'), "{}", output);
+    assert!(lines[1..lines.len() - 1].iter().all(|line| line.starts_with("| ") && line.ends_with(" |")), "{}", output);
+    assert!(lines[lines.len() - 1].chars().all(|c| c == '+' || c == '-'), "{}", output);
+    assert!(lines.iter().all(|line| line.len() == lines[0].len()), "{}", output);
+
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.args(["--frame", "--title", "Override"])
+        .write_stdin("---\ntitle: Order flow\n---\ngraph LR\nA --> B\n");
+    let output = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8");
+    assert!(output.starts_with("┌─ Override ─"), "{}", output);
+}
+
+#[test]
+fn lint_reports_relabelled_nodes() {
+    let input = "graph LR\nA[First] --> B\nA[Renamed] --> C\n";
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--lint").write_stdin(input);
+    let assert = cmd.assert().success();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).expect("valid utf-8");
+    assert!(stderr.contains("node A relabelled"), "{}", stderr);
+
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.write_stdin(input);
+    let assert = cmd.assert().success();
+    assert!(assert.get_output().stderr.is_empty());
+}
+
+#[test]
+fn edges_without_arrowheads_join_the_border_at_both_ends() {
+    let run = |input: &str| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.write_stdin(input.to_string());
+        String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8")
+    };
+    let output = run("graph LR\nA --- B\n");
+    assert!(output.contains("A ├─────┤ B"), "{}", output);
+    let output = run("graph TD\nA --- B\nA --- C\nB --- C\n");
+    assert_eq!(output.matches('┴').count(), 2, "{}", output);
+    assert!(output.contains("B ├─────┤ C"), "{}", output);
+    let output = run("graph LR\nA --> B\n");
+    assert!(output.contains("A ├────►│ B"), "{}", output);
+}
+
+#[test]
+fn class_legend_lists_styles_and_members() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.args(["--class-legend", "--ascii"]).write_stdin(
+        "graph LR\nclassDef hot color:red,stroke-width:2px\nclassDef cold fill:#00f\nA:::hot --> B:::hot\nB --> C\n",
+    );
+    let output = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8");
+    let legend: Vec<&str> = output.lines().rev().take(2).collect();
+    assert_eq!(legend, ["hot: color:red; stroke-width:2px - A, B", "cold: fill:#00f - (unused)"], "{}", output);
+}
+
+#[test]
+fn undirected_layout_grows_from_the_best_connected_node() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.args(["--layout", "undirected"])
+        .write_stdin("graph TD\nr1 --- sw\nr2 --- sw\nsw --- h1\nsw --- h2\nh2 --- h3\n");
+    let output = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8");
+    let row_of = |name: &str| {
+        output
+            .lines()
+            .position(|line| line.contains(&format!(" {} ", name)))
+            .unwrap_or_else(|| panic!("{} missing:\n{}", name, output))
+    };
+    assert!(output.lines().next().unwrap_or("").starts_with('┌'), "{}", output);
+    assert_eq!(row_of("sw"), 2, "{}", output);
+    for name in ["r1", "r2", "h1", "h2"] {
+        assert_eq!(row_of(name), row_of("r1"), "{} is one hop from sw:\n{}", name, output);
+    }
+    assert!(row_of("h3") > row_of("h2"), "{}", output);
+}
+
+#[test]
+fn outer_padding_adds_blank_cells_per_side() {
+    let run = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.arg("--ascii").args(args).write_stdin("graph LR\nA --> B\n");
+        String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8")
+    };
+    let plain = run(&[]);
+    let padded = run(&["--padding", "1,2", "--padding-left", "3"]);
+    let padded: Vec<&str> = padded.lines().collect();
+    assert_eq!(padded.len(), plain.lines().count() + 2);
+    assert!(padded[0].trim().is_empty() && padded[padded.len() - 1].trim().is_empty());
+    for (line, original) in padded[1..padded.len() - 1].iter().zip(plain.lines()) {
+        assert_eq!(*line, format!("   {}  ", original));
+    }
+}
+
+#[test]
+fn explain_tabulates_route_choices() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.args(["--explain", "--ascii"]).write_stdin("graph LR\nA --> B\nB --> A\n");
+    let output = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8");
+    let table: Vec<&str> = output.split("\n\n").last().unwrap_or("").lines().collect();
+    assert_eq!(table.len(), 3, "{}", output);
+    assert!(table[0].starts_with("edge") && table[0].ends_with("cells"), "{}", output);
+    assert!(table[1].starts_with("A -> B"), "{}", output);
+    assert!(table[2].starts_with("B -> A"), "{}", output);
+    assert!(table[1..].iter().all(|row| row.contains("preferred  ") || row.contains("alternative  ")), "{}", output);
+}
+
+#[test]
+fn color_marks_bold_and_italic_node_text() {
+    let input = "graph LR\nA:::loud --> B:::aside\nB --> C\nclassDef loud font-weight:bold\nclassDef aside font-style:italic\n";
+    let run = |color: &str| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.args(["--color", color]).write_stdin(input);
+        String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8")
+    };
+    let output = run("always");
+    assert!(output.contains("\x1b[0;1mA\x1b[0m"), "{:?}", output);
+    assert!(output.contains("\x1b[0;3mB\x1b[0m"), "{:?}", output);
+    assert!(!output.contains("\x1b[0;1mC") && !output.contains("\x1b[0;3mC"), "{:?}", output);
+    assert!(!run("auto").contains('\x1b'));
+}
+
+#[test]
+fn backward_edges_get_their_own_return_lanes() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.args(["--explain", "--ascii"])
+        .write_stdin("graph LR\nS --> A\nA --> B\nB --> C\nC --> D\nD --> A\nC --> A\n");
+    let output = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8");
+    let (drawing, table) = output.split_once("\n\n").expect("table follows the drawing");
+    for edge in ["D -> A", "C -> A"] {
+        let row = table.lines().find(|row| row.starts_with(edge)).expect("edge is listed");
+        assert!(row.contains("return channel"), "{}", output);
+    }
+    // One lane per loop, below the boxes: two rows with a long run of `-`.
+    let lanes = drawing.lines().skip(5).filter(|line| line.contains("--------")).count();
+    assert_eq!(lanes, 2, "{}", output);
+}
+
+#[cfg(feature = "profiling")]
+#[test]
+fn profile_writes_folded_stacks_per_phase() {
+    let path = std::env::temp_dir().join(format!("mermaid-ascii-profile-{}.folded", std::process::id()));
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--profile").arg(&path).arg("--file").arg("examples/complex.mermaid");
+    cmd.assert().success();
+    let stacks = std::fs::read_to_string(&path).expect("profile written");
+    std::fs::remove_file(&path).ok();
+    for phase in ["trace::parse", "trace::routing; mermaid_ascii::render::graph::determine_path", "trace::drawing"] {
+        assert!(stacks.contains(phase), "{} missing from:\n{}", phase, stacks);
+    }
+}
+
+#[test]
+fn exit_codes_follow_the_error_category() {
+    let code = |args: &[&str], input: &str| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.args(args).write_stdin(input.to_string());
+        cmd.assert().get_output().status.code()
+    };
+    assert_eq!(code(&["--quiet"], "graph LR\nA --> B\n"), Some(0));
+    assert_eq!(code(&[], "not a graph\n"), Some(2));
+    assert_eq!(code(&[], "graph LR\n"), Some(3));
+    assert_eq!(code(&["--file", "examples/missing.mermaid"], ""), Some(4));
+    assert_eq!(code(&["--no-such-flag"], ""), Some(1));
+
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.args(["--quiet", "--file", "examples/basic.mermaid"]);
+    assert!(cmd.assert().success().get_output().stdout.is_empty());
+}
+
+#[test]
+fn labels_sharing_a_line_are_stacked_with_leaders() {
+    let render = |input: &str| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.write_stdin(input);
+        String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8")
+    };
+    let output = render("graph LR\nA -->|one| B\nA -->|two| B\nA -->|three| B\n");
+    let column = |text: &str| {
+        let line = output.lines().find(|line| line.contains(text)).expect("label is drawn");
+        line[..line.find(text).expect("label")].chars().count()
+    };
+    assert!(output.contains("┬ one") && output.contains("├ two") && output.contains("└ three"), "{}", output);
+    assert_eq!(column("one"), column("three"), "{}", output);
+
+    let output = render("graph LR\nA -->|calls retries| B\n");
+    assert!(output.contains(" calls retries "), "{}", output);
+}
+
+#[test]
+fn multi_line_edge_labels_grow_their_rows() {
+    let render = |input: &str| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.write_stdin(input);
+        String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8")
+    };
+    let output = render("graph LR\nA -->|a<br>b<br/>c| B\n");
+    let lines: Vec<&str> = output.lines().collect();
+    let row = |text: &str| lines.iter().position(|line| line.contains(text)).expect("label line is drawn");
+    assert_eq!((row("a ►"), row(" b "), row(" c ")), (3, 4, 5), "{}", output);
+    assert_eq!(lines.len(), 7, "{}", output);
+    assert!(lines[6].starts_with('└'), "{}", output);
+
+    let output = render("graph TD\nA -->|a<br>b<br>c<br>d<br>e| B\n");
+    let labels: Vec<usize> = ["│ a", "│ c", "│ e"]
+        .iter()
+        .map(|text| output.lines().position(|line| line.contains(text)).expect("label is beside the line"))
+        .collect();
+    assert_eq!(labels[1] - labels[0], 2, "{}", output);
+    assert_eq!(labels[2] - labels[1], 2, "{}", output);
+}
+
+#[test]
+fn identical_input_options_and_seed_give_identical_output() {
+    let mut inputs: Vec<String> = ["basic", "labels", "subgraph", "complex"]
+        .iter()
+        .map(|name| std::fs::read_to_string(format!("examples/{}.mermaid", name)).expect("example exists"))
+        .collect();
+    for topology in ["random", "mesh"] {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.args(["gen-example", topology, "--nodes", "25", "--seed", "11"]);
+        inputs.push(String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8"));
+    }
+    let run = |input: &str| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.args(["--seed", "42", "--explain", "--emit-metadata", "footer"]).write_stdin(input);
+        cmd.assert().success().get_output().stdout.clone()
+    };
+    for input in &inputs {
+        let first = run(input);
+        for _ in 0..3 {
+            assert_eq!(run(input), first, "{}", input);
+        }
+    }
+}
+
+#[test]
+fn shadowed_classes_get_a_drop_shadow_right_and_below() {
+    let input = "graph LR\nA:::key --> B\nclassDef key shadow:true\n";
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.write_stdin(input);
+    let output = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8");
+    let lines: Vec<Vec<char>> = output.lines().map(|line| line.chars().collect()).collect();
+    assert_eq!(lines[0][5], ' ', "{}", output);
+    assert!((1..=4).all(|y| lines[y][5] == '▒' || lines[y][5] == '─'), "{}", output);
+    assert_eq!(lines[5][1..=5].iter().collect::<String>(), "▒▒▒▒▒", "{}", output);
+    assert_eq!(output.matches('▒').count(), 8, "{}", output);
+}
+
+#[test]
+fn summary_reports_counts_sizes_and_phase_times_on_stderr() {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--summary").write_stdin("graph LR\nA --> B\nB --> C\n");
+    let output = cmd.assert().success().get_output().clone();
+    let stdout = String::from_utf8(output.stdout).expect("valid utf-8");
+    let stderr = String::from_utf8(output.stderr).expect("valid utf-8");
+    let width = stdout.lines().map(|line| line.chars().count()).max().expect("drawn");
+    let height = stdout.lines().count();
+    let expected = format!("summary: 3 nodes, 2 edges, grid 11×3, output {}×{}, parse ", width, height);
+    assert!(stderr.starts_with(&expected), "{}", stderr);
+    for phase in ["mapping", "routing", "drawing"] {
+        assert!(stderr.contains(&format!(", {} ", phase)), "{}", stderr);
+    }
+    assert_eq!(stderr.lines().count(), 1);
+    assert!(!stdout.contains("summary"));
+}
+
+#[test]
+fn wrap_codeblock_fences_the_output_and_can_add_the_source() {
+    let render = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+        cmd.args(args).write_stdin("graph LR\nA --> B\n");
+        String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8")
+    };
+    let plain = render(&[]);
+    assert_eq!(render(&["--wrap-codeblock"]), format!("```text\n{}```\n", plain));
+
+    let output = render(&["--wrap-codeblock", "with-source", "--emit-metadata", "header"]);
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(lines[0].starts_with("<!-- mermaid-ascii") && lines[0].contains("--wrap-codeblock with-source"), "{}", output);
+    assert_eq!(lines[1], "```text", "{}", output);
+    assert!(
+        output.ends_with("```\n\n<details>\n<summary>Source</summary>\n\n```mermaid\ngraph LR\nA --> B\n```\n\n</details>\n"),
+        "{}",
+        output
+    );
+}
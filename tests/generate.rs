@@ -0,0 +1,15 @@
+use mermaid_ascii::generate::{generate, Topology};
+use mermaid_ascii::parser::mermaid_file_to_map;
+
+#[test]
+fn every_topology_parses_with_the_requested_node_count() {
+    for topology in [Topology::Chain, Topology::Tree, Topology::Mesh, Topology::Random] {
+        for nodes in [1, 2, 10, 50] {
+            let source = generate(topology, nodes, 7);
+            let properties = mermaid_file_to_map(&source, "cli").expect("generated graph parses");
+            assert_eq!(properties.data.len(), nodes, "{:?} with {} nodes:\n{}", topology, nodes, source);
+        }
+    }
+    assert_eq!(generate(Topology::Random, 30, 7), generate(Topology::Random, 30, 7));
+    assert_ne!(generate(Topology::Random, 30, 7), generate(Topology::Random, 30, 8));
+}
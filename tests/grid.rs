@@ -0,0 +1,49 @@
+use std::fs;
+use std::path::Path;
+
+use assert_cmd::Command;
+use mermaid_ascii::parser::mermaid_file_to_map;
+use mermaid_ascii::render::{Graph, RenderOptions};
+
+fn grid_of(input: &str) -> String {
+    let properties = mermaid_file_to_map(input, "cli").expect("parses");
+    let mut graph = Graph::new(&properties, RenderOptions::default());
+    graph.layout().expect("lays out");
+    graph.grid_snapshot()
+}
+
+/// Compares the layout of `examples/<example>.mermaid` with
+/// `tests/grids/<example>.grid`. Run with `UPDATE_GRIDS=1` to rewrite the
+/// snapshots after an intended layout change.
+fn assert_grid_matches(example: &str) {
+    let input = fs::read_to_string(format!("examples/{}.mermaid", example)).expect("example exists");
+    let actual = grid_of(&input);
+    let path = Path::new("tests/grids").join(format!("{}.grid", example));
+    if std::env::var_os("UPDATE_GRIDS").is_some() {
+        fs::write(&path, &actual).expect("snapshot written");
+        return;
+    }
+    let expected = fs::read_to_string(&path).expect("snapshot exists; run with UPDATE_GRIDS=1 to create it");
+    assert!(expected == actual, "layout of {} changed; new grid:\n{}", example, actual);
+}
+
+#[test]
+fn example_layouts_match_their_grid_snapshots() {
+    for example in ["basic", "labels", "complex", "subgraph"] {
+        assert_grid_matches(example);
+    }
+}
+
+#[test]
+fn dump_grid_writes_the_snapshot_next_to_the_drawing() {
+    let path = std::env::temp_dir().join(format!("mermaid-ascii-grid-{}.txt", std::process::id()));
+    let input = "graph LR\nA --> B\n";
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.arg("--dump-grid").arg(&path).write_stdin(input);
+    let output = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).expect("valid utf-8");
+    assert!(output.contains('A') && output.contains('B'), "{}", output);
+    let dumped = fs::read_to_string(&path).expect("grid written");
+    fs::remove_file(&path).ok();
+    assert_eq!(dumped, grid_of(input));
+    assert!(dumped.starts_with("direction lr\nnode 0,0 A\nnode 4,0 B\ncell 0,0 A\n"), "{}", dumped);
+}
@@ -0,0 +1,129 @@
+use anyhow::{bail, Result};
+use mermaid_ascii::error::ErrorCategory;
+use mermaid_ascii::parser::mermaid_file_to_map;
+use mermaid_ascii::render::{
+    render_properties, DrawingCoord, Graph, GridCoord, LayoutHooks, RenderOptions, SourceItem,
+};
+
+#[derive(Default)]
+struct Recorder {
+    nodes: Vec<String>,
+    edges: Vec<(String, String)>,
+    moves: Vec<(&'static str, GridCoord)>,
+    veto: Option<&'static str>,
+}
+
+impl LayoutHooks for Recorder {
+    fn on_node_placed(&mut self, name: &str, coord: &mut GridCoord) -> Result<()> {
+        if self.veto == Some(name) {
+            bail!("{} may not be placed", name);
+        }
+        if let Some((_, to)) = self.moves.iter().find(|(node, _)| *node == name) {
+            *coord = *to;
+        }
+        self.nodes.push(name.to_string());
+        Ok(())
+    }
+
+    fn on_edge_routed(&mut self, from: &str, to: &str, path: &mut Vec<GridCoord>) -> Result<()> {
+        assert!(!path.is_empty());
+        self.edges.push((from.to_string(), to.to_string()));
+        Ok(())
+    }
+}
+
+fn graph(input: &str) -> Graph {
+    let properties = mermaid_file_to_map(input, "cli").expect("parses");
+    Graph::new(&properties, RenderOptions::default())
+}
+
+fn line_of(output: &str, label: &str) -> usize {
+    output
+        .lines()
+        .position(|line| line.contains(label))
+        .expect("label is drawn")
+}
+
+#[test]
+fn hooks_see_every_node_and_edge_without_changing_the_output() {
+    let input = "graph LR\nA --> B\nA --> C\nC --> B\n";
+    let mut recorder = Recorder::default();
+    let mut graph = graph(input);
+    graph.layout_with(&mut recorder).expect("lays out");
+
+    assert_eq!(recorder.nodes, ["A", "B", "C"]);
+    assert_eq!(recorder.edges.len(), 3);
+    assert!(recorder.edges.contains(&("C".to_string(), "B".to_string())));
+    let properties = mermaid_file_to_map(input, "cli").expect("parses");
+    let expected = render_properties(&properties, &RenderOptions::default()).expect("renders");
+    assert_eq!(graph.render(), expected);
+}
+
+#[test]
+fn hooks_can_move_nodes() {
+    let mut recorder = Recorder {
+        moves: vec![("B", GridCoord { x: 4, y: 8 })],
+        ..Recorder::default()
+    };
+    let mut graph = graph("graph LR\nA --> B\n");
+    graph.layout_with(&mut recorder).expect("lays out");
+    let output = graph.render();
+    assert!(line_of(&output, "B") > line_of(&output, "A") + 4, "output:\n{}", output);
+}
+
+#[test]
+fn hooks_can_veto_the_layout() {
+    let mut recorder = Recorder {
+        veto: Some("B"),
+        ..Recorder::default()
+    };
+    let err = graph("graph LR\nA --> B\n")
+        .layout_with(&mut recorder)
+        .expect_err("vetoed");
+    assert_eq!(ErrorCategory::of(&err), Some(ErrorCategory::Layout));
+    assert_eq!(err.to_string(), "B may not be placed");
+
+    let mut recorder = Recorder {
+        moves: vec![("B", GridCoord { x: 1, y: 1 })],
+        ..Recorder::default()
+    };
+    let err = graph("graph LR\nA --> B\n")
+        .layout_with(&mut recorder)
+        .expect_err("overlaps");
+    assert!(err.to_string().contains("overlapping"), "{}", err);
+}
+
+#[test]
+fn positions_map_declarations_to_the_cells_they_were_drawn_in() {
+    let input = "---\ntitle: T\n---\ngraph LR\nA[Start] --> B\n%% note\nB --> C & D; D\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("parses");
+    let options = RenderOptions::builder().frame(true).build().expect("valid options");
+    let mut graph = Graph::new(&properties, options);
+    graph.layout().expect("lays out");
+    let rows: Vec<Vec<char>> = graph.render().lines().map(|line| line.chars().collect()).collect();
+    let at = |coord: DrawingCoord| rows[coord.y as usize][coord.x as usize];
+
+    let positions = graph.positions();
+    let declared: Vec<(&str, &SourceItem)> =
+        positions.iter().map(|position| (&input[position.span.clone()], &position.item)).collect();
+    let edge = |from: &str, to: &str, order| SourceItem::Edge { from: from.to_string(), to: to.to_string(), order };
+    assert_eq!(
+        declared,
+        [
+            ("A[Start]", &SourceItem::Node("A".to_string())),
+            ("A[Start] --> B", &edge("A", "B", 0)),
+            ("B", &SourceItem::Node("B".to_string())),
+            ("B --> C & D", &edge("B", "C", 1)),
+            ("B --> C & D", &edge("B", "D", 2)),
+            ("C", &SourceItem::Node("C".to_string())),
+            ("D", &SourceItem::Node("D".to_string())),
+        ]
+    );
+    for position in &positions {
+        if let SourceItem::Node(_) = position.item {
+            assert_eq!((at(position.top_left), at(position.bottom_right)), ('┌', '┘'), "{:?}", position);
+        }
+    }
+    let start = positions.iter().find(|position| position.item == SourceItem::Node("A".to_string())).unwrap();
+    assert!(rows[start.top_left.y as usize + 2].iter().collect::<String>().contains("Start"));
+}
@@ -0,0 +1,47 @@
+use mermaid_ascii::error::ErrorCategory;
+use mermaid_ascii::import::{csv_to_map, json_to_map};
+use mermaid_ascii::parser::Terminator;
+
+#[test]
+fn json_graphs_are_read_in_jgf_and_vis_js_layouts() {
+    let jgf = r#"{"graph": {"label": "Jobs", "nodes": {"a": {"label": "Extract"}, "b": {}},
+        "edges": [{"source": "a", "target": "b", "label": "rows"}, {"source": "b", "target": "c", "directed": false}]}}"#;
+    let properties = json_to_map(jgf, "cli").expect("parses");
+    assert_eq!(properties.title.as_deref(), Some("Jobs"));
+    assert_eq!(properties.data.keys().collect::<Vec<_>>(), ["a", "b", "c"]);
+    assert_eq!(properties.labels["a"], "Extract");
+    let edges: Vec<_> = properties.data.values().flatten().collect();
+    assert_eq!((edges[0].label.as_str(), edges[0].terminator), ("rows", Terminator::Arrow));
+    assert_eq!((edges[1].order, edges[1].terminator), (1, Terminator::None));
+
+    let vis = r#"{"nodes": [{"id": 1, "label": "One"}, {"id": 2}], "edges": [{"from": 1, "to": 2, "arrows": "to"}]}"#;
+    let properties = json_to_map(vis, "cli").expect("parses");
+    assert_eq!(properties.data["1"][0].child.name, "2");
+    assert_eq!(properties.data["1"][0].terminator, Terminator::Arrow);
+}
+
+#[test]
+fn malformed_json_graphs_are_parse_errors() {
+    for input in ["{", r#"{"x": 1}"#, r#"{"edges": [{"source": "a"}]}"#] {
+        let err = json_to_map(input, "cli").expect_err("rejected");
+        assert_eq!(ErrorCategory::of(&err), Some(ErrorCategory::Parse), "{}", input);
+    }
+}
+
+#[test]
+fn csv_edge_lists_are_read_with_or_without_a_header() {
+    let properties = csv_to_map("a,b,calls\nb,c\nlonely\n", ',', "cli").expect("parses");
+    assert_eq!(properties.data.keys().collect::<Vec<_>>(), ["a", "b", "c", "lonely"]);
+    assert_eq!(properties.data["a"][0].label, "calls");
+    assert_eq!(properties.data["b"][0].label, "");
+
+    let input = "label;Target;source\n\"says \"\"hi\"\"; twice\";b;a\n";
+    let properties = csv_to_map(input, ';', "cli").expect("parses");
+    assert_eq!(properties.data["a"][0].child.name, "b");
+    assert_eq!(properties.data["a"][0].label, "says \"hi\"; twice");
+
+    for input in ["a,\"b\n", "to,label\nb,x\n", ",b\n"] {
+        let err = csv_to_map(input, ',', "cli").expect_err("rejected");
+        assert_eq!(ErrorCategory::of(&err), Some(ErrorCategory::Parse), "{}", input);
+    }
+}
@@ -0,0 +1,36 @@
+use std::fs;
+
+use assert_cmd::Command;
+
+fn inject(doc: &std::path::Path, check: bool) -> assert_cmd::assert::Assert {
+    let mut cmd = Command::cargo_bin("mermaid-ascii").expect("binary exists");
+    cmd.args(["--ascii", "inject", "--doc"]).arg(doc);
+    if check {
+        cmd.arg("--check");
+    }
+    cmd.assert()
+}
+
+#[test]
+fn inject_adds_and_then_updates_text_renderings() {
+    let doc = std::env::temp_dir().join(format!("mermaid-ascii-inject-{}.md", std::process::id()));
+    fs::write(&doc, "# Flow\n\n```mermaid\ngraph LR\nA --> B\n```\n\nMore text.\n").expect("doc written");
+
+    inject(&doc, true).failure();
+    inject(&doc, false).success();
+    let first = fs::read_to_string(&doc).expect("doc read");
+    assert!(first.starts_with("# Flow\n\n```mermaid\ngraph LR\nA --> B\n```\n\n<!-- mermaid-ascii:begin -->\n```text\n+---+"), "{}", first);
+    assert!(first.ends_with("```\n<!-- mermaid-ascii:end -->\n\nMore text.\n"), "{}", first);
+    assert!(first.lines().all(|line| line == line.trim_end()), "{}", first);
+
+    inject(&doc, true).success();
+    inject(&doc, false).success();
+    assert_eq!(fs::read_to_string(&doc).expect("doc read"), first);
+
+    fs::write(&doc, first.replace("A --> B", "A --> Changed")).expect("doc written");
+    inject(&doc, false).success();
+    let updated = fs::read_to_string(&doc).expect("doc read");
+    fs::remove_file(&doc).ok();
+    assert_eq!(updated.matches("mermaid-ascii:begin").count(), 1, "{}", updated);
+    assert!(updated.contains("Changed |") && !updated.contains("| B |"), "{}", updated);
+}
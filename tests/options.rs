@@ -0,0 +1,214 @@
+use mermaid_ascii::error::ErrorCategory;
+use mermaid_ascii::parser::{mermaid_file_to_map, GraphDirection, GraphProperties};
+use mermaid_ascii::render::{
+    render_properties, render_to, AutoStyle, Color, ColorTheme, RenderOptions, RenderOptionsBuilder, Sides,
+};
+
+fn render_with(properties: &GraphProperties, builder: RenderOptionsBuilder) -> anyhow::Result<String> {
+    render_properties(properties, &builder.build().expect("valid options"))
+}
+
+#[test]
+fn builder_rejects_invalid_combinations() {
+    let err = RenderOptions::builder()
+        .border_padding(-1)
+        .braille(true)
+        .use_ascii(true)
+        .fit_to(80, 0)
+        .build()
+        .expect_err("invalid options");
+    let message = err.to_string();
+    assert!(message.contains("border_padding must not be negative"), "{}", message);
+    assert!(message.contains("braille and use_ascii"), "{}", message);
+    assert!(message.contains("fit_to"), "{}", message);
+}
+
+#[test]
+fn options_deserialize_with_defaults_and_override_the_graph() {
+    let options: RenderOptions = toml::from_str("direction = \"TD\"\npadding_x = 2\n").expect("parses");
+    assert_eq!(options.direction, Some(GraphDirection::Td));
+    assert_eq!(options.border_padding, 1);
+    assert!(toml::from_str::<RenderOptions>("colour = true\n").is_err());
+
+    let properties = mermaid_file_to_map("graph LR\nA --> B\n", "cli").expect("parses");
+    let output = render_properties(&properties, &options).expect("renders");
+    assert!(output.lines().count() > 5, "expected a vertical layout:\n{}", output);
+}
+
+#[test]
+fn padding_shorthand_follows_css() {
+    let sides = |values: &[usize]| Sides::from_shorthand(values).map(|s| [s.top, s.right, s.bottom, s.left]).ok();
+    assert_eq!(sides(&[1]), Some([1, 1, 1, 1]));
+    assert_eq!(sides(&[1, 2]), Some([1, 2, 1, 2]));
+    assert_eq!(sides(&[1, 2, 3]), Some([1, 2, 3, 2]));
+    assert_eq!(sides(&[1, 2, 3, 4]), Some([1, 2, 3, 4]));
+    assert_eq!(sides(&[]), None);
+    assert_eq!(sides(&[1, 2, 3, 4, 5]), None);
+}
+
+#[test]
+fn aspect_compensation_halves_the_vertical_gaps() {
+    let properties = mermaid_file_to_map("graph TD\nA --> B\n", "cli").expect("parses");
+    let render = |aspect_compensate: bool| {
+        let options = RenderOptions::builder().aspect_compensate(aspect_compensate).build().expect("valid");
+        render_properties(&properties, &options).expect("renders")
+    };
+    let (plain, compensated) = (render(false), render(true));
+    // Two five-row boxes and the gap between them: five rows, then three.
+    assert_eq!(plain.lines().count(), 15, "{}", plain);
+    assert_eq!(compensated.lines().count(), 13, "{}", compensated);
+    assert_eq!(plain.lines().next(), compensated.lines().next());
+}
+
+#[test]
+fn render_to_writes_the_same_text_as_render_properties() {
+    let input = "graph LR\nA:::x -->|go| B\nclassDef x font-weight:bold\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("parses");
+    let options = RenderOptions::builder().color(true).class_legend(true).build().expect("valid");
+    let mut written = Vec::new();
+    render_to(&properties, &options, &mut written).expect("renders");
+    let expected = render_properties(&properties, &options).expect("renders");
+    assert_eq!(String::from_utf8(written).expect("utf-8"), expected);
+    assert!(expected.contains("\x1b[0;1m"));
+}
+
+#[test]
+fn highlight_thickens_the_borders_of_matching_nodes() {
+    let properties = mermaid_file_to_map("graph LR\nA --> db[Database]\n", "cli").expect("parses");
+    let output = render_with(&properties, RenderOptions::builder().highlight("^Data")).expect("renders");
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(lines[0].starts_with("┌───┐") && lines[0].ends_with("┏━━━━━━━━━━┓"), "{}", output);
+    assert!(lines[2].ends_with("┃ Database ┃"), "{}", output);
+
+    let ascii = render_with(&properties, RenderOptions::builder().highlight("db").use_ascii(true)).expect("renders");
+    assert!(ascii.lines().next().is_some_and(|line| line.ends_with("#==========#")), "{}", ascii);
+
+    let colored = render_with(&properties, RenderOptions::builder().highlight("db").color(true)).expect("renders");
+    assert!(colored.lines().all(|line| line.contains("\x1b[0;7m")), "{}", colored);
+
+    let err = RenderOptions::builder().highlight("(").build().expect_err("invalid pattern");
+    assert!(err.to_string().contains("highlight is not a valid regular expression"), "{}", err);
+}
+
+#[test]
+fn turn_penalty_prefers_routes_with_fewer_corners() {
+    let properties = mermaid_file_to_map(include_str!("../examples/complex.mermaid"), "cli").expect("parses");
+    let corners = |turn_penalty: i32| {
+        let options = RenderOptions::builder().turn_penalty(turn_penalty).build().expect("valid options");
+        let output = render_properties(&properties, &options).expect("renders");
+        output.chars().filter(|c| matches!(c, '┌' | '┐' | '└' | '┘')).count()
+    };
+    assert!(corners(1) < corners(0), "{} corners with a penalty, {} without", corners(1), corners(0));
+    assert!(RenderOptions::builder().turn_penalty(-1).build().is_err());
+}
+
+#[test]
+fn layout_refuses_graphs_over_the_node_and_canvas_limits() {
+    let properties = mermaid_file_to_map("graph LR\nA --> B --> C\n", "cli").expect("parses");
+    assert!(render_with(&properties, RenderOptions::builder().max_nodes(3)).is_ok());
+    let err = render_with(&properties, RenderOptions::builder().max_nodes(2)).expect_err("too many nodes");
+    assert_eq!(ErrorCategory::of(&err), Some(ErrorCategory::Layout));
+    assert!(err.to_string().contains("3 nodes, more than the limit of 2"), "{}", err);
+
+    let err = render_with(&properties, RenderOptions::builder().max_canvas_cells(100)).expect_err("canvas too big");
+    assert_eq!(ErrorCategory::of(&err), Some(ErrorCategory::Layout));
+    assert!(err.to_string().contains("more than the limit of 100"), "{}", err);
+    assert!(RenderOptions::builder().max_nodes(0).build().is_err());
+}
+
+#[test]
+fn degree_auto_style_emphasizes_the_best_connected_nodes() {
+    let input = "graph LR\nA --> Hub\nB --> Hub\nHub --> C\nC --> D\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("parses");
+    let heavy_boxes = |top: usize| {
+        let builder = RenderOptions::builder().auto_style(AutoStyle::Degree).auto_style_top(top);
+        let options = builder.build().expect("valid options");
+        let output = render_properties(&properties, &options).expect("renders");
+        output.matches('┏').count()
+    };
+    assert_eq!(heavy_boxes(1), 1);
+    assert_eq!(heavy_boxes(2), 2);
+    assert_eq!(heavy_boxes(10), 5);
+    let plain = render_properties(&properties, &RenderOptions::default()).expect("renders");
+    assert!(!plain.contains('┏'), "{}", plain);
+}
+
+#[test]
+fn reachable_from_dots_the_borders_of_nodes_it_cannot_reach() {
+    let properties = mermaid_file_to_map("graph LR\nA --> B --> C\nD --> E\n", "cli").expect("parses");
+    let output = render_with(&properties, RenderOptions::builder().reachable_from("B")).expect("renders");
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(lines[2].starts_with("· A ·") && lines[2].ends_with("│ C │"), "{}", output);
+    assert!(output.contains("· D ·") && output.contains("· E ·"), "{}", output);
+
+    let colored = render_with(&properties, RenderOptions::builder().reachable_from("A").color(true)).expect("renders");
+    assert!(colored.contains("\x1b[0;2m") && !colored.contains('·'), "{}", colored);
+
+    let err = render_with(&properties, RenderOptions::builder().reachable_from("Z")).expect_err("unknown node");
+    assert_eq!(ErrorCategory::of(&err), Some(ErrorCategory::Layout));
+}
+
+#[test]
+fn labels_sit_centered_for_every_width_parity() {
+    for width in 0..=9 {
+        for len in 1..=6 {
+            let label = "x".repeat(len);
+            let input = format!("graph LR\nA[\"{}\"]\n", label);
+            let properties = mermaid_file_to_map(&input, "cli").expect("parses");
+            let options = RenderOptions::builder().min_node_width(width).build().expect("valid options");
+            let output = render_properties(&properties, &options).expect("renders");
+            let row = output.lines().find(|line| line.contains(&label)).expect("label drawn");
+            let inside = row.trim_end().trim_start_matches('│').trim_end_matches('│');
+            let left = inside.len() - inside.trim_start().len();
+            let right = inside.len() - inside.trim_end().len();
+            assert!(left >= 1 && left <= right && right <= left + 1, "width {} len {}:\n{}", width, len, output);
+        }
+    }
+}
+
+#[test]
+fn vertical_labels_stack_wide_td_labels_along_their_line() {
+    let properties = mermaid_file_to_map("graph TD\nA -->|a long label| B\nA --> C\nB -->|ok| D\n", "cli").expect("parses");
+    let render = |vertical: bool| {
+        let options = RenderOptions::builder().vertical_labels(vertical).build().expect("valid options");
+        render_properties(&properties, &options).expect("renders")
+    };
+    let (wide, stacked) = (render(false), render(true));
+    let width = |output: &str| output.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+    assert!(width(&stacked) < width(&wide), "{}\n{}", wide, stacked);
+    let column: Vec<&str> = stacked.lines().filter_map(|line| line.strip_prefix("  │ ")).map(|rest| &rest[..1]).collect();
+    assert!(column.concat().contains("a long label"), "{}", stacked);
+    assert!(stacked.contains("│ ok "), "labels that fit the gap stay level:\n{}", stacked);
+}
+
+#[test]
+fn source_map_numbers_each_box_row_with_its_declaring_line() {
+    let properties = mermaid_file_to_map("graph TD\n%% note\nA --> B\n\nA --> C\nC --> D\n", "cli").expect("input parses");
+    let options = RenderOptions::builder().source_map(true).build().expect("valid options");
+    let output = render_properties(&properties, &options).expect("renders");
+    let marked: Vec<&str> = output.lines().filter(|line| line.contains('→')).map(str::trim_end).collect();
+    assert_eq!(marked, ["  3→ ┌───┐", "3,5→ ┌───┐     ┌───┐", "  6→ ┌───┐       │"]);
+
+    let plain = render_properties(&properties, &RenderOptions::default()).expect("renders");
+    let stripped: Vec<String> = output.lines().map(|line| line.chars().skip(5).collect()).collect();
+    let stripped: Vec<&str> = stripped.iter().map(|line| line.trim_end()).collect();
+    assert_eq!(stripped, plain.lines().map(str::trim_end).collect::<Vec<_>>());
+}
+
+#[test]
+fn class_colors_are_matched_to_the_theme_unless_truecolor() {
+    assert_eq!(ColorTheme::Dark.nearest((230, 40, 40)), Color::Ansi(9));
+    assert_eq!(ColorTheme::Light.nearest((230, 40, 40)), Color::Ansi(1));
+    assert_eq!(ColorTheme::Solarized.nearest((100, 110, 200)), Color::Ansi(13));
+
+    let input = "graph LR\nA:::hot --> B\nclassDef hot color:#e33,stroke:#00f\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("input parses");
+    let light = RenderOptions::builder().color(true).color_theme(ColorTheme::Light);
+    let themed = render_with(&properties, light).expect("renders");
+    assert!(themed.contains("\x1b[0;31mA\x1b[0m"), "{:?}", themed);
+    assert!(themed.contains("\x1b[0;34m┌───┐\x1b[0m"), "{:?}", themed);
+    let exact = render_with(&properties, RenderOptions::builder().color(true).truecolor(true)).expect("renders");
+    assert!(exact.contains("\x1b[0;38;2;238;51;51mA\x1b[0m"), "{:?}", exact);
+    let plain = render_with(&properties, RenderOptions::builder().truecolor(true)).expect("renders");
+    assert!(!plain.contains('\x1b'));
+}
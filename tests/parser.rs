@@ -0,0 +1,549 @@
+use mermaid_ascii::error::ErrorCategory;
+use mermaid_ascii::parser::{
+    check_grammar, mermaid_file_to_map, mermaid_file_to_map_with_limit, skip_unsupported, unsupported_constructs,
+    GraphDirection, LineStyle, NodeShape, Port, Strictness, StyleClass, Terminator,
+};
+use mermaid_ascii::render::{render_properties, RenderOptions};
+
+fn edges(input: &str) -> Vec<(String, String)> {
+    let properties = mermaid_file_to_map(input, "cli").expect("input parses");
+    properties
+        .data
+        .values()
+        .flatten()
+        .map(|edge| (edge.parent.name.clone(), edge.child.name.clone()))
+        .collect()
+}
+
+#[test]
+fn semicolons_separate_statements() {
+    let parsed = edges("graph LR;\nA --> B; B --> C;\n");
+    assert_eq!(
+        parsed,
+        vec![
+            ("A".to_string(), "B".to_string()),
+            ("B".to_string(), "C".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn arrows_parse_without_surrounding_whitespace() {
+    let input = "graph LR\nA-->B\nB---C\nC-.->D-->E\nA-->|x|E\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("input parses");
+    let pairs = edges(input);
+    assert_eq!(pairs.len(), 5, "edges: {:?}", pairs);
+    assert!(pairs.contains(&("D".to_string(), "E".to_string())));
+
+    let b_to_c = &properties.data["B"][0];
+    assert_eq!(b_to_c.terminator, Terminator::None);
+    let c_to_d = &properties.data["C"][0];
+    assert_eq!(c_to_d.line_style, LineStyle::Dotted);
+    let labeled = properties.data["A"]
+        .iter()
+        .find(|edge| edge.child.name == "E")
+        .expect("labeled edge");
+    assert_eq!(labeled.label, "x");
+}
+
+#[test]
+fn edge_weights_come_from_extension_syntax_and_link_style() {
+    let input = "graph LR\nA -->|big|{weight=5} B\nB --> C\nlinkStyle 1 stroke-width:8px\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("input parses");
+    let a_to_b = &properties.data["A"][0];
+    assert_eq!(a_to_b.label, "big");
+    assert_eq!(a_to_b.weight, Some(5));
+    assert_eq!(properties.data["B"][0].weight, Some(8));
+
+    let properties = mermaid_file_to_map("graph LR\nA -->{weight=99999999999} B\n", "cli").expect("input parses");
+    assert_eq!(properties.data["A"][0].weight, Some(u32::MAX));
+    render_properties(&properties, &RenderOptions::default()).expect("renders");
+}
+
+#[test]
+fn long_arrow_chains_parse_without_overflowing_the_stack() {
+    let chain: Vec<String> = (0..5000).map(|i| format!("N{}", i)).collect();
+    let input = format!("graph LR\n{}\n", chain.join(" --> "));
+    let properties = mermaid_file_to_map(&input, "cli").expect("input parses");
+    assert_eq!(properties.edge_count(), 4999);
+}
+
+#[test]
+fn long_ampersand_groups_parse_and_respect_the_term_limit() {
+    let group: Vec<String> = (0..5000).map(|i| format!("N{}", i)).collect();
+    let input = format!("graph LR\n{} --> Sink\n", group.join(" & "));
+    let properties = mermaid_file_to_map(&input, "cli").expect("input parses");
+    assert_eq!(properties.edge_count(), 5000);
+
+    let err = mermaid_file_to_map_with_limit(&input, "cli", 100).expect_err("limit applies");
+    assert!(err.to_string().contains("more than 100"), "error: {}", err);
+}
+
+#[test]
+fn comments_are_stripped_outside_quotes_only() {
+    let input = "graph LR\nA[\"50%% off\"] --> B %% sale\nB --> C%%note\n";
+    assert_eq!(
+        edges(input),
+        vec![
+            ("A".to_string(), "B".to_string()),
+            ("B".to_string(), "C".to_string()),
+        ]
+    );
+    let properties = mermaid_file_to_map(input, "cli").expect("input parses");
+    assert_eq!(properties.labels.get("A").map(String::as_str), Some("50%% off"));
+}
+
+#[test]
+fn bracketed_labels_are_kept_apart_from_node_names() {
+    let input = "graph LR\nA[fa:fa-server Web]:::svc --> B\nB --> A\nC[(Store)]\n";
+    assert_eq!(
+        edges(input),
+        vec![
+            ("A".to_string(), "B".to_string()),
+            ("B".to_string(), "A".to_string()),
+        ]
+    );
+    let properties = mermaid_file_to_map(input, "cli").expect("input parses");
+    assert_eq!(properties.labels.get("A").map(String::as_str), Some("fa:fa-server Web"));
+    assert!(properties.data.contains_key("C[(Store)]"), "other shapes stay literal");
+}
+
+#[test]
+fn merge_unions_graphs_and_keeps_subgraphs_apart() {
+    let mut base = mermaid_file_to_map(
+        "graph LR\nsubgraph Backend\nA[Api] --> B\nend\nclassDef hot color:red\n",
+        "cli",
+    )
+    .expect("parses");
+    let other = mermaid_file_to_map(
+        "graph TD\nsubgraph Backend\nA[Other] --> B\nB --> C\nend\nclassDef cold color:blue\n",
+        "cli",
+    )
+    .expect("parses");
+    base.merge(other);
+
+    assert_eq!(base.data.keys().collect::<Vec<_>>(), ["A", "B", "C"]);
+    assert_eq!(base.edge_count(), 2);
+    assert_eq!(base.labels["A"], "Api");
+    assert!(base.style_classes.contains_key("hot") && base.style_classes.contains_key("cold"));
+    let names: Vec<&str> = base.subgraphs.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["Backend", "Backend (2)"]);
+    let orders: Vec<usize> = base.data.values().flatten().map(|edge| edge.order).collect();
+    assert_eq!(orders, [0, 1]);
+}
+
+#[test]
+fn front_matter_title_is_read_and_skipped() {
+    let properties = mermaid_file_to_map("---\ntitle: 'Order flow'\n---\ngraph TD\nA --> B\n", "cli").expect("parses");
+    assert_eq!(properties.title.as_deref(), Some("Order flow"));
+    assert_eq!(properties.edge_count(), 1);
+    let untitled = mermaid_file_to_map("graph LR\nA --> B\n", "cli").expect("parses");
+    assert_eq!(untitled.title, None);
+}
+
+#[test]
+fn front_matter_vars_are_substituted_into_labels() {
+    let input = "---\ntitle: Deploy to {{env}}\nvars:\n  env: prod\n  region: \"eu-west-1\"\n---\ngraph LR\n\
+                 subgraph {{ region }}\nA[api-{{env}}] -->|{{missing}}| B\nend\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("parses");
+    assert_eq!(properties.title.as_deref(), Some("Deploy to prod"));
+    assert_eq!(properties.labels["A"], "api-prod");
+    assert_eq!(properties.subgraphs[0].name, "eu-west-1");
+    assert_eq!(properties.data["A"][0].label, "{{missing}}");
+    assert_eq!(properties.warnings, ["`{{missing}}` is not one of the front matter's vars"]);
+
+    let flow = mermaid_file_to_map("---\nvars: {env: staging, tier: 2}\n---\ngraph LR\nA[{{env}}/{{tier}}]\n", "cli");
+    assert_eq!(flow.expect("parses").labels["A"], "staging/2");
+}
+
+#[test]
+fn redeclaring_a_node_replaces_its_label_and_warns() {
+    let properties =
+        mermaid_file_to_map("graph LR\nA[First] --> B\nA[First] --> C\nA[Renamed]\n", "cli").expect("parses");
+    assert_eq!(properties.data.len(), 3);
+    assert_eq!(properties.labels["A"], "Renamed");
+    assert_eq!(properties.warnings, [r#"node A relabelled from "First" to "Renamed""#]);
+}
+
+#[test]
+fn labels_between_dashes_match_pipe_labels() {
+    let properties =
+        mermaid_file_to_map("graph LR\nA -- yes --> B -- no way --- C\nD[x -- y] --> E\n", "cli").expect("parses");
+    assert_eq!(properties.data.keys().collect::<Vec<_>>(), ["A", "B", "C", "D", "E"]);
+    assert_eq!(properties.data["A"][0].label, "yes");
+    assert_eq!(properties.data["B"][0].label, "no way");
+    assert_eq!(properties.data["B"][0].terminator, Terminator::None);
+    assert_eq!(properties.data["D"][0].label, "");
+    assert_eq!(properties.labels["D"], "x -- y");
+}
+
+#[test]
+fn labels_between_dashes_need_no_spaces() {
+    let properties = mermaid_file_to_map("graph LR\nA--yes-->B\nB-.maybe.->C\nC -. no .-> D\nD---E\n", "cli")
+        .expect("parses");
+    assert_eq!(properties.data.keys().collect::<Vec<_>>(), ["A", "B", "C", "D", "E"]);
+    assert_eq!(properties.data["A"][0].label, "yes");
+    assert_eq!(properties.data["B"][0].label, "maybe");
+    assert_eq!(properties.data["B"][0].line_style, LineStyle::Dotted);
+    assert_eq!(properties.data["C"][0].label, "no");
+    assert_eq!(properties.data["D"][0].label, "");
+}
+
+#[test]
+fn flags_and_trapezoids_are_parsed_and_drawn_with_their_sides() {
+    let input = "graph LR\nA>Flag] --> B[/Up\\]\nB --> C[\\Down/]\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("parses");
+    assert_eq!(properties.data.keys().collect::<Vec<_>>(), ["A", "B", "C"]);
+    assert_eq!(properties.labels["B"], "Up");
+    assert_eq!(properties.shapes["A"], NodeShape::Flag);
+    assert_eq!(properties.shapes["B"], NodeShape::Trapezoid);
+    assert_eq!(properties.shapes["C"], NodeShape::ReverseTrapezoid);
+
+    let output = render_properties(&properties, &RenderOptions::default()).expect("renders");
+    let middle = output.lines().find(|line| line.contains("Flag")).expect("label is drawn");
+    assert!(middle.starts_with('>'), "output:\n{}", output);
+    assert!(middle.contains("►/") && middle.contains("\\────►\\"), "output:\n{}", output);
+}
+
+#[test]
+fn records_draw_a_title_over_aligned_rows() {
+    let input = "graph LR\nWeb --> db[[Postgres|host: db.internal|port: 5432|read-only]]\nCache[[Redis]]\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("parses");
+    assert_eq!(properties.shapes["db"], NodeShape::Record);
+    assert_eq!(properties.labels["db"], "Postgres|host: db.internal|port: 5432|read-only");
+    assert!(properties.data.contains_key("Cache[[Redis]]"));
+
+    let output = render_properties(&properties, &RenderOptions::default()).expect("renders");
+    let lines: Vec<&str> = output.lines().collect();
+    let row = |text: &str| lines.iter().position(|line| line.contains(text)).expect("text is drawn");
+    assert_eq!(row("├───"), row("Postgres") + 1, "output:\n{}", output);
+    assert!(lines[row("host")].contains("│ host: db.internal │"), "output:\n{}", output);
+    assert!(lines[row("port")].contains("│ port: 5432        │"), "output:\n{}", output);
+    assert!(lines[row("read-only")].contains("│ read-only         │"), "output:\n{}", output);
+    assert!(lines[row("host")].contains("├────►│ host"), "output:\n{}", output);
+}
+
+#[test]
+fn tall_records_are_routed_around_as_one_obstacle() {
+    let input = "graph TD\nA[[Log|1|2|3|4|5|6|7|8|9|10|11|12]] --> B\nA --> C\nX --> C\nY --> C\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("parses");
+    let output = render_properties(&properties, &RenderOptions::default()).expect("renders");
+    let lines: Vec<Vec<char>> = output.lines().map(|line| line.chars().collect()).collect();
+    let top = lines.iter().position(|line| line.first() == Some(&'┌')).expect("record is drawn");
+    let bottom = lines.iter().position(|line| line.first() == Some(&'└')).expect("record is closed");
+    let right = lines[top].iter().position(|c| *c == '┐').expect("record has a corner");
+    for line in &lines[top + 1..bottom] {
+        assert!(matches!(line[0], '│' | '├'), "output:\n{}", output);
+        assert!(matches!(line[right], '│' | '├' | '┤'), "output:\n{}", output);
+        assert!(line[1..right].iter().all(|c| !matches!(c, '┼' | '►' | '▼')), "output:\n{}", output);
+    }
+    let child = lines.iter().rposition(|line| line.contains(&'B')).expect("child is drawn");
+    assert!(child > bottom + 1, "output:\n{}", output);
+}
+
+#[test]
+fn spacers_and_invisible_links_take_room_without_being_drawn() {
+    let input = "graph LR\n%%spacer gap\nA ~~~ spacer ~~~ B\nA ~~~~ gap\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("parses");
+    assert_eq!(properties.data.keys().collect::<Vec<_>>(), ["A", "spacer~1", "B", "gap"]);
+    assert_eq!((properties.shapes["spacer~1"], properties.shapes["gap"]), (NodeShape::Spacer, NodeShape::Spacer));
+    let edges: Vec<_> = properties.data.values().flatten().collect();
+    assert!(edges.iter().all(|edge| edge.line_style == LineStyle::Invisible && edge.terminator == Terminator::None));
+    assert_eq!(edges.iter().map(|edge| edge.min_length).collect::<Vec<_>>(), [1, 2, 1]);
+
+    let output = render_properties(&properties, &RenderOptions::default()).expect("renders");
+    let lines: Vec<&str> = output.lines().collect();
+    // `gap` holds the top of B's level, so B goes below it.
+    assert_eq!(lines[2].trim_end(), "│ A │", "output:\n{}", output);
+    assert_eq!(lines[12], "               │ B │", "output:\n{}", output);
+    assert!(!output.contains(['►', '┬', '├']), "output:\n{}", output);
+}
+
+#[test]
+fn left_pointing_arrows_swap_their_endpoints() {
+    let input = "graph LR\nB <-- A\nC <-.-|maybe| B\nD<==C\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("input parses");
+    let parsed: Vec<_> = properties
+        .data
+        .values()
+        .flatten()
+        .map(|edge| (edge.parent.name.as_str(), edge.child.name.as_str(), edge.label.as_str(), edge.line_style))
+        .collect();
+    assert_eq!(
+        parsed,
+        [
+            ("A", "B", "", LineStyle::Solid),
+            ("B", "C", "maybe", LineStyle::Dotted),
+            ("C", "D", "", LineStyle::Solid),
+        ]
+    );
+    let edges: Vec<_> = properties.data.values().flatten().collect();
+    assert!(edges.iter().all(|edge| edge.terminator == Terminator::Arrow));
+    assert_eq!(edges[2].weight, Some(4));
+}
+
+#[test]
+fn circle_and_cross_links_mark_their_ends() {
+    let input = "graph LR\nA --o B\nC o--o D\nE x--x|no| F\nfox --> box\nG -.-x H\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("input parses");
+    let parsed: Vec<_> = properties
+        .data
+        .values()
+        .flatten()
+        .map(|edge| (edge.parent.name.as_str(), edge.child.name.as_str(), edge.start_terminator, edge.terminator))
+        .collect();
+    assert_eq!(
+        parsed,
+        [
+            ("A", "B", Terminator::None, Terminator::Circle),
+            ("C", "D", Terminator::Circle, Terminator::Circle),
+            ("E", "F", Terminator::Cross, Terminator::Cross),
+            ("fox", "box", Terminator::None, Terminator::Arrow),
+            ("G", "H", Terminator::None, Terminator::Cross),
+        ]
+    );
+    assert_eq!(unsupported_constructs(input), []);
+
+    let drawn = |use_ascii: bool| {
+        let properties = mermaid_file_to_map("graph LR\nC o--x D\n", "cli").expect("input parses");
+        let options = RenderOptions { use_ascii, ..RenderOptions::default() };
+        render_properties(&properties, &options).expect("renders")
+    };
+    assert!(drawn(false).contains("C │●───✕│ D"), "{}", drawn(false));
+    assert!(drawn(true).contains("C |o---x| D"), "{}", drawn(true));
+}
+
+#[test]
+fn aliases_differing_in_case_or_whitespace_can_be_merged() {
+    let input = r#"{"nodes": [{"id": "Api"}, {"id": "API "}, {"id": "db", "label": " Store "}],
+        "edges": [{"from": "Api", "to": "db"}, {"from": "API ", "to": "db"}, {"from": "api", "to": "Api"}]}"#;
+    let parse = || mermaid_ascii::import::json_to_map(input, "cli").expect("parses");
+
+    let mut properties = parse();
+    assert_eq!(properties.merge_aliases(false, true), Vec::<String>::new());
+    assert_eq!(properties.data.keys().collect::<Vec<_>>(), ["Api", "API", "db", "api"]);
+    assert_eq!(properties.labels["db"], "Store");
+
+    let mut properties = parse();
+    let merges = properties.merge_aliases(true, true);
+    assert_eq!(merges, [r#"merged "API " into "Api""#, r#"merged "api" into "Api""#]);
+    assert_eq!(properties.data.keys().collect::<Vec<_>>(), ["Api", "db"]);
+    let edges: Vec<_> = properties
+        .data
+        .values()
+        .flatten()
+        .map(|edge| (edge.parent.name.as_str(), edge.child.name.as_str()))
+        .collect();
+    assert_eq!(edges, [("Api", "db"), ("Api", "Api")]);
+}
+
+#[test]
+fn merged_aliases_keep_links_that_differ_only_at_the_start() {
+    let mut properties = mermaid_file_to_map("graph LR\na o--o B\nA --o B\n", "cli").expect("input parses");
+    properties.merge_aliases(true, false);
+    let starts: Vec<Terminator> = properties.data.values().flatten().map(|edge| edge.start_terminator).collect();
+    assert_eq!(starts, [Terminator::Circle, Terminator::None]);
+}
+
+#[test]
+fn extra_dashes_ask_for_longer_edges() {
+    let input = "graph TD\nA --> B\nA ----> C\nA -..-> D\nA ---- E\nF <=== A\nA -- yes ---> G\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("input parses");
+    let lengths: Vec<_> = properties
+        .data
+        .values()
+        .flatten()
+        .map(|edge| (edge.child.name.as_str(), edge.label.as_str(), edge.min_length))
+        .collect();
+    assert_eq!(
+        lengths,
+        [("B", "", 1), ("C", "", 3), ("D", "", 2), ("E", "", 2), ("F", "", 2), ("G", "yes", 2)]
+    );
+
+    let rows = |input: &str| {
+        let properties = mermaid_file_to_map(input, "cli").expect("input parses");
+        render_properties(&properties, &RenderOptions::default()).expect("renders").lines().count()
+    };
+    let (short, long, longer) = (rows("graph TD\nA --> B\n"), rows("graph TD\nA ---> B\n"), rows("graph TD\nA ----> B\n"));
+    assert!(short < long && long < longer, "{} {} {}", short, long, longer);
+}
+
+#[test]
+fn quoted_labels_may_hold_syntax_characters() {
+    let input = "graph LR\nA[\"list[0] | head --> tail & more\"]:::hot --> B>\"x]y\"] & C\nB -->|\"p|q\"| D[\"a:::b\"]\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("input parses");
+    assert_eq!(properties.data.keys().collect::<Vec<_>>(), ["A", "B", "C", "D"]);
+    assert_eq!(properties.labels["A"], "list[0] | head --> tail & more");
+    assert_eq!(properties.data["A"][0].parent.style_class.as_deref(), Some("hot"));
+    assert_eq!(properties.labels["B"], "x]y");
+    assert_eq!(properties.shapes["B"], NodeShape::Flag);
+    assert_eq!(properties.labels["D"], "a:::b");
+    assert_eq!(properties.data["B"][0].label, "p|q");
+}
+
+#[test]
+fn compass_ports_fix_the_sides_an_edge_uses() {
+    let input = "graph LR\nA:s --> B[Box]:n & C:::hot:w\nD <-- C:e\nE:::x --> F\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("input parses");
+    assert_eq!(properties.data.keys().collect::<Vec<_>>(), ["A", "B", "C", "D", "E", "F"]);
+    assert_eq!(properties.labels["B"], "Box");
+    let ports: Vec<_> = properties
+        .data
+        .values()
+        .flatten()
+        .map(|edge| (edge.parent.name.as_str(), edge.parent_port, edge.child.name.as_str(), edge.child_port))
+        .collect();
+    assert_eq!(
+        ports,
+        [
+            ("A", Some(Port::South), "B", Some(Port::North)),
+            ("A", Some(Port::South), "C", Some(Port::West)),
+            ("C", Some(Port::East), "D", None),
+            ("E", None, "F", None),
+        ]
+    );
+    assert_eq!(properties.data["A"][1].child.style_class.as_deref(), Some("hot"));
+
+    let properties = mermaid_file_to_map("graph LR\nA:s --> B:s\n", "cli").expect("input parses");
+    let output = render_properties(&properties, &RenderOptions::default()).expect("renders");
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(lines[4].contains('┬') && lines[5].contains('▲'), "{}", output);
+}
+
+#[test]
+fn ports_on_the_outer_sides_leave_no_blank_margin() {
+    let render = |input: &str| {
+        let properties = mermaid_file_to_map(input, "cli").expect("input parses");
+        render_properties(&properties, &RenderOptions::default()).expect("renders")
+    };
+    let top = render("graph LR\nA:n --> B:n\n");
+    assert!(top.lines().next().is_some_and(|line| line.contains('┌')), "{}", top);
+    let left = render("graph TD\nA:w --> B:w\n");
+    assert!(left.lines().any(|line| line.starts_with('┌')), "{}", left);
+}
+
+#[test]
+fn unsupported_mermaid_is_reported_by_line() {
+    let input = "---\ntitle: T\n---\nflowchart BT\nA ==> B\nstyle A fill:red\nA -->|\"a --o b\"| C((round)) & D\nA -.- E\n";
+    let diagnostics: Vec<String> = unsupported_constructs(input).iter().map(ToString::to_string).collect();
+    assert_eq!(
+        diagnostics,
+        [
+            "line 4: direction BT is drawn as TD",
+            "line 5: link `==>` is not supported",
+            "line 6: `style` statements are not supported and are drawn as a node",
+            "line 7: node shape in `C((round))` is not supported and is drawn as part of the name",
+            "line 8: link `-.-` is not supported",
+        ]
+    );
+    assert_eq!(check_grammar(input, Strictness::Lenient).expect("lenient"), []);
+    assert_eq!(check_grammar(input, Strictness::Compat).expect("compat").len(), 5);
+    let err = check_grammar(input, Strictness::Strict).expect_err("strict");
+    assert_eq!(ErrorCategory::of(&err), Some(ErrorCategory::Parse));
+    assert!(err.to_string().contains("line 8: link `-.-`"), "{}", err);
+
+    let clean = "graph TB\nA[\"x ==> y\"] --> B:::k\nclassDef k fill:red\nlinkStyle 0 stroke-width:2px\n";
+    assert_eq!(unsupported_constructs(clean), []);
+    assert!(check_grammar(clean, Strictness::Strict).is_ok());
+    let properties = mermaid_file_to_map(clean, "cli").expect("TB parses");
+    assert_eq!(properties.graph_direction, GraphDirection::Td);
+}
+
+#[test]
+fn skipping_unsupported_statements_keeps_the_rest_in_place() {
+    let input = "flowchart BT\nA ==> B; A --> F\nstyle A fill:red\nA --> C((round)) & D\nA -.- E\nstyle F color:blue\n";
+    let (kept, skipped, remaining) = skip_unsupported(input);
+    assert_eq!(kept.len(), input.len());
+    assert_eq!(kept.lines().nth(1), Some("       ; A --> F"));
+    assert_eq!(skipped.statements, 5);
+    assert_eq!(skipped.to_string(), "skipped 5 statements (kinds: `==>` link, `style`, node shape, `-.-` link)");
+    assert_eq!(remaining.iter().map(ToString::to_string).collect::<Vec<_>>(), ["line 1: direction BT is drawn as TD"]);
+    assert_eq!(check_grammar(input, Strictness::Skip).expect("skip"), remaining);
+    assert_eq!(edges(&kept), [("A".to_string(), "F".to_string())]);
+}
+
+#[test]
+fn keywords_in_node_position_stay_nodes() {
+    let input = "graph LR\nsubgraph \"Two; end\"\nsubgraph --> \\end\n\\subgraph[Sub] --> \"end\"\nend\nend --> B\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("parses");
+    assert_eq!(properties.subgraphs.len(), 1);
+    assert_eq!(properties.subgraphs[0].name, "Two; end");
+    assert_eq!(properties.subgraphs[0].nodes, ["subgraph", "end", "\"end\""]);
+    assert_eq!(
+        edges(input),
+        [
+            ("subgraph".to_string(), "end".to_string()),
+            ("subgraph".to_string(), "\"end\"".to_string()),
+            ("end".to_string(), "B".to_string()),
+        ]
+    );
+    assert_eq!(unsupported_constructs(input), []);
+}
+
+#[test]
+fn standalone_declarations_carry_over_to_bare_references() {
+    let input = "graph LR\nclassDef hot color:red\nA>Start]:::hot\nB[/Work\\]\nC:::hot\nA --> B --> C\nB:::cold --> D\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("parses");
+    assert_eq!(properties.shapes["A"], NodeShape::Flag);
+    assert_eq!(properties.shapes["B"], NodeShape::Trapezoid);
+    assert_eq!(properties.labels["A"], "Start");
+    let class = |name: &str| properties.classes.get(name).map(|classes| classes.join(","));
+    let classes = [class("A"), class("B"), class("C"), class("D")];
+    assert_eq!(classes.each_ref().map(Option::as_deref), [Some("hot"), Some("cold"), Some("hot"), None]);
+
+    let options = RenderOptions { class_legend: true, ..RenderOptions::default() };
+    let output = render_properties(&properties, &options).expect("renders");
+    assert!(output.contains("> Start") && output.lines().last() == Some("hot: color:red — Start, C"), "{}", output);
+}
+
+#[test]
+fn later_classes_override_the_styles_of_earlier_ones() {
+    let input = "graph LR\nclassDef a color:red,font-weight:bold\nclassDef b color:blue\nA:::a:::b --> B\nclass B, C b,a\nB:::b\nclass --> D\n";
+    let properties = mermaid_file_to_map(input, "cli").expect("parses");
+    assert_eq!(properties.classes["A"], ["a", "b"]);
+    assert_eq!(properties.classes["B"], ["b", "a"]);
+    assert_eq!(properties.data.keys().collect::<Vec<_>>(), ["A", "B", "class", "D"]);
+    assert_eq!(unsupported_constructs(input), []);
+
+    let style = |name: &str| StyleClass::resolve(&properties.classes[name], &properties.style_classes).expect("styled");
+    let (a, b) = (style("A"), style("B"));
+    assert_eq!((a.styles["color"].as_str(), a.styles["font-weight"].as_str()), ("blue", "bold"));
+    assert_eq!((b.styles["color"].as_str(), b.styles["font-weight"].as_str()), ("red", "bold"));
+    assert!(StyleClass::resolve(&["missing".to_string()], &properties.style_classes).is_none());
+}
+
+#[test]
+fn collapsed_subgraphs_become_one_node_with_their_outside_edges() {
+    let input = "graph LR\nA --> B\nsubgraph Backend\nB --> C\nC --> D\nsubgraph Db\nD --> E\nend\nend\nE --> F\nA --> C\nA --> D\n";
+    let mut properties = mermaid_file_to_map(input, "cli").expect("input parses");
+    properties.collapse_subgraphs(&["Backend".to_string()], None).expect("Backend is a subgraph");
+    assert_eq!(
+        edges_of(&properties),
+        [("A", "B"), ("A", "Backend"), ("B", "Backend"), ("Backend", "F")].map(|(a, b)| (a.to_string(), b.to_string()))
+    );
+    assert_eq!(properties.labels["Backend"], "Backend (3 nodes)");
+    assert!(properties.subgraphs.is_empty());
+
+    let mut properties = mermaid_file_to_map(input, "cli").expect("input parses");
+    properties.collapse_subgraphs(&[], Some(1)).expect("collapses");
+    assert_eq!(properties.labels["Db"], "Db (1 node)");
+    assert_eq!(properties.subgraphs.len(), 1);
+    assert_eq!(properties.subgraphs[0].nodes, ["C", "D", "Db"]);
+    assert!(edges_of(&properties).contains(&("Db".to_string(), "F".to_string())));
+
+    let err = properties.collapse_subgraphs(&["Nope".to_string()], None).expect_err("no such subgraph");
+    assert_eq!(err.to_string(), "collapse names no subgraph: Nope");
+
+    let input = "graph LR\nsubgraph S\nB\nC\nend\nA o--o B\nA --o C\n";
+    let mut properties = mermaid_file_to_map(input, "cli").expect("input parses");
+    properties.collapse_subgraphs(&["S".to_string()], None).expect("S is a subgraph");
+    let starts: Vec<Terminator> = properties.data.values().flatten().map(|edge| edge.start_terminator).collect();
+    assert_eq!(starts, [Terminator::Circle, Terminator::None]);
+}
+
+fn edges_of(properties: &mermaid_ascii::parser::GraphProperties) -> Vec<(String, String)> {
+    let mut edges: Vec<(String, String)> =
+        properties.data.values().flatten().map(|edge| (edge.parent.name.clone(), edge.child.name.clone())).collect();
+    edges.sort();
+    edges
+}
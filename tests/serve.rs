@@ -0,0 +1,70 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+
+use base64::engine::general_purpose::URL_SAFE;
+use base64::Engine;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+struct Server {
+    child: Child,
+    addr: String,
+}
+
+impl Server {
+    fn start() -> Server {
+        let mut child = Command::new(assert_cmd::cargo::cargo_bin("mermaid-ascii"))
+            .args(["serve", "--addr", "127.0.0.1:0"])
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("server starts");
+        let mut line = String::new();
+        BufReader::new(child.stderr.take().expect("stderr piped"))
+            .read_line(&mut line)
+            .expect("server announces its address");
+        let addr = line
+            .trim()
+            .strip_prefix("listening on http://")
+            .expect("listening line")
+            .to_string();
+        Server { child, addr }
+    }
+
+    fn request(&self, request: &str) -> String {
+        let mut stream = TcpStream::connect(&self.addr).expect("connects");
+        stream.write_all(request.as_bytes()).expect("request sent");
+        let mut response = String::new();
+        stream.read_to_string(&mut response).expect("response read");
+        response
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn serve_renders_posted_text_and_kroki_urls() {
+    let server = Server::start();
+    let source = "graph LR\nA --> B\n";
+
+    let posted = server.request(&format!(
+        "POST /mermaid/utxt HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+        source.len(),
+        source
+    ));
+    assert!(posted.starts_with("HTTP/1.1 200 OK") && posted.contains("►"), "{}", posted);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(source.as_bytes()).expect("compresses");
+    let payload = URL_SAFE.encode(encoder.finish().expect("compresses"));
+    let kroki = server.request(&format!("GET /mermaid/txt/{} HTTP/1.1\r\n\r\n", payload));
+    assert!(kroki.starts_with("HTTP/1.1 200 OK") && kroki.contains("+---+"), "{}", kroki);
+
+    let bad = server.request("GET /mermaid/txt/not-a-payload HTTP/1.1\r\n\r\n");
+    assert!(bad.starts_with("HTTP/1.1 400"), "{}", bad);
+}
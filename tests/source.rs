@@ -0,0 +1,42 @@
+use mermaid_ascii::source::{decode_source, expand_includes, SourceEncoding};
+
+#[test]
+fn byte_order_marks_are_stripped_and_utf16_transcoded() {
+    let (text, encoding) = decode_source(b"\xef\xbb\xbfgraph LR\n").expect("decodes");
+    assert_eq!((text.as_str(), encoding), ("graph LR\n", SourceEncoding::Utf8Bom));
+
+    let utf16: Vec<u8> = "\u{feff}graph LR\nA --> B\n"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let (text, encoding) = decode_source(&utf16).expect("decodes");
+    assert_eq!((text.as_str(), encoding), ("graph LR\nA --> B\n", SourceEncoding::Utf16Le));
+
+    let bare: Vec<u8> = "graph TD\n".encode_utf16().flat_map(u16::to_be_bytes).collect();
+    assert_eq!(decode_source(&bare).expect("decodes").1, SourceEncoding::Utf16Be);
+}
+
+#[test]
+fn invalid_utf8_reports_the_offending_byte() {
+    let err = decode_source(b"graph LR\nA\xe9 --> B\n").expect_err("not UTF-8");
+    assert!(err.to_string().contains("0xe9 at offset 10"), "error: {}", err);
+}
+
+#[test]
+fn includes_are_inlined_relative_to_their_file_and_cycles_fail() {
+    let dir = std::env::temp_dir().join(format!("mermaid-ascii-include-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("parts")).expect("create dirs");
+    let main = dir.join("main.mermaid");
+    std::fs::write(&main, "graph LR\nA --> B\n%%include parts/db.mermaid\n").expect("write main");
+    std::fs::write(dir.join("parts/db.mermaid"), "flowchart LR\nB --> DB\n").expect("write part");
+
+    let source = std::fs::read_to_string(&main).expect("read main");
+    let expanded = expand_includes(&source, Some(&main)).expect("expands");
+    assert_eq!(expanded, "graph LR\nA --> B\nB --> DB\n");
+
+    std::fs::write(dir.join("parts/db.mermaid"), "%%include ../main.mermaid\n").expect("write cycle");
+    let err = expand_includes(&source, Some(&main)).expect_err("cycle is rejected");
+    assert!(err.to_string().starts_with("include cycle:"), "{}", err);
+
+    std::fs::remove_dir_all(&dir).ok();
+}